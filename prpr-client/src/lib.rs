@@ -139,6 +139,7 @@ async fn the_main() -> Result<()> {
     let mut painter = TextPainter::new(font);
 
     let mut main = Main::new(Box::new(MainScene::new().await?), TimeManager::default(), None).await?;
+    main.show_fps = get_data().config.show_fps;
 
     let tm = TimeManager::default();
     let mut fps_time = -1;
@@ -57,7 +57,7 @@ impl SettingsPage {
                 .map(|it| format!("{}/{it}", dir::root().unwrap())),
         )
         .await?;
-        let emitter = ParticleEmitter::new(&res_pack, get_data().config.note_scale, res_pack.info.hide_particles)?;
+        let emitter = ParticleEmitter::new(&res_pack, &get_data().config)?;
         Ok(Self {
             focus: false,
 
@@ -134,7 +134,7 @@ impl Page for SettingsPage {
                     }
                     Ok((res_pack, dst)) => {
                         self.click_texture = res_pack.note_style.click.clone();
-                        self.emitter = ParticleEmitter::new(&res_pack, get_data().config.note_scale, res_pack.info.hide_particles)?;
+                        self.emitter = ParticleEmitter::new(&res_pack, &get_data().config)?;
                         self.res_pack = res_pack;
                         get_data_mut().config.res_pack_path = dst;
                         save_data()?;
@@ -2,18 +2,32 @@ use anyhow::{Context, Result};
 use macroquad::prelude::*;
 use prpr::{
     build_conf,
+    config::Config,
     core::init_assets,
     fs,
-    scene::{show_error, GameMode, LoadingScene, NextScene, Scene},
+    scene::{show_error, CalibrationScene, GameMode, LoadingScene, NextScene, Scene, SelectScene},
     time::TimeManager,
     ui::{FontArc, TextPainter, Ui},
     Main,
 };
 use std::ops::DerefMut;
 
-struct BaseScene(Option<NextScene>, bool);
+struct BaseScene(Option<NextScene>, bool, Option<(Config, Option<String>)>);
 impl Scene for BaseScene {
     fn on_result(&mut self, _tm: &mut TimeManager, result: Box<dyn std::any::Any>) -> Result<()> {
+        let result = match result.downcast::<Option<f32>>() {
+            Ok(offset) => {
+                if let (Some(offset), Some((config, path))) = (*offset, &mut self.2) {
+                    info!("Calibrated offset: {}ms", (offset * 1000.).round());
+                    config.offset = offset;
+                    if let Some(path) = path {
+                        std::fs::write(path, serde_yaml::to_string(config)?).context("Cannot write to config file")?;
+                    }
+                }
+                return Ok(());
+            }
+            Err(result) => result,
+        };
         show_error(result.downcast::<anyhow::Error>().unwrap().context("加载谱面失败"));
         self.1 = true;
         Ok(())
@@ -40,34 +54,50 @@ async fn main() -> Result<()> {
     init_assets();
 
     #[cfg(target_arch = "wasm32")]
-    let (mut fs, config) = {
+    let (fs, config, config_path, select) = {
         fn js_err(err: wasm_bindgen::JsValue) -> anyhow::Error {
             anyhow::Error::msg(format!("{err:?}"))
         }
         let params = web_sys::UrlSearchParams::new_with_str(&web_sys::window().unwrap().location().search().map_err(js_err)?).map_err(js_err)?;
         let name = params.get("chart").unwrap_or_else(|| "nc".to_string());
         (
-            fs::fs_from_assets(format!("charts/{name}/"))?,
-            Some(prpr::config::Config {
+            Some(fs::fs_from_assets(format!("charts/{name}/"))?),
+            Some(Config {
                 autoplay: false,
                 ..Default::default()
             }),
+            None,
+            false,
         )
     };
     #[cfg(any(target_os = "android", target_os = "ios"))]
-    let (mut fs, config) = (fs::fs_from_assets("charts/moment/")?, None);
+    let (fs, config, config_path, select) = (Some(fs::fs_from_assets("charts/moment/")?), None, None, false);
     #[cfg(all(not(target_arch = "wasm32"), not(target_os = "android"), not(target_os = "ios")))]
-    let (mut fs, config) = {
-        let mut args = std::env::args();
+    let (fs, config, config_path, select) = {
+        let practice = std::env::args().any(|it| it == "--practice");
+        let mut args = std::env::args().filter(|it| it != "--practice");
         let program = args.next().unwrap();
         let Some(path) = args.next() else {
-            anyhow::bail!("Usage: {program} <chart>");
+            anyhow::bail!("Usage: {program} <chart | --calibrate | --select> [config] [--practice]");
         };
-        let mut config = None;
-        if let Some(config_path) = args.next() {
+        let mut config: Option<Config> = None;
+        let config_path = args.next();
+        if let Some(config_path) = &config_path {
             config = Some(serde_yaml::from_str(&std::fs::read_to_string(config_path).context("Cannot read from config file")?)?);
         }
-        (fs::fs_from_file(std::path::Path::new(&path))?, config)
+        if practice {
+            config.get_or_insert_with(Config::default).practice = true;
+        }
+        (
+            if path == "--calibrate" || path == "--select" {
+                None
+            } else {
+                Some(fs::fs_from_file(std::path::Path::new(&path))?)
+            },
+            config,
+            config_path,
+            path == "--select",
+        )
     };
 
     let _guard = {
@@ -90,22 +120,24 @@ async fn main() -> Result<()> {
     let font = FontArc::try_from_vec(load_file("font.ttf").await?)?;
     let mut painter = TextPainter::new(font);
 
-    let info = fs::load_info(fs.deref_mut()).await?;
-    let config = config.unwrap_or_default();
+    let config: Config = config.unwrap_or_default();
 
     let mut fps_time = -1;
 
     let tm = TimeManager::default();
     let ctm = TimeManager::from_config(&config); // strange variable name...
-    let mut main = Main::new(
-        Box::new(BaseScene(
-            Some(NextScene::Overlay(Box::new(LoadingScene::new(GameMode::Normal, info, config, fs, (None, None), None, None).await?))),
-            false,
-        )),
-        ctm,
-        None,
-    )
-    .await?;
+    let first_scene: Box<dyn Scene> = if select {
+        // every chart folder or archive under charts/
+        Box::new(SelectScene::new(std::path::Path::new("charts"), config.clone()).await?)
+    } else if let Some(mut fs) = fs {
+        let info = fs::load_info(fs.deref_mut()).await?;
+        Box::new(LoadingScene::new(GameMode::Normal, info, config.clone(), fs, None, None, None).await?)
+    } else {
+        Box::new(CalibrationScene::new(&config).await?)
+    };
+    let show_fps = config.show_fps;
+    let mut main = Main::new(Box::new(BaseScene(Some(NextScene::Overlay(first_scene)), false, Some((config, config_path)))), ctm, None).await?;
+    main.show_fps = show_fps;
     'app: loop {
         let frame_start = tm.real_time();
         main.update()?;
@@ -8,6 +8,7 @@ use prpr::{
     config::Config,
     core::{init_assets, MSRenderTarget, NoteKind},
     fs::{self, PatchedFileSystem},
+    parse::ParseOptions,
     scene::{GameMode, GameScene, LoadingScene, BILLBOARD},
     time::TimeManager,
     ui::{ChartInfoEdit, FontArc, TextPainter, Ui},
@@ -82,11 +83,23 @@ async fn the_main() -> Result<()> {
     let font = FontArc::try_from_vec(load_file("font.ttf").await?)?;
     let mut painter = TextPainter::new(font);
 
-    let (path, config) = {
+    let (path, config, render_out) = {
         let mut args = std::env::args().skip(1);
         let Some(path) = args.next() else {
             bail!("请将谱面文件或文件夹拖动到该软件上！");
         };
+        let mut render_out = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--render-out" => {
+                    let Some(dir) = args.next() else {
+                        bail!("`--render-out` 需要指定输出目录");
+                    };
+                    render_out = Some(std::path::PathBuf::from(dir));
+                }
+                _ => bail!("未知参数：{arg}"),
+            }
+        }
         let config =
             match (|| -> Result<Config> { Ok(serde_yaml::from_str(&std::fs::read_to_string("conf.yml").context("无法加载配置文件")?)?) })() {
                 Err(err) => {
@@ -95,13 +108,21 @@ async fn the_main() -> Result<()> {
                 }
                 Ok(config) => config,
             };
-        (path, config)
+        (path, config, render_out)
     };
 
     let mut fs = fs::fs_from_file(std::path::Path::new(&path)).context("加载谱面失败")?;
     let info = fs::load_info(fs.deref_mut()).await.context("加载谱面信息失败")?;
 
-    let (chart, ..) = GameScene::load_chart(fs.deref_mut(), &info).await.context("加载谱面内容失败")?;
+    let (chart, ..) = GameScene::load_chart(
+        fs.deref_mut(),
+        &info,
+        ParseOptions {
+            strict: !config.lenient_parse,
+        },
+    )
+    .await
+    .context("加载谱面内容失败")?;
     macro_rules! ld {
         ($path:literal) => {
             AudioClip::new(load_file($path).await?).with_context(|| format!("加载音效 `{}` 失败", $path))?
@@ -186,6 +207,7 @@ async fn the_main() -> Result<()> {
 
     let edit = INFO_EDIT.lock().unwrap().take().unwrap();
     let volume_music = config.volume_music;
+    let music_volume = config.music_volume();
     let volume_sfx = config.volume_sfx;
     let config = Config {
         autoplay: true,
@@ -203,63 +225,66 @@ async fn the_main() -> Result<()> {
 
     let render_start_time = Instant::now();
 
-    info!("[1] 混音中…");
-    let sample_rate = 44100;
-    assert_eq!(sample_rate, ending.sample_rate());
-    assert_eq!(sample_rate, sfx_click.sample_rate());
-    assert_eq!(sample_rate, sfx_drag.sample_rate());
-    assert_eq!(sample_rate, sfx_flick.sample_rate());
-    let mut output = vec![0.0_f32; (video_length * sample_rate as f64).ceil() as usize * 2];
-    {
-        let pos = O - chart.offset.min(0.) as f64;
-        let count = (music.length() as f64 * sample_rate as f64) as usize;
-        let mut it = output[((pos * sample_rate as f64).round() as usize * 2)..].iter_mut();
-        let ratio = 1. / sample_rate as f64;
-        for frame in 0..count {
-            let position = frame as f64 * ratio;
-            let frame = music.sample(position as f32).unwrap_or_default();
-            *it.next().unwrap() += frame.0 * volume_music;
-            *it.next().unwrap() += frame.1 * volume_music;
+    // frames written as images don't need any audio
+    if render_out.is_none() {
+        info!("[1] 混音中…");
+        let sample_rate = 44100;
+        assert_eq!(sample_rate, ending.sample_rate());
+        assert_eq!(sample_rate, sfx_click.sample_rate());
+        assert_eq!(sample_rate, sfx_drag.sample_rate());
+        assert_eq!(sample_rate, sfx_flick.sample_rate());
+        let mut output = vec![0.0_f32; (video_length * sample_rate as f64).ceil() as usize * 2];
+        {
+            let pos = O - chart.offset.min(0.) as f64;
+            let count = (music.length() as f64 * sample_rate as f64) as usize;
+            let mut it = output[((pos * sample_rate as f64).round() as usize * 2)..].iter_mut();
+            let ratio = 1. / sample_rate as f64;
+            for frame in 0..count {
+                let position = frame as f64 * ratio;
+                let frame = music.sample(position as f32).unwrap_or_default();
+                *it.next().unwrap() += frame.0 * music_volume;
+                *it.next().unwrap() += frame.1 * music_volume;
+            }
         }
-    }
-    let mut place = |pos: f64, clip: &AudioClip, volume: f32| {
-        let position = (pos * sample_rate as f64).round() as usize * 2;
-        let slice = &mut output[position..];
-        let len = (slice.len() / 2).min(clip.frame_count());
-        let mut it = slice.iter_mut();
-        // TODO optimize?
-        for frame in clip.frames()[..len].iter() {
-            let dst = it.next().unwrap();
-            *dst += frame.0 * volume;
-            let dst = it.next().unwrap();
-            *dst += frame.1 * volume;
+        let mut place = |pos: f64, clip: &AudioClip, volume: f32| {
+            let position = (pos * sample_rate as f64).round() as usize * 2;
+            let slice = &mut output[position..];
+            let len = (slice.len() / 2).min(clip.frame_count());
+            let mut it = slice.iter_mut();
+            // TODO optimize?
+            for frame in clip.frames()[..len].iter() {
+                let dst = it.next().unwrap();
+                *dst += frame.0 * volume;
+                let dst = it.next().unwrap();
+                *dst += frame.1 * volume;
+            }
+        };
+        for note in chart.lines.iter().flat_map(|it| it.notes.iter()).filter(|it| !it.fake) {
+            place(
+                O + note.time as f64 + offset as f64,
+                match note.kind {
+                    NoteKind::Click | NoteKind::Hold { .. } => &sfx_click,
+                    NoteKind::Drag => &sfx_drag,
+                    NoteKind::Flick => &sfx_flick,
+                },
+                volume_sfx,
+            )
         }
-    };
-    for note in chart.lines.iter().flat_map(|it| it.notes.iter()).filter(|it| !it.fake) {
-        place(
-            O + note.time as f64 + offset as f64,
-            match note.kind {
-                NoteKind::Click | NoteKind::Hold { .. } => &sfx_click,
-                NoteKind::Drag => &sfx_drag,
-                NoteKind::Flick => &sfx_flick,
-            },
-            volume_sfx,
-        )
-    }
-    place(O + length + A, &ending, volume_music);
-    let mut proc = Command::new(&ffmpeg)
-        .args("-y -f f32le -ar 44100 -ac 2 -i - -c:a mp3 t_audio.mp3".split_whitespace())
-        .stdin(Stdio::piped())
-        .stderr(Stdio::null())
-        .spawn()
-        .context("无法执行 ffmpeg")?;
-    let input = proc.stdin.as_mut().unwrap();
-    let mut writer = BufWriter::new(input);
-    for sample in output.into_iter() {
-        writer.write_all(&sample.to_le_bytes())?;
+        place(O + length + A, &ending, volume_music);
+        let mut proc = Command::new(&ffmpeg)
+            .args("-y -f f32le -ar 44100 -ac 2 -i - -c:a mp3 t_audio.mp3".split_whitespace())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("无法执行 ffmpeg")?;
+        let input = proc.stdin.as_mut().unwrap();
+        let mut writer = BufWriter::new(input);
+        for sample in output.into_iter() {
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+        drop(writer);
+        proc.wait()?;
     }
-    drop(writer);
-    proc.wait()?;
 
     info!("[2] 渲染视频…");
     let mst = Rc::new(MSRenderTarget::new((vw, vh), config.sample_count));
@@ -297,35 +322,42 @@ async fn the_main() -> Result<()> {
     let fps = v_config.fps;
     let frame_delta = 1. / fps as f32;
 
-    let codecs = String::from_utf8(Command::new(&ffmpeg).arg("-codecs").output().context("无法执行 ffmpeg")?.stdout)?;
-    let use_cuda = v_config.hardware_accel && codecs.contains("h264_nvenc");
-    let has_qsv = v_config.hardware_accel && codecs.contains("h264_qsv");
+    let mut proc = if let Some(dir) = &render_out {
+        std::fs::create_dir_all(dir).context("无法创建输出目录")?;
+        None
+    } else {
+        let codecs = String::from_utf8(Command::new(&ffmpeg).arg("-codecs").output().context("无法执行 ffmpeg")?.stdout)?;
+        let use_cuda = v_config.hardware_accel && codecs.contains("h264_nvenc");
+        let has_qsv = v_config.hardware_accel && codecs.contains("h264_qsv");
 
-    let mut args = "-y -f rawvideo -c:v rawvideo".to_owned();
-    if use_cuda {
-        args += " -hwaccel_output_format cuda";
-    }
-    write!(
-        &mut args,
-        " -s {vw}x{vh} -r {fps} -pix_fmt rgb24 -i - -i t_audio.mp3 -c:a copy -c:v {} -map 0:v:0 -map 1:a:0 -qp 0 -vf vflip t_video.mp4",
+        let mut args = "-y -f rawvideo -c:v rawvideo".to_owned();
         if use_cuda {
-            "h264_nvenc"
-        } else if has_qsv {
-            "h264_qsv"
-        } else if v_config.hardware_accel {
-            bail!("不支持硬件加速！");
-        } else {
-            "libx264 -preset ultrafast"
-        },
-    )?;
-
-    let mut proc = Command::new(&ffmpeg)
-        .args(args.split_whitespace())
-        .stdin(Stdio::piped())
-        .stderr(Stdio::null())
-        .spawn()
-        .context("无法执行 ffmpeg")?;
-    let mut input = proc.stdin.take().unwrap();
+            args += " -hwaccel_output_format cuda";
+        }
+        write!(
+            &mut args,
+            " -s {vw}x{vh} -r {fps} -pix_fmt rgb24 -i - -i t_audio.mp3 -c:a copy -c:v {} -map 0:v:0 -map 1:a:0 -qp 0 -vf vflip t_video.mp4",
+            if use_cuda {
+                "h264_nvenc"
+            } else if has_qsv {
+                "h264_qsv"
+            } else if v_config.hardware_accel {
+                bail!("不支持硬件加速！");
+            } else {
+                "libx264 -preset ultrafast"
+            },
+        )?;
+
+        Some(
+            Command::new(&ffmpeg)
+                .args(args.split_whitespace())
+                .stdin(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .context("无法执行 ffmpeg")?,
+        )
+    };
+    let mut input = proc.as_mut().map(|it| it.stdin.take().unwrap());
 
     let mut bytes = vec![0; vw as usize * vh as usize * 3];
 
@@ -346,23 +378,33 @@ async fn the_main() -> Result<()> {
             mst.blit();
         }
         mst.output().texture.raw_miniquad_texture_handle().read_pixels(&mut bytes);
-        input.write_all(&bytes)?;
+        if let Some(input) = &mut input {
+            input.write_all(&bytes)?;
+        } else if let Some(dir) = &render_out {
+            // pixels are read bottom-up
+            let image = image::RgbImage::from_raw(vw, vh, bytes.clone()).unwrap();
+            image::imageops::flip_vertical(&image)
+                .save(dir.join(format!("{frame:06}.png")))
+                .with_context(|| format!("无法保存第 {frame} 帧"))?;
+        }
         if frame % 100 == 0 {
             info!("{frame} / {frames}, {:.2}fps", frame as f64 / start_time.elapsed().as_secs_f64());
         }
     }
     drop(input);
-    proc.wait()?;
-
-    info!("[3] 合并 & 转码 & 压制");
-    let _ = Command::new(&ffmpeg)
-        .args("-y -i t_video.mp4 -c:a copy -pix_fmt yuv420p -b:v".split_whitespace())
-        .arg(v_config.bitrate)
-        .arg("out.mp4")
-        .stdin(Stdio::null())
-        .stderr(Stdio::inherit())
-        .status()
-        .context("无法执行 ffmpeg")?;
+    if let Some(mut proc) = proc {
+        proc.wait()?;
+
+        info!("[3] 合并 & 转码 & 压制");
+        let _ = Command::new(&ffmpeg)
+            .args("-y -i t_video.mp4 -c:a copy -pix_fmt yuv420p -b:v".split_whitespace())
+            .arg(v_config.bitrate)
+            .arg("out.mp4")
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .status()
+            .context("无法执行 ffmpeg")?;
+    }
 
     info!("渲染完成！耗时：{:.2}s", render_start_time.elapsed().as_secs_f64());
     Ok(())
@@ -9,3 +9,17 @@ speed = Speed
 ex-time-out-of-range = Time is out of range
 ex-invalid-format = Invalid format
 ex-time-set = Time changed
+
+calibration = Offset calibration
+calibration-hint = Tap along with the clicks
+calibration-result = Suggested offset: { $offset }ms
+calibration-confirm = Press Enter to save, R to retry, or Esc to cancel
+calibration-retry = Retry
+
+select-title = Select a chart
+select-empty = No charts found in { $dir }
+select-load-failed = Failed to load the chart
+
+practice-set-checkpoint = Set checkpoint
+practice-clear-checkpoint = Clear checkpoint
+practice-checkpoint = Retrying from { $time }
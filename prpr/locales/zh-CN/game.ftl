@@ -9,3 +9,17 @@ speed = 速度
 ex-time-out-of-range = 时间不在范围内
 ex-invalid-format = 格式有误
 ex-time-set = 设置成功
+
+calibration = 延迟校准
+calibration-hint = 请跟随节拍点击屏幕
+calibration-result = 建议延迟：{ $offset }ms
+calibration-confirm = 按 Enter 保存，按 R 重试，按 Esc 取消
+calibration-retry = 重试
+
+select-title = 选择谱面
+select-empty = { $dir } 中没有找到谱面
+select-load-failed = 加载谱面失败
+
+practice-set-checkpoint = 设置检查点
+practice-clear-checkpoint = 清除检查点
+practice-checkpoint = 重试将从 { $time } 开始
@@ -21,11 +21,25 @@ pub trait BinaryData: Sized {
     fn write_binary<W: Write>(&self, w: &mut BinaryWriter<W>) -> Result<()>;
 }
 
-pub struct BinaryReader<R: Read>(pub R, u32);
+/// Version of the binary chart format. Charts are headed by [`CHART_MAGIC`] and the version, except for those of version 1, which start
+/// right with the offset.
+///
+/// - 2: line anchors
+/// - 3: visible times of notes
+/// - 4: custom hitsounds of notes
+pub const CHART_VERSION: u8 = 4;
+const CHART_MAGIC: &[u8; 3] = b"PBC";
+
+/// Reader of binary data, keeping the chart format version being read. It's the latest one unless a chart header says otherwise.
+pub struct BinaryReader<R: Read>(pub R, u32, u8);
 
 impl<R: Read> BinaryReader<R> {
     pub fn new(reader: R) -> Self {
-        Self(reader, 0)
+        Self(reader, 0, CHART_VERSION)
+    }
+
+    pub fn version(&self) -> u8 {
+        self.2
     }
 
     pub fn reset_time(&mut self) {
@@ -282,21 +296,27 @@ impl BinaryData for Object {
 
 impl BinaryData for CtrlObject {
     fn read_binary<R: Read>(r: &mut BinaryReader<R>) -> Result<Self> {
-        assert_eq!(r.read::<u8>()?, 8);
+        // 8 for the original four controls, 9 with skew
+        let fields = r.read::<u8>()?;
+        if !matches!(fields, 8 | 9) {
+            bail!("Invalid control object");
+        }
         Ok(Self {
             alpha: r.read()?,
             size: r.read()?,
             pos: r.read()?,
             y: r.read()?,
+            skew: if fields == 9 { r.read()? } else { Anim::default() },
         })
     }
 
     fn write_binary<W: Write>(&self, w: &mut BinaryWriter<W>) -> Result<()> {
-        w.write_val(8_u8)?;
+        w.write_val(9_u8)?;
         w.write(&self.alpha)?;
         w.write(&self.size)?;
         w.write(&self.pos)?;
         w.write(&self.y)?;
+        w.write(&self.skew)?;
         Ok(())
     }
 }
@@ -321,6 +341,8 @@ impl BinaryData for Note {
             above: r.read()?,
             multiple_hint: false,
             fake: r.read()?,
+            visible_time: if r.version() >= 3 && r.read()? { r.read()? } else { f32::INFINITY },
+            hitsound: if r.version() >= 4 && r.read()? { Some(r.read()?) } else { None },
             judge: JudgeStatus::NotJudged,
         })
     }
@@ -349,6 +371,18 @@ impl BinaryData for Note {
         }
         w.write_val(self.above)?;
         w.write_val(self.fake)?;
+        if self.visible_time.is_infinite() {
+            w.write_val(false)?;
+        } else {
+            w.write_val(true)?;
+            w.write_val(self.visible_time)?;
+        }
+        if let Some(hitsound) = &self.hitsound {
+            w.write_val(true)?;
+            w.write(hitsound)?;
+        } else {
+            w.write_val(false)?;
+        }
         Ok(())
     }
 }
@@ -362,6 +396,7 @@ impl BinaryData for JudgeLine {
             1 => JudgeLineKind::Texture(Texture2D::empty().into(), r.read()?),
             2 => JudgeLineKind::Text(r.read()?),
             3 => JudgeLineKind::Paint(r.read()?, RefCell::default()),
+            4 => JudgeLineKind::TextureGif(Vec::new(), r.read()?),
             _ => bail!("invalid judge line kind"),
         };
         let height = r.read()?;
@@ -377,6 +412,7 @@ impl BinaryData for JudgeLine {
         let ctrl_obj = RefCell::new(r.read()?);
         let incline = r.read()?;
         let z_index = r.read()?;
+        let anchor = if r.version() >= 2 { [r.read()?, r.read()?] } else { [0.5, 0.5] };
         Ok(Self {
             object,
             kind,
@@ -390,6 +426,7 @@ impl BinaryData for JudgeLine {
             ctrl_obj,
             incline,
             z_index,
+            anchor,
 
             cache,
         })
@@ -411,6 +448,10 @@ impl BinaryData for JudgeLine {
                 w.write_val(3_u8)?;
                 w.write(events)?;
             }
+            JudgeLineKind::TextureGif(_, path) => {
+                w.write_val(4_u8)?;
+                w.write(path)?;
+            }
         }
         w.write(&self.height)?;
         w.array(&self.notes)?;
@@ -424,6 +465,8 @@ impl BinaryData for JudgeLine {
         w.write(self.ctrl_obj.borrow().deref())?;
         w.write(&self.incline)?;
         w.write(&self.z_index)?;
+        w.write_val(self.anchor[0])?;
+        w.write_val(self.anchor[1])?;
         Ok(())
     }
 }
@@ -445,7 +488,20 @@ impl BinaryData for ChartSettings {
 
 impl BinaryData for Chart {
     fn read_binary<R: Read>(r: &mut BinaryReader<R>) -> Result<Self> {
-        let offset = r.read()?;
+        let mut head = [0; 4];
+        r.0.read_exact(&mut head)?;
+        let offset = if head[..3] == CHART_MAGIC[..] {
+            let version = head[3];
+            if !(2..=CHART_VERSION).contains(&version) {
+                bail!("Unsupported chart version: {version}");
+            }
+            r.2 = version;
+            r.read()?
+        } else {
+            // headless charts of version 1, the magic reads as an offset too tiny to be real
+            r.2 = 1;
+            f32::from_le_bytes(head)
+        };
         let mut lines = r.array()?;
         process_lines(&mut lines);
         let settings = r.read()?;
@@ -453,9 +509,96 @@ impl BinaryData for Chart {
     }
 
     fn write_binary<W: Write>(&self, w: &mut BinaryWriter<W>) -> Result<()> {
+        w.0.write_all(CHART_MAGIC)?;
+        w.write_val(CHART_VERSION)?;
         w.write_val(self.offset)?;
         w.array(&self.lines)?;
         w.write(&self.settings)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::AnimFloat,
+        judge::tests::{chart, line, note},
+    };
+    use std::io::Cursor;
+
+    fn write_chart(chart: &Chart) -> Vec<u8> {
+        let mut w = BinaryWriter::new(Vec::new());
+        w.write(chart).unwrap();
+        w.0
+    }
+
+    fn read_chart(bytes: &[u8]) -> Result<Chart> {
+        BinaryReader::new(Cursor::new(bytes)).read()
+    }
+
+    /// A line with click notes at the given times in the layout of version 1
+    fn write_legacy_line<W: Write>(w: &mut BinaryWriter<W>, notes: &[f32]) -> Result<()> {
+        w.reset_time();
+        w.write(&Object::default())?;
+        w.write_val(0_u8)?;
+        w.write(&AnimFloat::default())?;
+        w.uleb(notes.len() as _)?;
+        for time in notes {
+            w.write(&Object::default())?;
+            w.write_val(0_u8)?;
+            w.time(*time)?;
+            w.write_val(*time)?;
+            w.write_val(false)?;
+            w.write_val(true)?;
+            w.write_val(false)?;
+        }
+        w.write(&Anim::<Color>::default())?;
+        w.uleb(0)?;
+        w.write_val(true)?;
+        w.write_val(0_u8)?;
+        w.write_val(8_u8)?;
+        for _ in 0..4 {
+            w.write(&AnimFloat::default())?;
+        }
+        w.write(&AnimFloat::default())?;
+        w.write(&0_i32)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut line = line(vec![note(NoteKind::Click, 1., 0.2), note(NoteKind::Drag, 1.5, -0.3)]);
+        line.anchor = [0.25, 1.];
+        line.notes[1].visible_time = 0.8;
+        line.notes[0].hitsound = Some("hit.ogg".to_owned());
+        let bytes = write_chart(&chart(vec![line]));
+        assert_eq!(&bytes[..4], b"PBC\x04");
+        let chart = read_chart(&bytes).unwrap();
+        let line = &chart.lines[0];
+        assert_eq!(line.anchor, [0.25, 1.]);
+        assert_eq!(line.notes.iter().map(|it| (it.time, it.visible_time)).collect::<Vec<_>>(), [(1., f32::INFINITY), (1.5, 0.8)]);
+        assert_eq!(line.notes.iter().map(|it| it.hitsound.as_deref()).collect::<Vec<_>>(), [Some("hit.ogg"), None]);
+    }
+
+    #[test]
+    fn legacy_chart() {
+        let mut w = BinaryWriter::new(Vec::new());
+        w.write_val(0.5_f32).unwrap();
+        w.uleb(1).unwrap();
+        write_legacy_line(&mut w, &[1., 2.]).unwrap();
+        w.write(&ChartSettings::default()).unwrap();
+        let chart = read_chart(&w.0).unwrap();
+        assert_eq!(chart.offset, 0.5);
+        let line = &chart.lines[0];
+        assert_eq!(line.anchor, [0.5, 0.5]);
+        assert_eq!(line.notes.iter().map(|it| (it.time, it.visible_time)).collect::<Vec<_>>(), [(1., f32::INFINITY), (2., f32::INFINITY)]);
+        assert!(line.notes.iter().all(|it| it.hitsound.is_none()));
+    }
+
+    #[test]
+    fn newer_chart() {
+        let mut bytes = write_chart(&chart(Vec::new()));
+        bytes[3] = CHART_VERSION + 1;
+        assert!(read_chart(&bytes).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use anyhow::{bail, Result};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
@@ -14,6 +15,108 @@ pub enum ChallengeModeColor {
     Rainbow,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ParticleStyle {
+    #[default]
+    Square,
+    Circle,
+}
+
+/// Judge line colors while full combo / all perfect is kept
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JudgeLinePalette {
+    /// Colors of the resource pack
+    #[default]
+    Default,
+    /// Sky blue and orange, distinguishable under protanopia and deuteranopia
+    BlueOrange,
+    /// Reddish purple and bluish green, distinguishable under tritanopia
+    PurpleGreen,
+}
+
+impl JudgeLinePalette {
+    /// Colors (perfect, good) in hex, or `None` to use the resource pack ones
+    pub fn colors(self) -> Option<(u32, u32)> {
+        match self {
+            Self::Default => None,
+            Self::BlueOrange => Some((0x56b4e9, 0xe69f00)),
+            Self::PurpleGreen => Some((0xcc79a7, 0x009e73)),
+        }
+    }
+}
+
+/// How the score is computed from judgements
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScoreMode {
+    /// 90% accuracy and 10% max combo
+    #[default]
+    Standard,
+    /// 70% accuracy and 30% max combo, as in older versions
+    Classic,
+    /// Accuracy only, combo doesn't count
+    AccuracyWeighted,
+}
+
+impl ScoreMode {
+    /// Weights of accuracy and max combo ratio in the score, adding up to 1
+    pub fn weights(self) -> (f64, f64) {
+        match self {
+            Self::Standard => (0.9, 0.1),
+            Self::Classic => (0.7, 0.3),
+            Self::AccuracyWeighted => (1., 0.),
+        }
+    }
+
+    /// Score of an all perfect play
+    pub fn max_score(self) -> u32 {
+        match self {
+            Self::Standard | Self::AccuracyWeighted => 1_000_000,
+            Self::Classic => 100_000,
+        }
+    }
+
+    /// Number of digits to display scores with
+    pub fn digits(self) -> usize {
+        self.max_score().to_string().len()
+    }
+}
+
+/// Judgement windows in milliseconds
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct JudgeWindows {
+    pub perfect: f32,
+    pub good: f32,
+    pub bad: f32,
+}
+
+impl Default for JudgeWindows {
+    fn default() -> Self {
+        Self {
+            perfect: 80.,
+            good: 160.,
+            bad: 220.,
+        }
+    }
+}
+
+impl JudgeWindows {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.perfect > 0. && self.perfect <= self.good && self.good <= self.bad) {
+            bail!(
+                "Invalid judge windows: perfect ({}ms), good ({}ms) and bad ({}ms) must be positive and in ascending order",
+                self.perfect,
+                self.good,
+                self.bad
+            );
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 #[serde(default)]
 #[serde(rename_all = "camelCase")]
@@ -23,25 +126,61 @@ pub struct Config {
     pub aggressive: bool,
     pub aspect_ratio: Option<f32>,
     pub audio_buffer_size: Option<u32>,
+    pub audio_latency: f32,
+    pub audio_offset: f32,
     pub autoplay: bool,
+    pub autoplay_jitter_ms: f32,
+    pub autoplay_touches: bool,
+    pub background_dim: Option<f32>,
     pub challenge_color: ChallengeModeColor,
     pub challenge_rank: u32,
     pub chart_debug: bool,
     pub disable_effect: bool,
     pub double_click_to_pause: bool,
     pub double_hint: bool,
+    pub fade_in: f32,
+    pub fade_out: f32,
     pub fix_aspect_ratio: bool,
+    pub flick_min_distance: f32,
+    pub flick_threshold: f32,
+    pub flow_speed: f32,
     pub fxaa: bool,
+    pub haptics: bool,
+    pub hold_tick_interval: f32,
+    pub input_offset: f32,
     pub interactive: bool,
+    pub judge_line_palette: JudgeLinePalette,
+    pub judge_windows: JudgeWindows,
+    pub keyboard: bool,
+    pub keyboard_keys: Vec<String>,
+    pub lenient_parse: bool,
+    pub mirror: bool,
+    pub miss_sound: bool,
+    pub music_gain_db: f32,
+    pub mute_autoplay_sfx: bool,
     pub note_scale: f32,
     pub offline_mode: bool,
     pub offset: f32,
     pub particle: bool,
+    pub particle_color: u32,
+    pub particle_count: u32,
+    pub particle_style: ParticleStyle,
+    pub permissive_touch: bool,
     pub player_name: String,
     pub player_rks: f32,
+    pub practice: bool,
+    pub record_replay: Option<String>,
+    pub render_scale: f32,
+    pub replay: Option<String>,
     pub res_pack_path: Option<String>,
+    pub resume_countdown: u32,
+    pub resume_rewind: f32,
     pub sample_count: u32,
+    pub score_mode: ScoreMode,
     pub show_acc: bool,
+    pub show_early_late: bool,
+    pub show_fps: bool,
+    pub show_max_combo: bool,
     pub speed: f32,
     pub touch_debug: bool,
     pub volume_music: f32,
@@ -55,25 +194,61 @@ impl Default for Config {
             aggressive: true,
             aspect_ratio: None,
             audio_buffer_size: None,
+            audio_latency: 0.,
+            audio_offset: 0.,
             autoplay: false,
+            autoplay_jitter_ms: 0.,
+            autoplay_touches: false,
+            background_dim: None,
             challenge_color: ChallengeModeColor::Golden,
             challenge_rank: 45,
             chart_debug: false,
             disable_effect: false,
             double_click_to_pause: true,
             double_hint: true,
+            fade_in: 0.,
+            fade_out: 0.,
             fix_aspect_ratio: false,
+            flick_min_distance: 0.02,
+            flick_threshold: 1.15,
+            flow_speed: 1.0,
             fxaa: false,
+            haptics: false,
+            hold_tick_interval: 0.,
+            input_offset: 0.,
             interactive: true,
+            judge_line_palette: JudgeLinePalette::Default,
+            judge_windows: JudgeWindows::default(),
+            keyboard: false,
+            keyboard_keys: ["A", "S", "D", "F", "J", "K", "L", "Semicolon"].map(str::to_owned).to_vec(),
+            lenient_parse: false,
+            mirror: false,
+            miss_sound: false,
+            music_gain_db: 0.,
+            mute_autoplay_sfx: false,
             note_scale: 1.0,
             offline_mode: false,
             offset: 0.,
             particle: true,
+            particle_color: 0xffffff,
+            particle_count: 4,
+            particle_style: ParticleStyle::Square,
+            permissive_touch: false,
             player_name: "Mivik".to_string(),
             player_rks: 15.,
+            practice: false,
+            record_replay: None,
+            render_scale: 1.,
+            replay: None,
             res_pack_path: None,
+            resume_countdown: 3,
+            resume_rewind: 3.,
             sample_count: 1,
+            score_mode: ScoreMode::Standard,
             show_acc: false,
+            show_early_late: true,
+            show_fps: false,
+            show_max_combo: false,
             speed: 1.,
             touch_debug: false,
             volume_music: 1.,
@@ -81,3 +256,18 @@ impl Default for Config {
         }
     }
 }
+
+impl Config {
+    /// Checks settings that aren't checked where they're used, see also [`JudgeWindows::validate`]
+    pub fn validate(&self) -> Result<()> {
+        if self.resume_rewind.is_nan() || self.resume_rewind < 0. {
+            bail!("Invalid resume rewind: {}s, it must not be negative", self.resume_rewind);
+        }
+        Ok(())
+    }
+
+    /// Volume of the chart's music, which is `volume_music` with `music_gain_db` applied
+    pub fn music_volume(&self) -> f32 {
+        self.volume_music * 10f32.powf(self.music_gain_db / 20.)
+    }
+}
@@ -12,8 +12,11 @@ pub type Matrix = nalgebra::Matrix3<f32>;
 mod anim;
 pub use anim::{Anim, AnimFloat, AnimVector, Keyframe};
 
+mod audio;
+pub use audio::{load_audio, AudioFormat};
+
 mod chart;
-pub use chart::{Chart, ChartExtra, ChartSettings};
+pub use chart::{Chart, ChartExtra, ChartSettings, ChartStats, ChartWarning, NoteStats};
 
 mod effect;
 pub use effect::{Effect, Uniform};
@@ -29,7 +32,7 @@ mod object;
 pub use object::{CtrlObject, Object};
 
 mod render;
-pub use render::{copy_fbo, MSRenderTarget};
+pub use render::{blit_fbo, copy_fbo, MSRenderTarget};
 
 mod resource;
 pub use resource::{NoteStyle, ParticleEmitter, ResPackInfo, Resource, ResourcePack, DPI_VALUE};
@@ -52,7 +55,7 @@ pub fn init_assets() {
     set_pc_assets_folder("assets");
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct Triple(i32, u32, u32);
 impl Default for Triple {
     fn default() -> Self {
@@ -64,20 +67,106 @@ impl Triple {
     pub fn beats(&self) -> f32 {
         self.0 as f32 + self.1 as f32 / self.2 as f32
     }
+
+    /// Approximates `beats` as a mixed fraction, preferring small denominators
+    pub fn from_beats(beats: f32) -> Self {
+        let int = beats.floor();
+        let frac = beats - int;
+        let den = (1..=64)
+            .find(|den| {
+                let num = frac * *den as f32;
+                (num.round() - num).abs() < 1e-3
+            })
+            .unwrap_or(10000);
+        Self(int as i32, (frac * den as f32).round() as u32, den)
+    }
+
+    /// The exact beat this triple represents
+    pub fn beat(&self) -> Beat {
+        if self.2 == 0 {
+            // malformed, treat it as a whole bar
+            return Beat::new(self.0 as i64, 1);
+        }
+        Beat::new(self.0 as i64 * self.2 as i64 + self.1 as i64, self.2 as u64)
+    }
+}
+
+/// An exact rational beat, always kept in lowest terms so that equal beats compare (and convert) identically
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Beat {
+    num: i32,
+    den: u32,
+}
+
+impl Beat {
+    /// `num / den` in lowest terms. It saturates instead of failing: a zero denominator gives the extreme beat of the numerator's sign
+    /// (or zero), and terms too large to fit are clamped.
+    pub fn new(num: i64, den: u64) -> Self {
+        fn gcd(mut a: u64, mut b: u64) -> u64 {
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+            a
+        }
+        if den == 0 {
+            return Self {
+                num: num.signum() as i32 * i32::MAX,
+                den: 1,
+            };
+        }
+        let g = gcd(num.unsigned_abs(), den).max(1);
+        Self {
+            num: (num / g as i64).clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+            den: (den / g).min(u32::MAX as u64) as u32,
+        }
+    }
+
+    pub fn num(&self) -> i32 {
+        self.num
+    }
+
+    pub fn den(&self) -> u32 {
+        self.den
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+impl PartialOrd for Beat {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Beat {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.num as i64 * other.den as i64).cmp(&(other.num as i64 * self.den as i64))
+    }
 }
 
 #[derive(Default)] // the default is a dummy
 pub struct BpmList {
-    elements: Vec<(f32, f32, f32)>, // (beats, time, bpm)
-    cursor: usize,
+    // accumulated in f64 so that long charts with many BPM changes don't drift
+    elements: Vec<(f64, f64, f64)>, // (beats, time, bpm)
 }
 
 impl BpmList {
     pub fn new(ranges: Vec<(f32, f32)> /*(beat, bpm)*/) -> Self {
+        Self::build(ranges.into_iter().map(|(beats, bpm)| (beats as f64, bpm as f64)))
+    }
+
+    /// Like [`BpmList::new`], but without rounding the beats to f32 first
+    pub fn with_beats(ranges: Vec<(Beat, f32)>) -> Self {
+        Self::build(ranges.into_iter().map(|(beat, bpm)| (beat.as_f64(), bpm as f64)))
+    }
+
+    fn build(ranges: impl Iterator<Item = (f64, f64)>) -> Self {
         let mut elements = Vec::new();
         let mut time = 0.0;
         let mut last_beats = 0.0;
-        let mut last_bpm: Option<f32> = None;
+        let mut last_bpm: Option<f64> = None;
         for (now_beats, bpm) in ranges {
             if let Some(bpm) = last_bpm {
                 time += (now_beats - last_beats) * (60. / bpm);
@@ -86,38 +175,98 @@ impl BpmList {
             last_bpm = Some(bpm);
             elements.push((now_beats, time, bpm));
         }
-        BpmList { elements, cursor: 0 }
+        BpmList { elements }
     }
 
-    pub fn time_beats(&mut self, beats: f32) -> f32 {
-        while let Some(kf) = self.elements.get(self.cursor + 1) {
-            if kf.0 > beats {
-                break;
-            }
-            self.cursor += 1;
-        }
-        while self.cursor != 0 && self.elements[self.cursor].0 > beats {
-            self.cursor -= 1;
-        }
-        let (start_beats, time, bpm) = &self.elements[self.cursor];
-        time + (beats - start_beats) * (60. / bpm)
+    /// The `(beat, bpm)` pairs this list is built from
+    pub fn ranges(&self) -> Vec<(f32, f32)> {
+        self.elements.iter().map(|(beats, _, bpm)| (*beats as f32, *bpm as f32)).collect()
     }
 
-    pub fn time(&mut self, triple: &Triple) -> f32 {
-        self.time_beats(triple.beats())
+    pub fn time_of(&self, beats: f64) -> f32 {
+        let index = self.elements.partition_point(|it| it.0 <= beats).saturating_sub(1);
+        let (start_beats, time, bpm) = self.elements[index];
+        (time + (beats - start_beats) * (60. / bpm)) as f32
     }
 
-    pub fn beat(&mut self, time: f32) -> f32 {
-        while let Some(kf) = self.elements.get(self.cursor + 1) {
-            if kf.1 > time {
-                break;
+    pub fn time_beats(&self, beats: f32) -> f32 {
+        self.time_of(beats as f64)
+    }
+
+    pub fn time(&self, triple: &Triple) -> f32 {
+        self.time_of_beat(triple.beat())
+    }
+
+    /// Converts an exact beat to seconds, notes on the same beat always get the very same time
+    pub fn time_of_beat(&self, beat: Beat) -> f32 {
+        self.time_of(beat.as_f64())
+    }
+
+    pub fn beat(&self, time: f32) -> f32 {
+        let time = time as f64;
+        let index = self.elements.partition_point(|it| it.1 <= time).saturating_sub(1);
+        let (beats, start_time, bpm) = self.elements[index];
+        (beats + (time - start_time) / (60. / bpm)) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beat_terms() {
+        assert_eq!(Beat::new(4, 6), Beat::new(2, 3));
+        assert_eq!((Beat::new(-4, 6).num(), Beat::new(-4, 6).den()), (-2, 3));
+        assert!(Beat::new(1, 3) < Beat::new(1, 2));
+        // saturating instead of panicking or wrapping around
+        assert_eq!(Beat::new(5, 0), Beat::new(i32::MAX as i64, 1));
+        assert_eq!(Beat::new(-5, 0), Beat::new(-(i32::MAX as i64), 1));
+        assert_eq!(Beat::new(0, 0), Beat::new(0, 1));
+        assert_eq!(Beat::new(i64::MAX, 1).num(), i32::MAX);
+        assert_eq!(Beat::new(i64::MIN, 1).num(), i32::MIN);
+        assert_eq!(Beat::new(1, u64::MAX).den(), u32::MAX);
+    }
+
+    #[test]
+    fn bpm_list_brute_force() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut beat = 0;
+        let ranges: Vec<_> = (0..1500)
+            .map(|i| {
+                if i != 0 {
+                    beat += rng.gen_range(1..48);
+                }
+                (Beat::new(beat, 12), rng.gen_range(30..400) as f32 + [0., 0.5, 0.25][i % 3])
+            })
+            .collect();
+        let list = BpmList::with_beats(ranges.clone());
+        // integrates segment by segment from the very start for every query
+        let brute_force = |beats: f64| {
+            let mut time = 0.;
+            for (i, (start, bpm)) in ranges.iter().enumerate() {
+                let start = start.as_f64();
+                if start > beats {
+                    break;
+                }
+                let end = ranges.get(i + 1).map_or(f64::INFINITY, |it| it.0.as_f64()).min(beats);
+                time += (end - start) * 60. / *bpm as f64;
             }
-            self.cursor += 1;
+            time
+        };
+        let last = ranges.last().unwrap().0.as_f64() + 10.;
+        for _ in 0..5000 {
+            let beat = Beat::new(rng.gen_range(0..(last * 48.) as i64), 48);
+            let expected = brute_force(beat.as_f64());
+            let time = list.time_of_beat(beat);
+            assert!((time as f64 - expected).abs() < 1e-3, "beat {beat:?}: {time} != {expected}");
+            assert!((list.beat(time) as f64 - beat.as_f64()).abs() < 1e-2, "beat {beat:?} back from {time}");
         }
-        while self.cursor != 0 && self.elements[self.cursor].1 > time {
-            self.cursor -= 1;
+        // change points themselves
+        for (start, _) in &ranges {
+            assert!((list.time_of_beat(*start) as f64 - brute_force(start.as_f64())).abs() < 1e-3);
         }
-        let (beats, start_time, bpm) = &self.elements[self.cursor];
-        beats + (time - start_time) / (60. / bpm)
     }
 }
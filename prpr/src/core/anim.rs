@@ -63,6 +63,11 @@ impl<T: Tweenable> Anim<T> {
         self.keyframes.is_empty() && self.next.is_none()
     }
 
+    /// Number of keyframes, including those of chained animations
+    pub fn keyframe_count(&self) -> usize {
+        self.keyframes.len() + self.next.as_ref().map_or(0, |it| it.keyframe_count())
+    }
+
     pub fn chain(elements: Vec<Anim<T>>) -> Self {
         if elements.is_empty() {
             return Self::default();
@@ -115,14 +120,12 @@ impl<T: Tweenable> Anim<T> {
     }
 
     pub fn now_opt(&self) -> Option<T> {
-        let Some(now) = self.now_opt_inner() else {
-            return None;
-        };
-        Some(if let Some(next) = &self.next {
-            T::add(&now, &next.now_opt().unwrap())
-        } else {
-            now
-        })
+        // chained animations (e.g. RPE event layers) add up, and empty ones contribute nothing
+        let next = self.next.as_ref().and_then(|it| it.now_opt());
+        match (self.now_opt_inner(), next) {
+            (Some(now), Some(next)) => Some(T::add(&now, &next)),
+            (now, next) => now.or(next),
+        }
     }
 
     pub fn map_value(&mut self, mut f: impl FnMut(T) -> T) {
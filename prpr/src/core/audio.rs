@@ -0,0 +1,67 @@
+use anyhow::{bail, Context, Result};
+use macroquad::prelude::info;
+use sasa::AudioClip;
+use std::{fmt, path::Path};
+
+/// Audio container formats we can decode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Ogg,
+    Flac,
+    Mp3,
+}
+
+impl fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Wav => "WAV",
+            Self::Ogg => "OGG",
+            Self::Flac => "FLAC",
+            Self::Mp3 => "MP3",
+        })
+    }
+}
+
+impl AudioFormat {
+    fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WAVE" {
+            Some(Self::Wav)
+        } else if bytes.starts_with(b"OggS") {
+            Some(Self::Ogg)
+        } else if bytes.starts_with(b"fLaC") {
+            Some(Self::Flac)
+        } else if bytes.starts_with(b"ID3") || (bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] & 0xE0 == 0xE0) {
+            // ID3 tag or MPEG frame sync
+            Some(Self::Mp3)
+        } else {
+            None
+        }
+    }
+
+    fn from_extension(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        Some(match ext.as_str() {
+            "wav" | "wave" => Self::Wav,
+            "ogg" | "oga" => Self::Ogg,
+            "flac" => Self::Flac,
+            "mp3" => Self::Mp3,
+            _ => return None,
+        })
+    }
+
+    /// Detects the format from magic bytes, falling back to the file extension
+    pub fn detect(path: &str, bytes: &[u8]) -> Result<Self> {
+        match Self::from_magic(bytes).or_else(|| Self::from_extension(path)) {
+            Some(format) => Ok(format),
+            None => bail!("Unsupported audio format of {path}, only WAV, OGG, FLAC and MP3 are supported"),
+        }
+    }
+}
+
+/// Detects the format of the given audio file and decodes it
+pub fn load_audio(path: &str, bytes: Vec<u8>) -> Result<AudioClip> {
+    let format = AudioFormat::detect(path, &bytes)?;
+    info!("Loading audio {path} as {format}");
+    AudioClip::new(bytes).with_context(|| format!("Failed to decode {format} audio {path}"))
+}
@@ -1,8 +1,30 @@
-use super::{BpmList, Effect, JudgeLine, JudgeLineKind, Matrix, Resource, UIElement, Vector, Video};
-use crate::{fs::FileSystem, judge::JudgeStatus, ui::Ui};
+use super::{load_audio, AnimFloat, BpmList, Effect, JudgeLine, JudgeLineKind, Matrix, NoteKind, Resource, UIElement, Vector, Video, EPS};
+use crate::{ext::NotNanExt, fs::FileSystem, judge::JudgeStatus, ui::Ui};
 use anyhow::{Context, Result};
+use image::{codecs::gif::GifDecoder, AnimationDecoder, RgbaImage};
 use macroquad::prelude::*;
-use std::cell::RefCell;
+use sasa::AudioClip;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt,
+    io::Cursor,
+};
+
+fn decode_gif(bytes: Vec<u8>) -> Result<Vec<(f32, RgbaImage)>> {
+    let mut time = 0.;
+    GifDecoder::new(Cursor::new(bytes))?
+        .into_frames()
+        .map(|frame| -> Result<_> {
+            let frame = frame?;
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let delay = numer as f32 / denom as f32 / 1000.;
+            // browsers treat zero delays as 100ms, and so do we
+            time += if delay <= 0. { 0.1 } else { delay };
+            Ok((time, frame.into_buffer()))
+        })
+        .collect()
+}
 
 #[derive(Default)]
 pub struct ChartExtra {
@@ -11,6 +33,60 @@ pub struct ChartExtra {
     pub videos: Vec<Video>,
 }
 
+/// Suspicious content found by [`Chart::validate`]
+pub struct ChartWarning {
+    pub line: usize,
+    pub note: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ChartWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "In judge line #{}", self.line)?;
+        if let Some(note) = self.note {
+            write!(f, ", note #{note}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Note counts of a chart, see [`Chart::note_stats`]. Fake notes are not counted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoteStats {
+    pub taps: u32,
+    pub drags: u32,
+    pub holds: u32,
+    pub flicks: u32,
+    pub total: u32,
+    /// The time the last note (or hold) ends at
+    pub last_note_time: f32,
+}
+
+/// Overall statistics of a chart, see [`Chart::stats`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChartStats {
+    pub notes: NoteStats,
+    pub fake_notes: u32,
+    /// The time the last note ends or the last event finishes, whichever is later
+    pub duration: f32,
+    /// Maximum number of real notes within any 1s window
+    pub peak_nps: u32,
+    pub lines: usize,
+    /// Total keyframes of all judge lines, including those of notes
+    pub events: usize,
+}
+
+impl fmt::Display for ChartStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let n = &self.notes;
+        write!(
+            f,
+            "{} notes ({} taps, {} drags, {} holds, {} flicks, {} fake), {:.1}s, peak {} nps, {} lines, {} events",
+            n.total, n.taps, n.drags, n.holds, n.flicks, self.fake_notes, self.duration, self.peak_nps, self.lines, self.events
+        )
+    }
+}
+
 #[derive(Default)]
 pub struct ChartSettings {
     pub pe_alpha_extension: bool,
@@ -23,6 +99,8 @@ pub struct Chart {
     pub bpm_list: RefCell<BpmList>,
     pub settings: ChartSettings,
     pub extra: ChartExtra,
+    /// Custom hitsounds of notes, keyed by their paths
+    pub hitsounds: HashMap<String, AudioClip>,
 
     pub order: Vec<usize>,
     pub attach_ui: [Option<usize>; 7],
@@ -48,6 +126,7 @@ impl Chart {
             bpm_list: RefCell::new(bpm_list),
             settings,
             extra,
+            hitsounds: HashMap::new(),
 
             order,
             attach_ui,
@@ -57,10 +136,12 @@ impl Chart {
     #[inline]
     pub fn with_element<R>(&self, ui: &mut Ui, res: &Resource, element: UIElement, f: impl FnOnce(&mut Ui, Color, Matrix) -> R) -> R {
         if let Some(id) = self.attach_ui[element as usize - 1] {
-            let obj = &self.lines[id].object;
-            let mut tr = obj.now_translation(res);
+            let line = &self.lines[id];
+            let obj = &line.object;
+            // follow the parent chain, like the line itself would when rendered
+            let mut tr = line.now_translation(res.aspect_ratio, &self.lines);
             tr.y = -tr.y;
-            let mut color = self.lines[id].color.now_opt().unwrap_or(WHITE);
+            let mut color = line.color.now_opt().unwrap_or(WHITE);
             color.a *= obj.now_alpha().max(0.);
             ui.with(obj.now_rotation().append_translation(&tr), |ui| f(ui, color, obj.now_scale()))
         } else {
@@ -68,15 +149,226 @@ impl Chart {
         }
     }
 
+    pub fn note_stats(&self) -> NoteStats {
+        let mut stats = NoteStats::default();
+        for note in self.lines.iter().flat_map(|it| it.notes.iter()).filter(|it| !it.fake) {
+            let end_time = match note.kind {
+                NoteKind::Click => {
+                    stats.taps += 1;
+                    note.time
+                }
+                NoteKind::Drag => {
+                    stats.drags += 1;
+                    note.time
+                }
+                NoteKind::Hold { end_time, .. } => {
+                    stats.holds += 1;
+                    end_time
+                }
+                NoteKind::Flick => {
+                    stats.flicks += 1;
+                    note.time
+                }
+            };
+            stats.total += 1;
+            stats.last_note_time = stats.last_note_time.max(end_time);
+        }
+        stats
+    }
+
+    pub fn stats(&self) -> ChartStats {
+        let notes = self.note_stats();
+        let mut times: Vec<_> = self
+            .lines
+            .iter()
+            .flat_map(|it| it.notes.iter())
+            .filter(|it| !it.fake)
+            .map(|it| it.time.not_nan())
+            .collect();
+        times.sort_unstable();
+        let mut peak_nps = 0;
+        let mut start = 0;
+        for (end, time) in times.iter().enumerate() {
+            while **time - *times[start] >= 1. {
+                start += 1;
+            }
+            peak_nps = peak_nps.max(end + 1 - start);
+        }
+        let mut duration = notes.last_note_time;
+        let mut events = 0;
+        let mut fake_notes = 0;
+        fn visit(anim: &AnimFloat, duration: &mut f32, events: &mut usize) {
+            *events += anim.keyframe_count();
+            let mut anim = Some(anim);
+            while let Some(it) = anim {
+                if let Some(last) = it.keyframes.last() {
+                    *duration = duration.max(last.time);
+                }
+                anim = it.next.as_deref();
+            }
+        }
+        for line in &self.lines {
+            let obj = &line.object;
+            for anim in [
+                &obj.alpha,
+                &obj.rotation,
+                &obj.translation.0,
+                &obj.translation.1,
+                &obj.scale.0,
+                &obj.scale.1,
+                &line.incline,
+            ] {
+                visit(anim, &mut duration, &mut events);
+            }
+            // height is extended to the end of the chart by parsers, it's not an actual event
+            events += line.height.keyframe_count();
+            for note in &line.notes {
+                if note.fake {
+                    fake_notes += 1;
+                }
+                let obj = &note.object;
+                events += [
+                    &obj.alpha,
+                    &obj.rotation,
+                    &obj.translation.0,
+                    &obj.translation.1,
+                    &obj.scale.0,
+                    &obj.scale.1,
+                ]
+                .into_iter()
+                .map(|it| it.keyframe_count())
+                .sum::<usize>();
+            }
+        }
+        ChartStats {
+            notes,
+            fake_notes,
+            duration,
+            peak_nps: peak_nps as u32,
+            lines: self.lines.len(),
+            events,
+        }
+    }
+
+    /// Collects suspicious content in the chart. Nothing is fixed, this is purely diagnostic.
+    pub fn validate(&self) -> Vec<ChartWarning> {
+        let mut warnings = Vec::new();
+        for (line_id, line) in self.lines.iter().enumerate() {
+            let mut push = |note: Option<usize>, message: String| {
+                warnings.push(ChartWarning {
+                    line: line_id,
+                    note,
+                    message,
+                })
+            };
+            let obj = &line.object;
+            for (anim, desc) in [
+                (&obj.alpha, "alpha"),
+                (&obj.rotation, "rotate"),
+                (&obj.translation.0, "move X"),
+                (&obj.translation.1, "move Y"),
+                (&obj.scale.0, "scale X"),
+                (&obj.scale.1, "scale Y"),
+                (&line.height, "speed"),
+            ] {
+                if let Some(time) = Self::reversed_keyframe(anim) {
+                    push(None, format!("{desc} events go backwards in time at {time:.3}s"));
+                }
+            }
+            for (id, note) in line.notes.iter().enumerate() {
+                if note.time < 0. {
+                    push(Some(id), format!("note is placed before time 0 ({:.3}s)", note.time));
+                }
+                if let NoteKind::Hold { end_time, .. } = note.kind {
+                    if end_time < note.time {
+                        push(Some(id), format!("hold ends ({end_time:.3}s) before it starts ({:.3}s)", note.time));
+                    }
+                }
+                if let Some(x) = note.object.translation.0.keyframes.iter().map(|it| it.value).find(|it| it.abs() > 1.) {
+                    push(Some(id), format!("note is placed outside of the screen (x = {x:.3})"));
+                }
+            }
+            let mut sorted: Vec<usize> = (0..line.notes.len()).collect();
+            sorted.sort_by_key(|it| line.notes[*it].time.not_nan());
+            for (i, id) in sorted.iter().enumerate() {
+                let note = &line.notes[*id];
+                let x = note.object.translation.0.now_opt().unwrap_or_default();
+                for other in sorted[i + 1..].iter().take_while(|it| (line.notes[**it].time - note.time).abs() < EPS) {
+                    let other_note = &line.notes[*other];
+                    if other_note.above == note.above && (other_note.object.translation.0.now_opt().unwrap_or_default() - x).abs() < EPS {
+                        push(Some(*other), format!("duplicated with note #{id}"));
+                    }
+                }
+            }
+        }
+        warnings
+    }
+
+    fn reversed_keyframe(anim: &AnimFloat) -> Option<f32> {
+        anim.keyframes.windows(2).find(|it| it[1].time < it[0].time).map(|it| it[1].time)
+    }
+
+    /// Loads custom hitsounds of notes. Notes whose hitsounds fail to load fall back to the default ones.
+    pub async fn load_hitsounds(&mut self, fs: &mut dyn FileSystem) {
+        let mut failed = HashSet::new();
+        for note in self.lines.iter_mut().flat_map(|it| it.notes.iter_mut()) {
+            let Some(path) = &note.hitsound else {
+                continue;
+            };
+            if self.hitsounds.contains_key(path) {
+                continue;
+            }
+            if !failed.contains(path) {
+                match fs.load_file(path).await.and_then(|it| load_audio(path, it)) {
+                    Ok(clip) => {
+                        self.hitsounds.insert(path.clone(), clip);
+                        continue;
+                    }
+                    Err(err) => {
+                        warn!("Failed to load hitsound {path}: {err:?}");
+                        failed.insert(path.clone());
+                    }
+                }
+            }
+            note.hitsound = None;
+        }
+    }
+
     pub async fn load_textures(&mut self, fs: &mut dyn FileSystem) -> Result<()> {
         for line in &mut self.lines {
             if let JudgeLineKind::Texture(tex, path) = &mut line.kind {
                 *tex = image::load_from_memory(&fs.load_file(path).await.with_context(|| format!("failed to load illustration {path}"))?)?.into();
             }
+            if let JudgeLineKind::TextureGif(frames, path) = &mut line.kind {
+                let bytes = fs.load_file(path).await.with_context(|| format!("failed to load illustration {path}"))?;
+                // decoding large GIFs takes a while, keep it off the main thread
+                #[cfg(target_arch = "wasm32")]
+                let decoded = decode_gif(bytes);
+                #[cfg(not(target_arch = "wasm32"))]
+                let decoded = crate::ext::thread_as_future(move || decode_gif(bytes)).await;
+                *frames = decoded
+                    .with_context(|| format!("failed to decode GIF {path}"))?
+                    .into_iter()
+                    .map(|(time, image)| (time, Texture2D::from_rgba8(image.width() as _, image.height() as _, &image).into()))
+                    .collect();
+            }
         }
         Ok(())
     }
 
+    /// Flips the chart horizontally by negating x positions and rotations of lines and notes. Input isn't affected, so judging goes along
+    /// with what's shown.
+    pub fn mirror(&mut self) {
+        for line in &mut self.lines {
+            line.object.translation.0.map_value(|x| -x);
+            line.object.rotation.map_value(|r| -r);
+            for note in &mut line.notes {
+                note.object.translation.0.map_value(|x| -x);
+                note.object.rotation.map_value(|r| -r);
+            }
+        }
+    }
+
     pub fn reset(&mut self) {
         self.lines
             .iter_mut()
@@ -92,7 +384,11 @@ impl Chart {
             line.object.set_time(res.time);
         }
         // TODO optimize
-        let trs = self.lines.iter().map(|it| it.now_transform(res, &self.lines)).collect::<Vec<_>>();
+        let trs = self
+            .lines
+            .iter()
+            .map(|it| it.now_transform(res.aspect_ratio, &self.lines))
+            .collect::<Vec<_>>();
         for (line, tr) in self.lines.iter_mut().zip(trs) {
             line.update(res, tr);
         }
@@ -112,8 +408,15 @@ impl Chart {
         }
         res.apply_model_of(&Matrix::identity().append_nonuniform_scaling(&Vector::new(1.0, -1.0)), |res| {
             let mut guard = self.bpm_list.borrow_mut();
+            let mut last_z = None;
             for id in &self.order {
-                self.lines[*id].render(ui, res, &self.lines, &mut guard, &self.settings, *id);
+                let line = &self.lines[*id];
+                // notes are batched, flush them when entering a new layer so they stay in their line's layer
+                if last_z.map_or(false, |z| z != line.z_index) {
+                    res.note_buffer.borrow_mut().draw_all();
+                }
+                last_z = Some(line.z_index);
+                line.render(ui, res, &self.lines, &mut guard, &self.settings, *id);
             }
             drop(guard);
             res.note_buffer.borrow_mut().draw_all();
@@ -7,10 +7,10 @@ use crate::{
 use macroquad::prelude::*;
 use miniquad::{RenderPass, Texture, TextureParams, TextureWrap};
 use nalgebra::Rotation2;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 
-#[derive(Clone, Copy, Deserialize)]
+#[derive(Clone, Copy, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum UIElement {
@@ -43,6 +43,8 @@ pub enum JudgeLineKind {
     #[default]
     Normal,
     Texture(SafeTexture, String),
+    /// Animated texture, with each frame paired with the time it ends at
+    TextureGif(Vec<(f32, SafeTexture)>, String),
     Text(Anim<String>),
     Paint(Anim<f32>, RefCell<(Option<RenderPass>, bool)>),
 }
@@ -56,7 +58,7 @@ pub struct JudgeLineCache {
 
 impl JudgeLineCache {
     pub fn new(notes: &mut Vec<Note>) -> Self {
-        notes.sort_by_key(|it| (it.plain(), !it.above, it.speed.not_nan(), ((it.height + it.object.translation.1.now()) * it.speed).not_nan()));
+        notes.sort_by_key(Self::order);
         let mut res = Self {
             update_order: Vec::new(),
             not_plain_count: 0,
@@ -67,6 +69,11 @@ impl JudgeLineCache {
         res
     }
 
+    /// Key of the order notes are kept in, which parsers can sort data paired with notes on beforehand
+    pub(crate) fn order(note: &Note) -> impl Ord {
+        (note.plain(), !note.above, note.speed.not_nan(), ((note.height + note.object.translation.1.now()) * note.speed).not_nan())
+    }
+
     pub(crate) fn reset(&mut self, notes: &mut Vec<Note>) {
         self.update_order = (0..notes.len() as u32).collect();
         self.above_indices.clear();
@@ -108,6 +115,8 @@ pub struct JudgeLine {
     pub z_index: i32,
     pub show_below: bool,
     pub attach_ui: Option<UIElement>,
+    /// The point of the texture placed at the line's position, relative to the texture size. `[0.5, 0.5]` is the center
+    pub anchor: [f32; 2],
 
     pub cache: JudgeLineCache,
 }
@@ -161,21 +170,28 @@ impl JudgeLine {
         });
     }
 
-    pub fn now_transform(&self, res: &Resource, lines: &[JudgeLine]) -> Matrix {
+    pub fn now_translation(&self, aspect_ratio: f32, lines: &[JudgeLine]) -> Vector {
         if let Some(parent) = self.parent {
-            let po = &lines[parent].object;
-            let mut tr = Rotation2::new(po.rotation.now().to_radians()) * self.object.now_translation(res);
-            tr += po.now_translation(res);
-            self.object.now_rotation().append_translation(&tr)
+            let parent = &lines[parent];
+            Rotation2::new(parent.object.rotation.now().to_radians()) * self.object.now_translation(aspect_ratio)
+                + parent.now_translation(aspect_ratio, lines)
+        } else {
+            self.object.now_translation(aspect_ratio)
+        }
+    }
+
+    pub fn now_transform(&self, aspect_ratio: f32, lines: &[JudgeLine]) -> Matrix {
+        if self.parent.is_some() {
+            self.object.now_rotation().append_translation(&self.now_translation(aspect_ratio, lines))
         } else {
-            self.object.now(res)
+            self.object.now(aspect_ratio)
         }
     }
 
     pub fn render(&self, ui: &mut Ui, res: &mut Resource, lines: &[JudgeLine], bpm_list: &mut BpmList, settings: &ChartSettings, id: usize) {
         let alpha = self.object.alpha.now_opt().unwrap_or(1.0) * res.alpha;
         let color = self.color.now_opt();
-        res.with_model(self.now_transform(res, lines), |res| {
+        res.with_model(self.now_transform(res.aspect_ratio, lines), |res| {
             if res.config.chart_debug {
                 res.apply_model(|_| {
                     ui.text(id.to_string()).pos(0., -0.01).anchor(0.5, 1.).size(0.8).draw();
@@ -189,14 +205,23 @@ impl JudgeLine {
                         let len = res.info.line_length;
                         draw_line(-len, 0., len, 0., 0.01, color);
                     }
-                    JudgeLineKind::Texture(texture, _) => {
+                    JudgeLineKind::Texture(..) | JudgeLineKind::TextureGif(..) => {
+                        let texture = match &self.kind {
+                            JudgeLineKind::Texture(texture, _) => texture,
+                            JudgeLineKind::TextureGif(frames, _) => {
+                                let Some((total, _)) = frames.last() else { return };
+                                let t = res.time.rem_euclid(*total);
+                                &frames[frames.partition_point(|(end, _)| *end <= t).min(frames.len() - 1)].1
+                            }
+                            _ => unreachable!(),
+                        };
                         let mut color = color.unwrap_or(WHITE);
                         color.a = alpha.max(0.0);
                         let hf = vec2(texture.width() / res.aspect_ratio, texture.height() / res.aspect_ratio);
                         draw_texture_ex(
                             **texture,
-                            -hf.x / 2.,
-                            -hf.y / 2.,
+                            -hf.x * self.anchor[0],
+                            -hf.y * self.anchor[1],
                             color,
                             DrawTextureParams {
                                 dest_size: Some(hf),
@@ -209,6 +234,9 @@ impl JudgeLine {
                         let mut color = color.unwrap_or(WHITE);
                         color.a = alpha.max(0.0);
                         let now = anim.now();
+                        if now.is_empty() {
+                            return;
+                        }
                         res.apply_model_of(&Matrix::identity().append_nonuniform_scaling(&Vector::new(1., -1.)), |_| {
                             draw_text_aligned(ui, &now, 0., 0., (0.5, 0.5), 1., color);
                         });
@@ -315,7 +343,7 @@ impl JudgeLine {
             }
             for index in &self.cache.above_indices {
                 let speed = self.notes[*index].speed;
-                let limit = height_above / speed;
+                let limit = height_above / (speed * res.config.flow_speed);
                 for note in self.notes[*index..].iter() {
                     if !note.above || speed != note.speed {
                         break;
@@ -332,7 +360,7 @@ impl JudgeLine {
                 }
                 for index in &self.cache.below_indices {
                     let speed = self.notes[*index].speed;
-                    let limit = height_below / speed;
+                    let limit = height_below / (speed * res.config.flow_speed);
                     for note in self.notes[*index..].iter() {
                         if speed != note.speed {
                             break;
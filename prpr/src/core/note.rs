@@ -35,6 +35,10 @@ pub struct Note {
     pub above: bool,
     pub multiple_hint: bool,
     pub fake: bool,
+    /// How long before its time the note shows up, infinite for always visible
+    pub visible_time: f32,
+    /// Path of a custom hitsound in the chart folder, played instead of the default one
+    pub hitsound: Option<String>,
     pub judge: JudgeStatus,
 }
 
@@ -145,14 +149,25 @@ impl Note {
             None
         } {
             self.init_ctrl_obj(ctrl_obj, line_height);
-            res.with_model(parent_tr * self.now_transform(res, ctrl_obj, 0., 0.), |res| {
+            res.with_model(parent_tr * self.now_transform(res.aspect_ratio, ctrl_obj, 0., 0.), |res| {
+                res.emit_at_origin(parent_rot + if self.above { 0. } else { 180. }, color)
+            });
+        }
+        // fake notes are never judged, but still show the hit effect when reaching the line
+        if self.fake && matches!(self.judge, JudgeStatus::NotJudged) && res.time >= self.time {
+            self.judge = JudgeStatus::PreJudge;
+            self.init_ctrl_obj(ctrl_obj, line_height);
+            let color = res.res_pack.info.fx_perfect();
+            res.with_model(parent_tr * self.now_transform(res.aspect_ratio, ctrl_obj, 0., 0.), |res| {
                 res.emit_at_origin(parent_rot + if self.above { 0. } else { 180. }, color)
             });
         }
     }
 
     pub fn dead(&self) -> bool {
-        (!matches!(self.kind, NoteKind::Hold { .. }) || matches!(self.judge, JudgeStatus::Judged)) && self.object.dead()
+        (!matches!(self.kind, NoteKind::Hold { .. }) || matches!(self.judge, JudgeStatus::Judged))
+            && !(self.fake && matches!(self.judge, JudgeStatus::NotJudged))
+            && self.object.dead()
         // && self.ctrl_obj.dead()
     }
 
@@ -160,20 +175,37 @@ impl Note {
         ctrl_obj.set_height((self.height - line_height + self.object.translation.1.now() / self.speed) * RPE_HEIGHT / 2.);
     }
 
-    pub fn now_transform(&self, res: &Resource, ctrl_obj: &CtrlObject, base: f32, incline_sin: f32) -> Matrix {
-        let incline_val = 1. - incline_sin * (base * res.aspect_ratio + self.object.translation.1.now()) * RPE_HEIGHT / 2. / 360.;
-        let mut tr = self.object.now_translation(res);
+    pub fn now_transform(&self, aspect_ratio: f32, ctrl_obj: &CtrlObject, base: f32, incline_sin: f32) -> Matrix {
+        let incline_val = 1. - incline_sin * (base * aspect_ratio + self.object.translation.1.now()) * RPE_HEIGHT / 2. / 360.;
+        let mut tr = self.object.now_translation(aspect_ratio);
         tr.x *= incline_val * ctrl_obj.pos.now_opt().unwrap_or(1.);
         tr.y += base;
         let mut scale = self.object.scale.now_with_def(1., 1.);
         scale.x *= ctrl_obj.size.now_opt().unwrap_or(1.);
-        self.object.now_rotation().append_nonuniform_scaling(&scale).append_translation(&tr)
+        let transform = self.object.now_rotation().append_nonuniform_scaling(&scale).append_translation(&tr);
+        match ctrl_obj.skew.now_opt() {
+            Some(skew) if skew != 0. => {
+                let shear = skew.to_radians().tan();
+                transform * Matrix::new(1., shear, 0., 0., 1., 0., 0., 0., 1.)
+            }
+            _ => transform,
+        }
+    }
+
+    /// Transform for hold notes, with the incline applied as a shear so that the body and the head tilt along with the note column
+    fn now_hold_transform(&self, aspect_ratio: f32, ctrl_obj: &CtrlObject, incline_sin: f32) -> Matrix {
+        let tr = self.object.now_translation(aspect_ratio);
+        let shear = -incline_sin * tr.x * ctrl_obj.pos.now_opt().unwrap_or(1.) * aspect_ratio * RPE_HEIGHT / 2. / 360.;
+        Matrix::new(1., shear, -shear * tr.y, 0., 1., 0., 0., 0., 1.) * self.now_transform(aspect_ratio, ctrl_obj, 0., incline_sin)
     }
 
     pub fn render(&self, res: &mut Resource, config: &mut RenderConfig, bpm_list: &mut BpmList) {
         if matches!(self.judge, JudgeStatus::Judged) && !matches!(self.kind, NoteKind::Hold { .. }) {
             return;
         }
+        if res.time < self.time - self.visible_time {
+            return;
+        }
         if config.appear_before.is_finite() {
             // TODO optimize
             let beat = bpm_list.beat(self.time);
@@ -191,7 +223,7 @@ impl Note {
         self.init_ctrl_obj(ctrl_obj, config.line_height);
         let mut color = self.object.now_color();
         color.a *= res.alpha * ctrl_obj.alpha.now_opt().unwrap_or(1.);
-        let spd = self.speed * ctrl_obj.y.now_opt().unwrap_or(1.);
+        let spd = self.speed * ctrl_obj.y.now_opt().unwrap_or(1.) * res.config.flow_speed;
 
         let line_height = config.line_height / res.aspect_ratio * spd;
         let height = self.height / res.aspect_ratio * spd;
@@ -214,7 +246,7 @@ impl Note {
             if !config.draw_below {
                 color.a *= (self.time - res.time).min(0.) / FADEOUT_TIME + 1.;
             }
-            res.with_model(self.now_transform(res, ctrl_obj, base, config.incline_sin), |res| {
+            res.with_model(self.now_transform(res.aspect_ratio, ctrl_obj, base, config.incline_sin), |res| {
                 draw_center(res, tex, order, scale, color);
             });
         };
@@ -223,7 +255,7 @@ impl Note {
                 draw(res, *style.click);
             }
             NoteKind::Hold { end_time, end_height } => {
-                res.with_model(self.now_transform(res, ctrl_obj, 0., 0.), |res| {
+                res.with_model(self.now_hold_transform(res.aspect_ratio, ctrl_obj, config.incline_sin), |res| {
                     let style = if res.config.double_hint && self.multiple_hint {
                         &res.res_pack.note_style_mh
                     } else {
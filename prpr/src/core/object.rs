@@ -1,4 +1,4 @@
-use super::{AnimFloat, AnimVector, Color, Matrix, Resource, Vector};
+use super::{AnimFloat, AnimVector, Color, Matrix, Vector};
 use macroquad::prelude::*;
 use nalgebra::Rotation2;
 
@@ -38,8 +38,8 @@ impl Object {
             && self.translation.1.dead()
     }
 
-    pub fn now(&self, res: &Resource) -> Matrix {
-        self.now_rotation().append_translation(&self.now_translation(res))
+    pub fn now(&self, aspect_ratio: f32) -> Matrix {
+        self.now_rotation().append_translation(&self.now_translation(aspect_ratio))
     }
 
     #[inline]
@@ -48,9 +48,9 @@ impl Object {
     }
 
     #[inline]
-    pub fn now_translation(&self, res: &Resource) -> Vector {
+    pub fn now_translation(&self, aspect_ratio: f32) -> Vector {
         let mut tr = self.translation.now();
-        tr.y /= res.aspect_ratio;
+        tr.y /= aspect_ratio;
         tr
     }
 
@@ -76,6 +76,8 @@ pub struct CtrlObject {
     pub size: AnimFloat,
     pub pos: AnimFloat,
     pub y: AnimFloat,
+    /// Horizontal skew in degrees
+    pub skew: AnimFloat,
 }
 
 impl CtrlObject {
@@ -84,5 +86,6 @@ impl CtrlObject {
         self.size.set_time(height);
         self.pos.set_time(height);
         self.y.set_time(height);
+        self.skew.set_time(height);
     }
 }
@@ -6,6 +6,7 @@ use miniquad::{gl::GLuint, RenderPass, Texture, TextureFormat};
 
 pub struct MSRenderTarget {
     dim: (u32, u32),
+    format: TextureFormat,
     fbo: GLuint,
     rbo: GLuint,
     dummy: RenderTarget,
@@ -13,12 +14,19 @@ pub struct MSRenderTarget {
 }
 
 pub fn copy_fbo(src: GLuint, dst: GLuint, dim: (u32, u32)) -> bool {
+    blit_fbo(src, dim, dst, dim)
+}
+
+/// Copies `src` onto `dst`, stretching it with linear filtering if the sizes differ
+pub fn blit_fbo(src: GLuint, src_dim: (u32, u32), dst: GLuint, dst_dim: (u32, u32)) -> bool {
     unsafe {
         use miniquad::gl::*;
         glBindFramebuffer(GL_READ_FRAMEBUFFER, src);
         glBindFramebuffer(GL_DRAW_FRAMEBUFFER, dst);
-        let (w, h) = (dim.0 as i32, dim.1 as i32);
-        glBlitFramebuffer(0, 0, w, h, 0, 0, w, h, GL_COLOR_BUFFER_BIT, GL_NEAREST);
+        let (sw, sh) = (src_dim.0 as i32, src_dim.1 as i32);
+        let (dw, dh) = (dst_dim.0 as i32, dst_dim.1 as i32);
+        let filter = if src_dim == dst_dim { GL_NEAREST } else { GL_LINEAR };
+        glBlitFramebuffer(0, 0, sw, sh, 0, 0, dw, dh, GL_COLOR_BUFFER_BIT, filter);
         glGetError() == GL_NO_ERROR
     }
 }
@@ -27,33 +35,48 @@ fn internal_id(target: RenderTarget) -> GLuint {
     target.render_pass.gl_internal_id(unsafe { get_internal_gl() }.quad_context)
 }
 
+fn new_texture(dim: (u32, u32), format: TextureFormat) -> Texture {
+    Texture::new_render_texture(
+        unsafe { get_internal_gl() }.quad_context,
+        miniquad::TextureParams {
+            width: dim.0,
+            height: dim.1,
+            format,
+            ..Default::default()
+        },
+    )
+}
+
 impl MSRenderTarget {
     pub fn new(dim: (u32, u32), samples: u32) -> Self {
+        Self::with_format(dim, samples, TextureFormat::RGB8)
+    }
+
+    /// Creates a render target of the given format, which should be either `RGB8` or `RGBA8` (to keep the alpha channel)
+    pub fn with_format(dim: (u32, u32), samples: u32, format: TextureFormat) -> Self {
+        let internal_format = match format {
+            TextureFormat::RGB8 => miniquad::gl::GL_RGB8,
+            TextureFormat::RGBA8 => miniquad::gl::GL_RGBA8,
+            _ => panic!("Unsupported render target format: {format:?}"),
+        };
         let mut fbo = 0;
         let mut rbo = 0;
         unsafe {
             use miniquad::gl::*;
             glGenRenderbuffers(1, &mut rbo as *mut _);
             glBindRenderbuffer(GL_RENDERBUFFER, rbo);
-            glRenderbufferStorageMultisample(GL_RENDERBUFFER, samples as _, GL_RGB8, dim.0 as _, dim.1 as _);
+            glRenderbufferStorageMultisample(GL_RENDERBUFFER, samples as _, internal_format, dim.0 as _, dim.1 as _);
             glGenFramebuffers(1, &mut fbo as *mut _);
             glBindFramebuffer(GL_FRAMEBUFFER, fbo);
             glFramebufferRenderbuffer(GL_FRAMEBUFFER, GL_COLOR_ATTACHMENT0, GL_RENDERBUFFER, rbo);
         }
         let gl = unsafe { get_internal_gl() };
-        let texture = Texture::new_render_texture(
-            gl.quad_context,
-            miniquad::TextureParams {
-                width: dim.0,
-                height: dim.1,
-                format: TextureFormat::RGB8,
-                ..Default::default()
-            },
-        );
+        let texture = new_texture(dim, format);
         let render_pass = RenderPass::new(gl.quad_context, texture, None);
         let dummy_render_pass = RenderPass::from_raw(gl.quad_context, fbo, texture);
         Self {
             dim,
+            format,
             fbo,
             rbo,
             dummy: RenderTarget {
@@ -70,6 +93,10 @@ impl MSRenderTarget {
         }
     }
 
+    pub fn dim(&self) -> (u32, u32) {
+        self.dim
+    }
+
     pub fn blit(&self) {
         copy_fbo(self.fbo, internal_id(self.output[0].unwrap()), self.dim);
     }
@@ -78,15 +105,7 @@ impl MSRenderTarget {
         self.output.swap(0, 1);
         if self.output[0].is_none() {
             let gl = unsafe { get_internal_gl() };
-            let texture = miniquad::Texture::new_render_texture(
-                gl.quad_context,
-                miniquad::TextureParams {
-                    width: self.dim.0,
-                    height: self.dim.1,
-                    format: TextureFormat::RGB8,
-                    ..Default::default()
-                },
-            );
+            let texture = new_texture(self.dim, self.format);
             let render_pass = RenderPass::new(gl.quad_context, texture, None);
             self.output[0] = Some(RenderTarget {
                 texture: Texture2D::from_miniquad_texture(texture),
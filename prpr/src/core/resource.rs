@@ -1,17 +1,25 @@
-use super::{MSRenderTarget, Matrix, Point, NOTE_WIDTH_RATIO_BASE};
+use super::{load_audio, MSRenderTarget, Matrix, Point, NOTE_WIDTH_RATIO_BASE};
 use crate::{
-    config::Config,
+    config::{Config, ParticleStyle},
     ext::{create_audio_manger, nalgebra_to_glm, SafeTexture},
     fs::FileSystem,
     info::ChartInfo,
-    particle::{AtlasConfig, ColorCurve, Emitter, EmitterConfig},
+    particle::{AtlasConfig, ColorCurve, Emitter, EmitterConfig, ParticleShape},
+    ui::TextPainter,
 };
 use anyhow::{bail, Context, Result};
 use macroquad::prelude::*;
 use miniquad::{gl::GLuint, Texture, TextureWrap};
 use sasa::{AudioClip, AudioManager, Sfx};
 use serde::Deserialize;
-use std::{cell::RefCell, collections::BTreeMap, ops::DerefMut, path::Path, sync::atomic::AtomicU32};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    ops::DerefMut,
+    path::Path,
+    rc::Rc,
+    sync::atomic::AtomicU32,
+};
 
 pub const MAX_SIZE: usize = 64; // needs tweaking
 pub static DPI_VALUE: AtomicU32 = AtomicU32::new(250);
@@ -148,6 +156,7 @@ pub struct ResourcePack {
     pub sfx_click: AudioClip,
     pub sfx_drag: AudioClip,
     pub sfx_flick: AudioClip,
+    pub sfx_miss: AudioClip,
     pub ending: AudioClip,
     pub hit_fx: SafeTexture,
 }
@@ -226,6 +235,7 @@ impl ResourcePack {
             sfx_click: load_clip!("click.ogg"),
             sfx_drag: load_clip!("drag.ogg"),
             sfx_flick: load_clip!("flick.ogg"),
+            sfx_miss: load_clip!("miss.wav"),
             ending: load_clip!("ending.mp3"),
             hit_fx,
         })
@@ -237,12 +247,13 @@ pub struct ParticleEmitter {
     emitter: Emitter,
     emitter_square: Emitter,
     hide_particles: bool,
+    particle_count: usize,
 }
 
 impl ParticleEmitter {
-    pub fn new(res_pack: &ResourcePack, scale: f32, hide_particles: bool) -> Result<Self> {
+    pub fn new(res_pack: &ResourcePack, config: &Config) -> Result<Self> {
         let colors_curve = {
-            let start = WHITE;
+            let start = Color::from_hex(config.particle_color);
             let mut mid = start;
             let mut end = start;
             mid.a *= 0.7;
@@ -271,15 +282,20 @@ impl ParticleEmitter {
                 initial_direction_spread: 2. * std::f32::consts::PI,
                 size_randomness: 0.3,
                 emitting: false,
-                initial_velocity: 2.5 * scale,
+                initial_velocity: 2.5 * config.note_scale,
                 initial_velocity_randomness: 1. / 10.,
                 linear_accel: -6. / 1.,
                 colors_curve,
+                shape: match config.particle_style {
+                    ParticleStyle::Square => ParticleShape::Rectangle { aspect_ratio: 1. },
+                    ParticleStyle::Circle => ParticleShape::Circle { subdivisions: 16 },
+                },
                 ..Default::default()
             }),
-            hide_particles,
+            hide_particles: res_pack.info.hide_particles,
+            particle_count: config.particle_count as usize,
         };
-        res.set_scale(scale);
+        res.set_scale(config.note_scale);
         Ok(res)
     }
 
@@ -289,7 +305,7 @@ impl ParticleEmitter {
         self.emitter.emit(pt, 1);
         if !self.hide_particles {
             self.emitter_square.config.base_color = color;
-            self.emitter_square.emit(pt, 4);
+            self.emitter_square.emit(pt, self.particle_count);
         }
     }
 
@@ -298,6 +314,11 @@ impl ParticleEmitter {
         self.emitter_square.draw(vec2(0., 0.), dt);
     }
 
+    pub fn clear(&mut self) {
+        self.emitter.clear();
+        self.emitter_square.clear();
+    }
+
     pub fn set_scale(&mut self, scale: f32) {
         self.emitter.config.size = self.scale * scale / 5.;
         self.emitter_square.config.size = self.scale * scale / 44.;
@@ -336,6 +357,8 @@ impl NoteBuffer {
 pub struct Resource {
     pub config: Config,
     pub info: ChartInfo,
+    /// Font specified by the chart, see [`ChartInfo::font`]
+    pub chart_font: Option<Rc<RefCell<TextPainter>>>,
     pub aspect_ratio: f32,
     pub dpi: u32,
     pub last_screen_size: (u32, u32),
@@ -368,6 +391,10 @@ pub struct Resource {
     pub sfx_click: Sfx,
     pub sfx_drag: Sfx,
     pub sfx_flick: Sfx,
+    /// Played when combo breaks, if `miss_sound` is on
+    pub sfx_miss: Sfx,
+    /// Custom hitsounds of the chart, keyed by their paths
+    pub hitsounds: HashMap<String, Sfx>,
 
     pub chart_target: Option<MSRenderTarget>,
     pub no_effect: bool,
@@ -421,8 +448,9 @@ impl Resource {
     }
 
     pub async fn new(
-        config: Config,
+        mut config: Config,
         info: ChartInfo,
+        chart_font: Option<Rc<RefCell<TextPainter>>>,
         mut fs: Box<dyn FileSystem>,
         player: Option<SafeTexture>,
         background: SafeTexture,
@@ -434,6 +462,7 @@ impl Resource {
                 SafeTexture::from(Texture2D::from_image(&load_image($path).await?))
             };
         }
+        config.flow_speed = config.flow_speed.clamp(0.5, 3.);
         let res_pack = ResourcePack::from_path(config.res_pack_path.as_ref())
             .await
             .context("Failed to load resource pack")?;
@@ -444,18 +473,18 @@ impl Resource {
         };
 
         let mut audio = create_audio_manger(&config)?;
-        let music = AudioClip::new(fs.load_file(&info.music).await?)?;
+        let music = load_audio(&info.music, fs.load_file(&info.music).await?)?;
         let track_length = music.length();
         let buffer_size = Some(1024);
         let sfx_click = audio.create_sfx(res_pack.sfx_click.clone(), buffer_size)?;
         let sfx_drag = audio.create_sfx(res_pack.sfx_drag.clone(), buffer_size)?;
         let sfx_flick = audio.create_sfx(res_pack.sfx_flick.clone(), buffer_size)?;
+        let sfx_miss = audio.create_sfx(res_pack.sfx_miss.clone(), buffer_size)?;
 
         let aspect_ratio = config.aspect_ratio.unwrap_or(info.aspect_ratio);
         let note_width = config.note_scale * NOTE_WIDTH_RATIO_BASE;
-        let note_scale = config.note_scale;
 
-        let emitter = ParticleEmitter::new(&res_pack, note_scale, res_pack.info.hide_particles)?;
+        let emitter = ParticleEmitter::new(&res_pack, &config)?;
 
         let no_effect = config.disable_effect || has_no_effect;
 
@@ -463,6 +492,7 @@ impl Resource {
         Ok(Self {
             config,
             info,
+            chart_font,
             aspect_ratio,
             dpi: DPI_VALUE.load(std::sync::atomic::Ordering::SeqCst),
             last_screen_size: (0, 0),
@@ -495,6 +525,8 @@ impl Resource {
             sfx_click,
             sfx_drag,
             sfx_flick,
+            sfx_miss,
+            hitsounds: HashMap::new(),
 
             chart_target: None,
             no_effect,
@@ -515,13 +547,30 @@ impl Resource {
             .emit_at(vec2(pt.x, -pt.y), if self.res_pack.info.hit_fx_rotate { rotation.to_radians() } else { 0. }, color);
     }
 
+    /// Judge line colors (perfect, good), taken from the configured palette or the resource pack
+    pub fn judge_line_colors(&self) -> (Color, Color) {
+        let (perfect, good) = self
+            .config
+            .judge_line_palette
+            .colors()
+            .unwrap_or((self.res_pack.info.color_perfect, self.res_pack.info.color_good));
+        (Color::from_hex(perfect), Color::from_hex(good))
+    }
+
+    /// Dim of the in-playfield background, overriding the chart's own one if configured
+    pub fn background_dim(&self) -> f32 {
+        self.config.background_dim.unwrap_or(self.info.background_dim).clamp(0., 1.)
+    }
+
     pub fn update_size(&mut self, dim: (u32, u32)) -> bool {
         if self.last_screen_size == dim {
             return false;
         }
         self.last_screen_size = dim;
-        if !self.no_effect || self.config.sample_count != 1 {
-            self.chart_target = Some(MSRenderTarget::new(dim, self.config.sample_count));
+        if !self.no_effect || self.config.sample_count != 1 || self.config.render_scale != 1. {
+            let scale = self.config.render_scale;
+            let target_dim = (((dim.0 as f32 * scale).round() as u32).max(1), ((dim.1 as f32 * scale).round() as u32).max(1));
+            self.chart_target = Some(MSRenderTarget::new(target_dim, self.config.sample_count));
         }
         fn viewport(aspect_ratio: f32, (w, h): (u32, u32)) -> (i32, i32, i32, i32) {
             let w = w as f32;
@@ -550,6 +599,19 @@ impl Resource {
         true
     }
 
+    /// Viewport to use when rendering onto the chart target. [`Resource::camera`] keeps the viewport in screen space, which input mapping relies on,
+    /// while the chart target is `render_scale` times the size of the screen.
+    pub fn render_viewport(&self) -> Option<(i32, i32, i32, i32)> {
+        let scale = self.config.render_scale;
+        if self.chart_target.is_none() || scale == 1. {
+            return self.camera.viewport;
+        }
+        self.camera.viewport.map(|(x, y, w, h)| {
+            let f = |it: i32| (it as f32 * scale).round() as i32;
+            (f(x), f(y), f(w), f(h))
+        })
+    }
+
     pub fn world_to_screen(&self, pt: Point) -> Point {
         self.model_stack.last().unwrap().transform_point(&pt)
     }
@@ -145,7 +145,12 @@ impl StaticTween {
 pub struct ClampedTween(pub TweenId, pub Range<f32>, pub Range<f32>);
 impl TweenFunction for ClampedTween {
     fn y(&self, x: f32) -> f32 {
-        (TWEEN_FUNCTIONS[self.0 as usize](f32::tween(&self.1.start, &self.1.end, x)) - self.2.start) / (self.2.end - self.2.start)
+        let height = self.2.end - self.2.start;
+        if height.abs() < 1e-6 {
+            // the clipped segment starts and ends at the same value (e.g. easingLeft == easingRight), normalizing would give NaN
+            return x;
+        }
+        (TWEEN_FUNCTIONS[self.0 as usize](f32::tween(&self.1.start, &self.1.end, x)) - self.2.start) / height
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -238,8 +243,9 @@ impl BezierTween {
             return x;
         }
         let id = (x / SAMPLE_STEP) as usize;
-        let id = id.min(SAMPLE_TABLE_SIZE - 1);
-        let dist = (x - self.sample_table[id]) / (self.sample_table[id + 1] - self.sample_table[id]);
+        let id = id.min(SAMPLE_TABLE_SIZE - 2);
+        let span = self.sample_table[id + 1] - self.sample_table[id];
+        let dist = if span.abs() <= SLOPE_EPS { 0. } else { (x - self.sample_table[id]) / span };
         let init_t = SAMPLE_STEP * (id as f32 + dist);
         match Self::slope(self.p1.0, self.p2.0, init_t) {
             y if y <= SLOPE_EPS => init_t,
@@ -249,6 +255,9 @@ impl BezierTween {
     }
 
     pub fn new(p1: (f32, f32), p2: (f32, f32)) -> Self {
+        // x coordinates outside [0, 1] would make the curve non-monotonic in x
+        let p1 = (if p1.0.is_finite() { p1.0.clamp(0., 1.) } else { 0. }, p1.1);
+        let p2 = (if p2.0.is_finite() { p2.0.clamp(0., 1.) } else { 1. }, p2.1);
         Self {
             sample_table: std::array::from_fn(|i| Self::sample(p1.0, p2.0, i as f32 * SAMPLE_STEP)),
             p1,
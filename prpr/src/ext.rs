@@ -1,7 +1,7 @@
 use crate::{
     config::Config,
     core::{Matrix, Point, Vector},
-    ui::Ui,
+    ui::{TextPainter, Ui},
 };
 use anyhow::{anyhow, Result};
 use image::DynamicImage;
@@ -165,7 +165,28 @@ pub fn get_viewport() -> (i32, i32, i32, i32) {
 
 #[inline]
 pub fn draw_text_aligned(ui: &mut Ui, text: &str, x: f32, y: f32, anchor: (f32, f32), scale: f32, color: Color) -> Rect {
-    ui.text(text).pos(x, y).anchor(anchor.0, anchor.1).size(scale).color(color).draw()
+    draw_text_aligned_with_font(ui, None, text, x, y, anchor, scale, color)
+}
+
+/// Same as [`draw_text_aligned`], with the given font instead of the default one if it's present
+#[allow(clippy::too_many_arguments)]
+#[inline]
+pub fn draw_text_aligned_with_font(
+    ui: &mut Ui,
+    font: Option<&mut TextPainter>,
+    text: &str,
+    x: f32,
+    y: f32,
+    anchor: (f32, f32),
+    scale: f32,
+    color: Color,
+) -> Rect {
+    ui.text(text)
+        .pos(x, y)
+        .anchor(anchor.0, anchor.1)
+        .size(scale)
+        .color(color)
+        .draw_with_font(font)
 }
 
 #[derive(Default, Clone, Copy, Deserialize)]
@@ -388,6 +409,29 @@ pub fn create_audio_manger(config: &Config) -> Result<AudioManager> {
     }
 }
 
+/// Vibrates the device for a short while. Only Android is supported, elsewhere this does nothing.
+pub fn vibrate(#[allow(unused_variables)] ms: i64) {
+    #[cfg(target_os = "android")]
+    unsafe {
+        let env = miniquad::native::attach_jni_env();
+        let ctx = ndk_context::android_context().context();
+        let class = (**env).GetObjectClass.unwrap()(env, ctx);
+        let method =
+            (**env).GetMethodID.unwrap()(env, class, b"getSystemService\0".as_ptr() as _, b"(Ljava/lang/String;)Ljava/lang/Object;\0".as_ptr() as _);
+        let name = (**env).NewStringUTF.unwrap()(env, b"vibrator\0".as_ptr() as _);
+        let vibrator = (**env).CallObjectMethod.unwrap()(env, ctx, method, name);
+        if !vibrator.is_null() {
+            let class = (**env).GetObjectClass.unwrap()(env, vibrator);
+            let method = (**env).GetMethodID.unwrap()(env, class, b"vibrate\0".as_ptr() as _, b"(J)V\0".as_ptr() as _);
+            (**env).CallVoidMethod.unwrap()(env, vibrator, method, ms);
+        }
+        // e.g. the VIBRATE permission is missing
+        if (**env).ExceptionCheck.unwrap()(env) != 0 {
+            (**env).ExceptionClear.unwrap()(env);
+        }
+    }
+}
+
 pub fn make_pipeline(write_color: bool, pass_op: StencilOp, test_func: CompareFunc, test_ref: i32) -> GlPipeline {
     let InternalGlContext {
         quad_gl: gl,
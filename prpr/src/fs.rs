@@ -1,4 +1,4 @@
-use crate::{ext::spawn_task, info::ChartInfo};
+use crate::{ext::spawn_task, info::ChartInfo, ui::FontArc};
 use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
 use chardetng::EncodingDetector;
@@ -127,11 +127,15 @@ pub struct ZipFileSystem(pub Arc<Mutex<ZipArchive<Cursor<Vec<u8>>>>>, String);
 impl ZipFileSystem {
     pub fn new(bytes: Vec<u8>) -> Result<Self> {
         let zip = ZipArchive::new(Cursor::new(bytes))?;
-        let root_dirs = zip
+        // archives made by zipping a folder keep everything inside of it, even without an explicit directory entry
+        let mut roots = zip
             .file_names()
-            .filter(|it| it.ends_with('/') && it.find('/') == Some(it.len() - 1))
-            .collect::<Vec<_>>();
-        let root = if root_dirs.len() == 1 { root_dirs[0].to_owned() } else { String::new() };
+            .filter(|it| !it.starts_with("__MACOSX/"))
+            .map(|it| it.split_once('/').map(|it| it.0));
+        let root = match roots.next() {
+            Some(Some(first)) if roots.all(|it| it == Some(first)) => format!("{first}/"),
+            _ => String::new(),
+        };
         Ok(Self(Arc::new(Mutex::new(zip)), root))
     }
 }
@@ -161,7 +165,8 @@ impl FileSystem for ZipFileSystem {
             .lock()
             .unwrap()
             .file_names()
-            .filter(|it| it.strip_prefix(&self.1).map_or(false, |it| !it.contains('/')))
+            .filter_map(|it| it.strip_prefix(&self.1))
+            .filter(|it| !it.is_empty() && !it.contains('/'))
             .map(str::to_owned)
             .collect())
     }
@@ -391,11 +396,23 @@ fn bytes_to_text_auto(data: &[u8]) -> String {
     s.into_owned()
 }
 
+/// Loads the font specified by the chart, if any. Failing to load it is only warned about, leaving the default font in use.
+pub async fn load_chart_font(fs: &mut dyn FileSystem, info: &ChartInfo) -> Option<FontArc> {
+    let path = info.font.as_ref()?;
+    match fs.load_file(path).await.and_then(|bytes| Ok(FontArc::try_from_vec(bytes)?)) {
+        Ok(font) => Some(font),
+        Err(err) => {
+            warn!("Failed to load font {path} of the chart: {err:?}");
+            None
+        }
+    }
+}
+
 pub async fn load_info(fs: &mut dyn FileSystem) -> Result<ChartInfo> {
-    let info = if let Ok(bytes) = fs.load_file(":info").await {
-        serde_yaml::from_str(&bytes_to_text_auto(&bytes))?
+    let info: ChartInfo = if let Ok(bytes) = fs.load_file(":info").await {
+        serde_yaml::from_str(&bytes_to_text_auto(&bytes)).context("Invalid info.yml")?
     } else if let Ok(bytes) = fs.load_file("info.yml").await {
-        serde_yaml::from_str(&bytes_to_text_auto(&bytes))?
+        serde_yaml::from_str(&bytes_to_text_auto(&bytes)).context("Invalid info.yml")?
     } else if let Ok(bytes) = fs.load_file("info.txt").await {
         info_from_txt(&bytes_to_text_auto(&bytes))?
     } else if let Ok(bytes) = fs.load_file("info.csv").await {
@@ -406,6 +423,7 @@ pub async fn load_info(fs: &mut dyn FileSystem) -> Result<ChartInfo> {
         fix_info(fs, &mut info).await?;
         info
     };
+    info.validate()?;
     Ok(info)
 }
 
@@ -1,6 +1,8 @@
-use serde::{Deserialize, Serialize};
+use crate::config::JudgeWindows;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 #[repr(u8)]
 #[serde(rename_all = "lowercase")]
 pub enum ChartFormat {
@@ -8,6 +10,15 @@ pub enum ChartFormat {
     Pec,
     Pgr,
     Pbc,
+    Osu,
+    /// Detected from the chart content, see [`crate::parse::detect_format`]
+    #[default]
+    Auto,
+}
+
+/// Older versions wrote `format: null` for charts of unknown format, which is taken as [`ChartFormat::Auto`]
+fn deserialize_format<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<ChartFormat, D::Error> {
+    Ok(Option::<ChartFormat>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -24,7 +35,8 @@ pub struct ChartInfo {
     pub illustrator: String,
 
     pub chart: String,
-    pub format: Option<ChartFormat>,
+    #[serde(deserialize_with = "deserialize_format")]
+    pub format: ChartFormat,
     pub music: String,
     pub illustration: String,
 
@@ -38,8 +50,12 @@ pub struct ChartInfo {
     pub tags: Vec<String>,
 
     pub intro: String,
+    /// Font file for texts of the chart, such as its name, in place of the default font
+    pub font: Option<String>,
 
     pub hold_partial_cover: bool,
+    /// Overrides the judge windows in the config
+    pub judge_windows: Option<JudgeWindows>,
 }
 
 impl Default for ChartInfo {
@@ -55,7 +71,7 @@ impl Default for ChartInfo {
             illustrator: "UK".to_string(),
 
             chart: "chart.json".to_string(),
-            format: None,
+            format: ChartFormat::Auto,
             music: "song.mp3".to_string(),
             illustration: "background.png".to_string(),
 
@@ -69,8 +85,56 @@ impl Default for ChartInfo {
             tags: Vec::new(),
 
             intro: String::new(),
+            font: None,
 
             hold_partial_cover: false,
+            judge_windows: None,
+        }
+    }
+}
+
+impl ChartInfo {
+    /// Checks fields that can't be given a sensible default, naming the offending one
+    pub fn validate(&self) -> Result<()> {
+        for (field, value) in [("chart", &self.chart), ("music", &self.music), ("illustration", &self.illustration)] {
+            if value.trim().is_empty() {
+                bail!("field `{field}` is required in info.yml");
+            }
+        }
+        if !(self.aspect_ratio.is_finite() && self.aspect_ratio > 0.) {
+            bail!("field `aspectRatio` in info.yml should be a positive number, got {}", self.aspect_ratio);
+        }
+        if !(0. ..=1.).contains(&self.background_dim) {
+            bail!("field `backgroundDim` in info.yml should be between 0 and 1, got {}", self.background_dim);
         }
+        if !(self.line_length.is_finite() && self.line_length > 0.) {
+            bail!("field `lineLength` in info.yml should be a positive number, got {}", self.line_length);
+        }
+        if let Some(windows) = &self.judge_windows {
+            windows.validate().context("field `judgeWindows` in info.yml is invalid")?;
+        }
+        if let Some(end) = self.preview_end {
+            if end <= self.preview_start {
+                bail!("field `previewEnd` ({end}) in info.yml should be greater than `previewStart` ({})", self.preview_start);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_of(yaml: &str) -> ChartFormat {
+        serde_yaml::from_str::<ChartInfo>(yaml).unwrap().format
+    }
+
+    #[test]
+    fn null_format() {
+        assert!(matches!(format_of("name: Test\nformat: null\n"), ChartFormat::Auto));
+        assert!(matches!(format_of("name: Test\nformat: ~\n"), ChartFormat::Auto));
+        assert!(matches!(format_of("name: Test\n"), ChartFormat::Auto));
+        assert!(matches!(format_of("name: Test\nformat: pec\n"), ChartFormat::Pec));
     }
 }
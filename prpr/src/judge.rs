@@ -1,8 +1,9 @@
 use crate::{
-    config::Config,
-    core::{BadNote, Chart, NoteKind, Point, Resource, Vector, NOTE_WIDTH_RATIO_BASE},
-    ext::{get_viewport, NotNanExt},
+    config::{Config, JudgeWindows, ScoreMode},
+    core::{BadNote, Chart, Matrix, Note, NoteKind, Point, Resource, Vector, NOTE_WIDTH_RATIO_BASE},
+    ext::{get_viewport, vibrate, NotNanExt},
 };
+use anyhow::{anyhow, bail, Result};
 use macroquad::prelude::{
     utils::{register_input_subscriber, repeat_all_miniquad_input},
     *,
@@ -11,19 +12,54 @@ use miniquad::{EventHandler, MouseButton};
 use once_cell::sync::Lazy;
 use sasa::{PlaySfxParams, Sfx};
 use serde::Serialize;
-use std::{cell::RefCell, collections::HashMap, num::FpCategory};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    num::FpCategory,
+};
 
-pub const FLICK_SPEED_THRESHOLD: f32 = 0.8;
-pub const LIMIT_PERFECT: f32 = 0.08;
-pub const LIMIT_GOOD: f32 = 0.16;
-pub const LIMIT_BAD: f32 = 0.22;
+/// Default minimal flick speed, in screen-local units (the screen spans 2 units horizontally) per second
+pub const FLICK_SPEED_THRESHOLD: f32 = 1.15;
+/// Number of recent samples a flick's velocity is measured over
+const FLICK_SAMPLES: usize = 4;
 pub const UP_TOLERANCE: f32 = 0.05;
+/// Releasing a hold after this portion of it has passed is judged as Good instead of Miss
+pub const HOLD_RELEASE_GOOD_RATIO: f32 = 0.8;
 pub const DIST_FACTOR: f32 = 0.2;
 
 const EARLY_OFFSET: f32 = 0.07;
+/// Two key presses within this interval make a flick in keyboard play
+const KEY_FLICK_INTERVAL: f32 = 0.08;
+/// Notes closer in time than this are told apart by distance when allocating touches
+const TIME_TIE_EPS: f32 = 0.01;
+/// How long a touch marker of autoplay lasts after the hit, in seconds
+const AUTOPLAY_TOUCH_DURATION: f32 = 0.2;
+const AUTOPLAY_TOUCH_RADIUS: f32 = 0.04;
+/// Number of ticks of a note, one every `interval` (in chart time) after the head of a hold and before its end. Only holds have ticks,
+/// and none of them do if `interval` isn't positive.
+fn hold_tick_count(note: &Note, interval: f32) -> u32 {
+    match note.kind {
+        NoteKind::Hold { end_time, .. } if interval > 0. && !note.fake => (((end_time - note.time) / interval).ceil() as u32).saturating_sub(1),
+        _ => 0,
+    }
+}
+
+/// Offset in [-1, 1) of the autoplay hit of a note, made up from its position so that autoplay runs are reproducible
+fn autoplay_offset(line_id: usize, id: u32) -> f32 {
+    // splitmix64
+    let mut x = ((line_id as u64) << 32 | id as u64).wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^= x >> 31;
+    (x >> 40) as f32 / (1 << 23) as f32 - 1.
+}
+
+/// Length of the vibration when combo breaks, in milliseconds
+const HAPTIC_PULSE_MS: i64 = 40;
 
 pub fn play_sfx(sfx: &mut Sfx, config: &Config) {
-    if config.volume_sfx <= 1e-2 {
+    if config.volume_sfx <= 1e-2 || (config.autoplay && config.mute_autoplay_sfx) {
         return;
     }
     let _ = sfx.play(PlaySfxParams {
@@ -31,6 +67,73 @@ pub fn play_sfx(sfx: &mut Sfx, config: &Config) {
     });
 }
 
+/// What [`Judge::update`] takes from its surroundings: the chart time, the config and the aspect ratio, along with where the feedback
+/// of judgements goes. [`Resource`] is the one used in game, while tests judge without any graphics or audio.
+pub trait JudgeEnv {
+    fn config(&self) -> &Config;
+
+    /// Current chart time
+    fn time(&self) -> f32;
+
+    fn aspect_ratio(&self) -> f32;
+
+    /// Plays the hitsound of a note, which is either its custom one or the default one of its kind
+    fn play_note_sfx(&mut self, kind: &NoteKind, hitsound: Option<&str>);
+
+    /// Plays the sound of a combo break
+    fn play_miss_sfx(&mut self);
+
+    /// Emits hit particles of a Perfect or Good judgement at the origin of `model`
+    fn emit_hit(&mut self, model: Matrix, rotation: f32, what: Judgement);
+
+    /// Where the origin of `model` is on the screen
+    fn to_screen(&self, model: Matrix) -> Point;
+}
+
+impl JudgeEnv for Resource {
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn time(&self) -> f32 {
+        self.time
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
+    fn play_note_sfx(&mut self, kind: &NoteKind, hitsound: Option<&str>) {
+        if let Some(sfx) = hitsound.and_then(|it| self.hitsounds.get_mut(it)) {
+            play_sfx(sfx, &self.config);
+            return;
+        }
+        let sfx = match kind {
+            NoteKind::Click | NoteKind::Hold { .. } => &mut self.sfx_click,
+            NoteKind::Drag => &mut self.sfx_drag,
+            NoteKind::Flick => &mut self.sfx_flick,
+        };
+        play_sfx(sfx, &self.config);
+    }
+
+    fn play_miss_sfx(&mut self) {
+        play_sfx(&mut self.sfx_miss, &self.config);
+    }
+
+    fn emit_hit(&mut self, model: Matrix, rotation: f32, what: Judgement) {
+        let color = if matches!(what, Judgement::Perfect) {
+            self.res_pack.info.fx_perfect()
+        } else {
+            self.res_pack.info.fx_good()
+        };
+        self.with_model(model, |res| res.emit_at_origin(rotation, color));
+    }
+
+    fn to_screen(&self, model: Matrix) -> Point {
+        (self.model_stack.last().unwrap() * model).transform_point(&Point::default())
+    }
+}
+
 #[cfg(all(not(target_os = "windows"), not(target_os = "ios")))]
 fn get_uptime() -> f64 {
     let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
@@ -45,51 +148,64 @@ fn get_uptime() -> f64 {
     msg_send![msg_send![class(ProcessInfo), proecssInfo], systemUptime]
 }
 
+/// Recognizes flicks of a single touch. The velocity is measured over the last few timestamped samples instead of between frames, so that
+/// the sensitivity doesn't depend on the frame rate.
 pub struct FlickTracker {
+    /// Minimal flick speed, in screen-local units per second
     threshold: f32,
-    last_point: Point,
-    last_delta: Option<Vector>,
-    last_time: f32,
+    /// Minimal travel of a stroke to make a flick, in screen-local units
+    min_distance: f32,
+    samples: VecDeque<(f32, Point)>,
+    /// Where the current stroke starts
+    anchor: Point,
+    /// Direction of the current stroke, if it's moving
+    direction: Option<Vector>,
     flicked: bool,
-    stopped: bool,
+    /// Whether a new flick can be made, which takes slowing down or turning back after the last one
+    armed: bool,
 }
 
 impl FlickTracker {
-    pub fn new(_dpi: u32, time: f32, point: Point) -> Self {
-        // TODO maybe a better approach?
-        let dpi = 275;
+    /// `speed` is the minimal flick speed, see [`FLICK_SPEED_THRESHOLD`]
+    pub fn new(speed: f32, min_distance: f32, time: f32, point: Point) -> Self {
         Self {
-            threshold: FLICK_SPEED_THRESHOLD * dpi as f32 / 386.,
-            last_point: point,
-            last_delta: None,
-            last_time: time,
+            threshold: speed,
+            min_distance,
+            samples: VecDeque::from([(time, point)]),
+            anchor: point,
+            direction: None,
             flicked: false,
-            stopped: true,
+            armed: true,
         }
     }
 
     pub fn push(&mut self, time: f32, position: Point) {
-        let delta = position - self.last_point;
-        self.last_point = position;
-        if let Some(last_delta) = &self.last_delta {
-            let dt = time - self.last_time;
-            let speed = delta.dot(last_delta) / dt;
-            if speed < self.threshold {
-                self.stopped = true;
-            }
-            if self.stopped && !self.flicked {
-                self.flicked = delta.magnitude() / dt >= self.threshold * 2.;
+        if self.samples.back().map_or(false, |it| time <= it.0) {
+            // no time passed, only the position gets updated
+            self.samples.back_mut().unwrap().1 = position;
+        } else {
+            self.samples.push_back((time, position));
+            if self.samples.len() > FLICK_SAMPLES {
+                self.samples.pop_front();
             }
-            // if speed < self.threshold || self.stopped {
-            // self.stopped = delta.magnitude() / dt < self.threshold * 5.;
-            // self.flicked = self.threshold <= speed;
-            // if self.flicked {
-            // warn!("new flick!");
-            // }
-            // }
         }
-        self.last_delta = Some(delta.normalize());
-        self.last_time = time;
+        let (&(t0, p0), &(t1, p1)) = (self.samples.front().unwrap(), self.samples.back().unwrap());
+        if t1 <= t0 {
+            return;
+        }
+        let velocity = (p1 - p0) / (t1 - t0);
+        let speed = velocity.magnitude();
+        let turned = self.direction.map_or(false, |it| velocity.dot(&it) < 0.);
+        if speed < self.threshold / 2. || turned {
+            // a new stroke starts from here
+            self.armed = true;
+            self.anchor = p1;
+        }
+        self.direction = if speed > 0. { Some(velocity / speed) } else { None };
+        if self.armed && !self.flicked && speed >= self.threshold && (p1 - self.anchor).magnitude() >= self.min_distance {
+            self.flicked = true;
+            self.armed = false;
+        }
     }
 }
 
@@ -110,6 +226,16 @@ pub enum Judgement {
     Miss,
 }
 
+/// Full combo state of a run, see [`Judge::fc_state`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum FcState {
+    /// Every judgement is a Perfect
+    AllPerfect,
+    /// Every judgement is a Perfect or Good
+    FullCombo,
+    None,
+}
+
 #[cfg(not(feature = "closed"))]
 #[derive(Default)]
 pub(crate) struct JudgeInner {
@@ -161,6 +287,9 @@ impl JudgeInner {
     }
 
     pub fn accuracy(&self) -> f64 {
+        if self.num_of_notes == 0 {
+            return 1.;
+        }
         (self.counts[0] as f64 + self.counts[1] as f64 * 0.65) / self.num_of_notes as f64
     }
 
@@ -172,20 +301,11 @@ impl JudgeInner {
         (self.counts[0] as f64 + self.counts[1] as f64 * 0.65) / cnt as f64
     }
 
-    pub fn score(&self) -> u32 {
-        const TOTAL: u32 = 1000000;
-        if self.counts[0] == self.num_of_notes {
-            TOTAL
-        } else {
-            let score = (0.9 * self.accuracy() + self.max_combo as f64 / self.num_of_notes as f64 * 0.1) * TOTAL as f64;
-            score.round() as u32
-        }
-    }
-
+    /// Counts of the run, where the score is left to [`Judge::result`] as it depends on the [`ScoreMode`]
     pub fn result(&self) -> PlayResult {
         let early = self.diffs.iter().filter(|it| **it < 0.).count() as u32;
         PlayResult {
-            score: self.score(),
+            score: 0,
             accuracy: self.accuracy(),
             max_combo: self.max_combo,
             num_of_notes: self.num_of_notes,
@@ -199,6 +319,10 @@ impl JudgeInner {
         self.combo
     }
 
+    pub fn max_combo(&self) -> u32 {
+        self.max_combo
+    }
+
     pub fn counts(&self) -> [u32; 4] {
         self.counts
     }
@@ -209,56 +333,424 @@ mod inner;
 #[cfg(feature = "closed")]
 use inner::*;
 
+/// Notes of a judge line to be judged, sorted by time. Those before the head are all judged, while those after it can be in any state,
+/// e.g. behind a hold being held.
+pub struct NoteQueue {
+    ids: Vec<u32>,
+    head: usize,
+}
+
+impl NoteQueue {
+    fn new(notes: &[Note]) -> Self {
+        let mut ids: Vec<u32> = (0..(notes.len() as u32)).filter(|it| !notes[*it as usize].fake).collect();
+        ids.sort_by_key(|id| notes[*id as usize].time.not_nan());
+        Self { ids, head: 0 }
+    }
+
+    /// Notes from the head on
+    pub fn pending(&self) -> &[u32] {
+        &self.ids[self.head..]
+    }
+
+    /// Pending notes with times within `[from, to]`, found by binary search
+    pub fn window(&self, notes: &[Note], from: f32, to: f32) -> &[u32] {
+        let pending = self.pending();
+        let start = pending.partition_point(|id| notes[*id as usize].time < from);
+        let end = start + pending[start..].partition_point(|id| notes[*id as usize].time <= to);
+        &pending[start..end]
+    }
+
+    /// Moves the head past the judged notes
+    fn advance(&mut self, notes: &[Note]) {
+        while self
+            .ids
+            .get(self.head)
+            .map_or(false, |id| matches!(notes[*id as usize].judge, JudgeStatus::Judged))
+        {
+            self.head += 1;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.head = 0;
+    }
+}
+
 #[repr(C)]
 pub struct Judge {
-    // notes of each line in order
-    // LinkedList::drain_filter is unstable...
-    pub notes: Vec<(Vec<u32>, usize)>,
+    /// Notes of each line to be judged
+    pub notes: Vec<NoteQueue>,
     pub trackers: HashMap<u64, FlickTracker>,
+    /// Touch holding each active hold note by (line, note), or `None` if it's held by keys. Holds without an entry can be held by any touch or key.
+    hold_owners: HashMap<(usize, u32), Option<u64>>,
     pub last_time: f32,
+    /// Signed offset (negative for early) of the last Good / Bad judgement, along with the time it happened
+    pub last_judge_diff: Option<(f32, f32)>,
+    /// Signed offsets (negative for early) of all timed hits so far, in seconds
+    offsets: Vec<f32>,
+    detailed_counts: DetailedCounts,
+    /// Judgements committed so far in order, replayed to restore the state when seeking
+    history: Vec<JudgeRecord>,
+    /// Queue of judged notes for embedders, only kept after [`Judge::enable_events`]
+    events: Option<Vec<JudgeEvent>>,
 
     key_down_count: u32,
+    /// Keys used for keyboard play, empty if it's disabled
+    keys: Vec<KeyCode>,
+    last_key_time: f32,
+
+    /// Judge windows in seconds
+    windows: JudgeWindows,
+    flick_threshold: f32,
+    flick_min_distance: f32,
+    /// Latency of input in seconds, subtracted from input times before judging
+    input_offset: f32,
+    /// Lets a touch take any nearby note instead of the closest one in time
+    permissive_touch: bool,
+    score_mode: ScoreMode,
+    /// Output latency of hitsounds in seconds. Hitsounds of notes that are sure to be hit are played this much earlier than the chart time
+    /// (`res.time`, which follows the music clock read by `get_time`) reaches them, so that they're heard right at the visual hit.
+    /// Unlike `chart.offset` this leaves both the music and judging untouched.
+    audio_latency: f32,
+    /// Notes whose hitsounds have been played ahead of their judgements
+    early_sfx: HashSet<(usize, u32)>,
+    /// Interval of hold ticks in chart time, see [`hold_tick_count`]
+    hold_tick_interval: f32,
+    /// Ticks committed so far of each hold note being judged
+    hold_ticks: HashMap<(usize, u32), u32>,
+    /// Number of judgements to be made, which are notes and hold ticks
+    num_of_notes: u32,
+    /// Where the practice run started by [`Judge::start_from`] begins and ends, along with the number of judgements in it
+    practice: Option<(f32, f32, u32)>,
+    /// Largest offset in seconds of hits made by autoplay, which are all right on time if it's zero
+    autoplay_jitter: f32,
+    /// Where autoplay hit notes recently, with the chart time of each hit, shown as touch markers
+    autoplay_touches: Vec<(Point, f32)>,
+    /// Where autoplay is holding holds in this frame
+    autoplay_holds: Vec<Point>,
+    /// Touches started in an excluded region of the input, which are ignored until they end
+    excluded_touches: HashSet<u64>,
+    /// Whether combo broke since the last feedback, so that misses in the same frame share one sound and vibration
+    combo_broken: bool,
+    /// Matches touches against all pending notes instead of those around them in time, as a reference for tests
+    #[cfg(test)]
+    full_scan: bool,
 
     pub(crate) inner: JudgeInner,
 }
 
+/// Input consumed by a single [`Judge::update`].
+#[derive(Clone, Default)]
+pub struct JudgeInput {
+    /// Current touches in viewport coordinates, with times converted to chart time
+    pub touches: Vec<Touch>,
+    /// Raw touch events of this frame in screen-local coordinates, used for flick tracking
+    pub events: Vec<Touch>,
+    pub key_delta: i32,
+    pub keys_down: u32,
+    /// Regions in viewport coordinates, e.g. the pause button, where touches starting in are left out of judging
+    pub exclusions: Vec<Rect>,
+}
+
+impl JudgeInput {
+    /// Input made up of the given touches only, for feeding scripted input into [`Judge::update`].
+    ///
+    /// Touches should be in viewport coordinates with chart times. They are also used as flick tracking events.
+    pub fn from_touches(touches: Vec<Touch>) -> Self {
+        Self {
+            events: touches.clone(),
+            touches,
+            ..Default::default()
+        }
+    }
+}
+
 static SUBSCRIBER_ID: Lazy<usize> = Lazy::new(register_input_subscriber);
 thread_local! {
-    static TOUCHES: RefCell<(Vec<Touch>, i32, u32)> = RefCell::default();
+    static TOUCHES: RefCell<(Vec<Touch>, Vec<(KeyCode, bool)>)> = RefCell::default();
+    static MINIMIZED: Cell<bool> = Cell::default();
 }
 
 impl Judge {
-    pub fn new(chart: &Chart) -> Self {
-        let notes = chart
+    pub fn new(chart: &Chart, config: &Config) -> Result<Self> {
+        if !(config.flick_threshold > 0.) {
+            bail!("Invalid flick threshold: {}, it must be positive", config.flick_threshold);
+        }
+        if !(config.flick_min_distance >= 0.) {
+            bail!("Invalid flick min distance: {}, it must not be negative", config.flick_min_distance);
+        }
+        if !(config.autoplay_jitter_ms >= 0.) {
+            bail!("Invalid autoplay jitter: {}ms, it must not be negative", config.autoplay_jitter_ms);
+        }
+        if !(config.audio_latency >= 0.) {
+            bail!("Invalid audio latency: {}ms, it must not be negative", config.audio_latency);
+        }
+        let windows = config.judge_windows;
+        windows.validate()?;
+        let keys = if config.keyboard {
+            config
+                .keyboard_keys
+                .iter()
+                .map(|it| parse_key(it).ok_or_else(|| anyhow!("Unknown key for keyboard play: {it}")))
+                .collect::<Result<_>>()?
+        } else {
+            Vec::new()
+        };
+        if !(config.hold_tick_interval >= 0.) {
+            bail!("Invalid hold tick interval: {}, it must not be negative", config.hold_tick_interval);
+        }
+        let notes = chart.lines.iter().map(|line| NoteQueue::new(&line.notes)).collect();
+        let num_of_notes = chart
             .lines
             .iter()
-            .map(|line| {
-                let mut idx: Vec<u32> = (0..(line.notes.len() as u32)).filter(|it| !line.notes[*it as usize].fake).collect();
-                idx.sort_by_key(|id| line.notes[*id as usize].time.not_nan());
-                (idx, 0)
-            })
-            .collect();
-        Self {
+            .flat_map(|it| it.notes.iter())
+            .filter(|it| !it.fake)
+            .map(|it| 1 + hold_tick_count(it, config.hold_tick_interval))
+            .sum();
+        Ok(Self {
             notes,
             trackers: HashMap::new(),
+            hold_owners: HashMap::new(),
             last_time: 0.,
+            last_judge_diff: None,
+            offsets: Vec::new(),
+            detailed_counts: DetailedCounts::default(),
+            history: Vec::new(),
+            events: None,
 
             key_down_count: 0,
+            keys,
+            last_key_time: f32::NEG_INFINITY,
 
-            inner: JudgeInner::new(chart.lines.iter().map(|it| it.notes.iter().filter(|it| !it.fake).count() as u32).sum()),
-        }
+            windows: JudgeWindows {
+                perfect: windows.perfect / 1000.,
+                good: windows.good / 1000.,
+                bad: windows.bad / 1000.,
+            },
+            flick_threshold: config.flick_threshold,
+            flick_min_distance: config.flick_min_distance,
+            input_offset: config.input_offset,
+            permissive_touch: config.permissive_touch,
+            score_mode: config.score_mode,
+            audio_latency: config.audio_latency / 1000.,
+            early_sfx: HashSet::new(),
+            hold_tick_interval: config.hold_tick_interval,
+            hold_ticks: HashMap::new(),
+            num_of_notes,
+            practice: None,
+            autoplay_jitter: config.autoplay_jitter_ms / 1000.,
+            autoplay_touches: Vec::new(),
+            autoplay_holds: Vec::new(),
+            excluded_touches: HashSet::new(),
+            combo_broken: false,
+            #[cfg(test)]
+            full_scan: false,
+
+            inner: JudgeInner::new(num_of_notes),
+        })
     }
 
     pub fn reset(&mut self) {
-        self.notes.iter_mut().for_each(|it| it.1 = 0);
+        self.notes.iter_mut().for_each(NoteQueue::reset);
         self.trackers.clear();
-        self.inner.reset();
+        self.excluded_touches.clear();
+        self.autoplay_touches.clear();
+        self.autoplay_holds.clear();
+        self.last_time = 0.;
+        self.key_down_count = 0;
+        self.hold_owners.clear();
+        self.hold_ticks.clear();
+        self.early_sfx.clear();
+        self.combo_broken = false;
+        self.last_judge_diff = None;
+        self.last_key_time = f32::NEG_INFINITY;
+        self.offsets.clear();
+        self.detailed_counts = DetailedCounts::default();
+        self.history.clear();
+        // a practice run counts only the notes in it, see `start_from`
+        self.inner = JudgeInner::new(self.practice.map_or(self.num_of_notes, |it| it.2));
+    }
+
+    /// Leaves the practice run started by [`Judge::start_from`], so that the whole chart is judged again after the next reset
+    pub fn end_practice(&mut self) {
+        self.practice = None;
+    }
+
+    /// Called when the game gets paused. Touches holding notes are gone after that, so any touch is allowed to continue holding them.
+    pub fn on_pause(&mut self) {
+        self.hold_owners.clear();
+    }
+
+    /// Brings judging to `time` after a seek. Notes finished before `time` keep their judgements, while those after it can be hit again,
+    /// with combo and score recomputed accordingly. Notes that are skipped over, including holds still going on at `time`, are judged as
+    /// Perfect in autoplay and Miss otherwise. Notes out of a practice run are left out, the same as in [`Judge::start_from`].
+    pub fn seek(&mut self, chart: &mut Chart, time: f32, autoplay: bool) {
+        let finished = |note: &Note| match note.kind {
+            NoteKind::Hold { end_time, .. } => end_time < time,
+            _ => note.time < time,
+        };
+        // restoring the state isn't news to embedders
+        let events = self.events.take();
+        let kept: Vec<_> = std::mem::take(&mut self.history)
+            .into_iter()
+            .filter(|it| finished(&chart.lines[it.note.0].notes[it.note.1 as usize]))
+            .collect();
+        self.reset();
+        chart.reset();
+        for record in kept {
+            let note = &mut chart.lines[record.note.0].notes[record.note.1 as usize];
+            note.judge = JudgeStatus::Judged;
+            if record.tick {
+                self.record_tick(record.note, record.what);
+            } else {
+                self.record(record.note, &note.kind, record.what, record.diff);
+            }
+        }
+        let (start, end) = self.practice.map_or((f32::NEG_INFINITY, f32::INFINITY), |it| (it.0, it.1));
+        let mut skipped = Vec::new();
+        for (line_id, line) in chart.lines.iter_mut().enumerate() {
+            for (id, note) in line.notes.iter_mut().enumerate() {
+                if matches!(note.judge, JudgeStatus::Judged) {
+                    continue;
+                }
+                if note.time < start || note.time >= end {
+                    note.judge = JudgeStatus::Judged;
+                    continue;
+                }
+                if note.time >= time {
+                    continue;
+                }
+                note.judge = JudgeStatus::Judged;
+                if !note.fake {
+                    skipped.push((note.time, line_id, id as u32));
+                }
+            }
+        }
+        skipped.sort_by_key(|it| it.0.not_nan());
+        for (_, line_id, id) in skipped {
+            let note = &chart.lines[line_id].notes[id as usize];
+            let kind = note.kind.clone();
+            let ticks = hold_tick_count(note, self.hold_tick_interval);
+            self.record_ticks((line_id, id), ticks, if autoplay { Judgement::Perfect } else { Judgement::Miss });
+            if autoplay {
+                self.record((line_id, id), &kind, Judgement::Perfect, 0.);
+            } else {
+                self.record((line_id, id), &kind, Judgement::Miss, 0.25);
+            }
+        }
+        // restored judgements broke no combo just now
+        self.combo_broken = false;
+        self.events = events;
+    }
+
+    /// Starts judging afresh at `time`, as if the chart began there. Notes before `time`, and those at or after `end` if given, are dropped
+    /// without being judged, so that score and combo only count the notes in between. This holds through later seeks and resets until
+    /// [`Judge::end_practice`]. Used by practice checkpoints and A/B loops.
+    pub fn start_from(&mut self, chart: &mut Chart, time: f32, end: Option<f32>) {
+        let end = end.unwrap_or(f32::INFINITY);
+        let in_run = |note: &Note| note.time >= time && note.time < end;
+        let remaining = chart
+            .lines
+            .iter()
+            .flat_map(|it| it.notes.iter())
+            .filter(|it| !it.fake && in_run(it))
+            .map(|it| 1 + hold_tick_count(it, self.hold_tick_interval))
+            .sum();
+        self.practice = Some((time, end, remaining));
+        self.reset();
+        chart.reset();
+        for note in chart.lines.iter_mut().flat_map(|it| it.notes.iter_mut()) {
+            if !in_run(note) {
+                note.judge = JudgeStatus::Judged;
+            }
+        }
+    }
+
+    /// Starts queueing judged notes, to be taken by [`Judge::drain_events`]. Nothing is queued unless this is called.
+    pub fn enable_events(&mut self) {
+        self.events.get_or_insert_with(Vec::new);
+    }
+
+    /// Takes the notes judged since the last call, in the order they're judged
+    pub fn drain_events(&mut self) -> Vec<JudgeEvent> {
+        self.events.as_mut().map(std::mem::take).unwrap_or_default()
     }
 
     pub fn commit(&mut self, what: Judgement, diff: f32) {
         self.inner.commit(what, diff);
     }
 
+    /// Commits the judgement of a note, keeping it in the history and statistics
+    fn record(&mut self, note: (usize, u32), kind: &NoteKind, what: Judgement, diff: f32) {
+        self.commit(what, diff);
+        self.detailed_counts.add(kind, what);
+        if !matches!(what, Judgement::Miss) && !matches!(kind, NoteKind::Drag | NoteKind::Flick) {
+            self.offsets.push(diff);
+        }
+        if matches!(what, Judgement::Bad | Judgement::Miss) {
+            self.combo_broken = true;
+        }
+        self.hold_ticks.remove(&note);
+        self.history.push(JudgeRecord {
+            note,
+            what,
+            diff,
+            tick: false,
+        });
+        if let Some(events) = &mut self.events {
+            events.push(JudgeEvent {
+                line: note.0,
+                note: note.1,
+                judgement: what,
+                diff,
+            });
+        }
+    }
+
+    /// Commits the next tick of a hold note. Ticks count towards combo and score like notes do, but not towards statistics of notes.
+    fn record_tick(&mut self, note: (usize, u32), what: Judgement) {
+        *self.hold_ticks.entry(note).or_default() += 1;
+        self.commit(what, 0.);
+        if matches!(what, Judgement::Bad | Judgement::Miss) {
+            self.combo_broken = true;
+        }
+        self.history.push(JudgeRecord {
+            note,
+            what,
+            diff: 0.,
+            tick: true,
+        });
+    }
+
+    /// Commits ticks of a hold note until `count` of them are done
+    fn record_ticks(&mut self, note: (usize, u32), count: u32, what: Judgement) {
+        while self.hold_ticks.get(&note).copied().unwrap_or_default() < count {
+            self.record_tick(note, what);
+        }
+    }
+
+    /// Judgement counts broken down by note kind
+    pub fn detailed_counts(&self) -> &DetailedCounts {
+        &self.detailed_counts
+    }
+
+    /// Statistics of the signed offsets of all timed hits so far. Drags, flicks and misses are not counted.
+    pub fn offset_stats(&self) -> OffsetStats {
+        let count = self.offsets.len();
+        if count == 0 {
+            return OffsetStats::default();
+        }
+        let mean = self.offsets.iter().sum::<f32>() / count as f32;
+        let variance = self.offsets.iter().map(|it| (it - mean).powi(2)).sum::<f32>() / count as f32;
+        let early = self.offsets.iter().filter(|it| **it < 0.).count() as u32;
+        OffsetStats {
+            mean,
+            std_dev: variance.sqrt(),
+            early,
+            late: count as u32 - early,
+        }
+    }
+
     #[inline]
     pub fn accuracy(&self) -> f64 {
         self.inner.accuracy()
@@ -269,32 +761,61 @@ impl Judge {
         self.inner.real_time_accuracy()
     }
 
+    /// Score computed with the configured [`ScoreMode`]
     #[inline]
     pub fn score(&self) -> u32 {
-        self.inner.score()
+        self.score_with(self.score_mode)
+    }
+
+    /// Score of the run so far under `mode`, weighing accuracy and max combo by [`ScoreMode::weights`] up to [`ScoreMode::max_score`]
+    pub fn score_with(&self, mode: ScoreMode) -> u32 {
+        let (accuracy_weight, combo_weight) = mode.weights();
+        let result = self.inner.result();
+        let max = mode.max_score();
+        if result.counts[0] == result.num_of_notes {
+            return max;
+        }
+        let score = accuracy_weight * self.accuracy() + combo_weight * result.max_combo as f64 / result.num_of_notes as f64;
+        (score * max as f64).round() as u32
+    }
+
+    /// Number of digits to display scores with
+    pub fn score_digits(&self) -> usize {
+        self.score_mode.digits()
     }
 
     pub(crate) fn on_new_frame() {
-        let mut handler = Handler(Vec::new(), 0, 0);
+        let mut handler = Handler(Vec::new(), Vec::new());
         repeat_all_miniquad_input(&mut handler, *SUBSCRIBER_ID);
         handler.finalize();
         TOUCHES.with(|it| {
-            *it.borrow_mut() = (handler.0, handler.1, handler.2);
+            *it.borrow_mut() = (handler.0, handler.1);
         });
     }
 
-    fn touch_transform() -> impl Fn(&mut Touch) {
-        let vp = get_viewport();
+    /// Keys used for keyboard play, which is disabled if this is empty
+    pub fn play_keys(&self) -> &[KeyCode] {
+        &self.keys
+    }
+
+    /// Whether the window got minimized since the last call. miniquad has no focus event, so switching away from a window that stays visible goes unnoticed.
+    pub(crate) fn take_minimized() -> bool {
+        MINIMIZED.with(|it| it.replace(false))
+    }
+
+    /// Maps screen positions into the space of the given viewport on a screen of the given height, where x spans from -1 to 1
+    fn touch_transform(vp: (i32, i32, i32, i32), screen_height: f32) -> impl Fn(&mut Touch) {
+        // viewports count from the bottom while touches count from the top, which only differ when the bars aren't even
+        let top = screen_height - (vp.1 + vp.3) as f32;
         move |touch| {
             let p = touch.position;
-            touch.position =
-                vec2((p.x - vp.0 as f32) / vp.2 as f32 * 2. - 1., ((p.y - vp.1 as f32) / vp.3 as f32 * 2. - 1.) / (vp.2 as f32 / vp.3 as f32));
+            touch.position = vec2((p.x - vp.0 as f32) / vp.2 as f32 * 2. - 1., ((p.y - top) / vp.3 as f32 * 2. - 1.) / (vp.2 as f32 / vp.3 as f32));
         }
     }
 
     pub fn get_touches() -> Vec<Touch> {
         TOUCHES.with(|it| {
-            let tr = Self::touch_transform();
+            let tr = Self::touch_transform(get_viewport(), screen_height());
             it.borrow()
                 .0
                 .iter()
@@ -307,66 +828,127 @@ impl Judge {
         })
     }
 
-    pub fn update(&mut self, res: &mut Resource, chart: &mut Chart, bad_notes: &mut Vec<BadNote>) {
-        if res.config.autoplay {
+    /// Collects this frame's live input. The result can be recorded and fed back into [`Judge::update`] later.
+    pub fn live_input(&self, res: &Resource) -> JudgeInput {
+        fn to_local(Vec2 { x, y }: Vec2) -> Vec2 {
+            vec2(x / screen_width() * 2. - 1., y / screen_height() * 2. - 1.)
+        }
+        // timestamps are left out on Windows, where they can't be related to the uptime, so that judging never depends on the platform
+        #[cfg(target_os = "windows")]
+        let chart_time = |_: f64| f64::NEG_INFINITY;
+        #[cfg(not(target_os = "windows"))]
+        let chart_time = {
+            let uptime = get_uptime();
+            let t = res.time;
+            let spd = res.config.speed;
+            // touches without a timestamp are judged at the frame time
+            move |time: f64| {
+                if time.is_infinite() {
+                    f64::NEG_INFINITY
+                } else {
+                    crate::time::TimeManager::map_timestamp(t as f64, spd as f64, uptime, time)
+                }
+            }
+        };
+        let mut touches = touches();
+        let btn = MouseButton::Left;
+        let id = button_to_id(btn);
+        let phase = if is_mouse_button_pressed(btn) {
+            Some(TouchPhase::Started)
+        } else if is_mouse_button_down(btn) {
+            Some(TouchPhase::Moved)
+        } else if is_mouse_button_released(btn) {
+            Some(TouchPhase::Ended)
+        } else {
+            None
+        };
+        if let Some(phase) = phase {
+            let p = mouse_position();
+            touches.push(Touch {
+                id,
+                phase,
+                position: vec2(p.0, p.1),
+                time: f64::NEG_INFINITY,
+            });
+        }
+        // the letterboxed playfield, independent of whatever viewport is set now
+        let tr = match res.camera.viewport {
+            Some(vp) if res.last_screen_size.1 != 0 => Self::touch_transform(vp, res.last_screen_size.1 as f32),
+            _ => Self::touch_transform(get_viewport(), screen_height()),
+        };
+        let touches = touches
+            .into_iter()
+            .map(|mut it| {
+                tr(&mut it);
+                it.time = chart_time(it.time);
+                it
+            })
+            .collect();
+        let (events, keys) = TOUCHES.with(|it| {
+            let guard = it.borrow();
+            (guard.0.clone(), guard.1.clone())
+        });
+        // only the configured keys take part in keyboard play, which is empty when it's disabled
+        let (mut key_delta, mut keys_down) = (0, 0);
+        for (key, down) in keys {
+            if !self.keys.contains(&key) {
+                continue;
+            }
+            if down {
+                key_delta += 1;
+                keys_down += 1;
+            } else {
+                key_delta -= 1;
+            }
+        }
+        let events = events
+            .into_iter()
+            .map(|mut it| {
+                it.position = to_local(it.position);
+                it.time = chart_time(it.time);
+                it
+            })
+            .collect();
+        JudgeInput {
+            touches,
+            events,
+            key_delta,
+            keys_down,
+            exclusions: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, res: &mut impl JudgeEnv, chart: &mut Chart, bad_notes: &mut Vec<BadNote>, input: JudgeInput) {
+        if res.config().autoplay {
             self.auto_play_update(res, chart);
             return;
         }
         const X_DIFF_MAX: f32 = 0.21 / (16. / 9.) * 2.;
-        let spd = res.config.speed;
+        let spd = res.config().speed;
+        let aspect_ratio = res.aspect_ratio();
+        let JudgeWindows {
+            perfect: limit_perfect,
+            good: limit_good,
+            bad: limit_bad,
+        } = self.windows;
 
-        #[cfg(not(target_os = "windows"))]
-        let uptime = get_uptime();
-
-        let t = res.time;
+        let t = res.time();
         // TODO optimize
-        let mut touches: HashMap<u64, Touch> = {
-            let mut touches = touches();
-            let btn = MouseButton::Left;
-            let id = button_to_id(btn);
-            if is_mouse_button_pressed(btn) {
-                let p = mouse_position();
-                touches.push(Touch {
-                    id,
-                    phase: TouchPhase::Started,
-                    position: vec2(p.0, p.1),
-                    time: f64::NEG_INFINITY,
-                });
-            } else if is_mouse_button_down(btn) {
-                let p = mouse_position();
-                touches.push(Touch {
-                    id,
-                    phase: TouchPhase::Moved,
-                    position: vec2(p.0, p.1),
-                    time: f64::NEG_INFINITY,
-                });
-            } else if is_mouse_button_released(btn) {
-                let p = mouse_position();
-                touches.push(Touch {
-                    id,
-                    phase: TouchPhase::Ended,
-                    position: vec2(p.0, p.1),
-                    time: f64::NEG_INFINITY,
-                });
-            }
-            let tr = Self::touch_transform();
-            touches
-                .into_iter()
-                .map(|mut it| {
-                    tr(&mut it);
-                    (it.id, it)
-                })
-                .collect()
-        };
-        let (events, keys_down) = TOUCHES.with(|it| {
-            let guard = it.borrow();
-            (guard.0.clone(), guard.2)
-        });
-        self.key_down_count = self.key_down_count.saturating_add_signed(TOUCHES.with(|it| it.borrow().1));
+        let JudgeInput {
+            touches,
+            events,
+            key_delta,
+            keys_down,
+            exclusions,
+        } = input;
+        let mut touches: HashMap<u64, Touch> = touches.into_iter().map(|it| (it.id, it)).collect();
+        self.key_down_count = self.key_down_count.saturating_add_signed(key_delta);
+        // a key pressed along with or right after another one makes a flick
+        let key_flick = keys_down >= 2 || (keys_down != 0 && (t - self.last_key_time) / spd <= KEY_FLICK_INTERVAL);
+        if keys_down != 0 {
+            self.last_key_time = t;
+        }
         {
-            fn to_local(Vec2 { x, y }: Vec2) -> Point {
-                Point::new(x / screen_width() * 2. - 1., y / screen_height() * 2. - 1.)
-            }
             let delta = (t / spd - self.last_time) as f64 / (events.len() + 1) as f64;
             let mut t = self.last_time as f64;
             for Touch {
@@ -377,11 +959,13 @@ impl Judge {
             } in events.into_iter()
             {
                 t += delta;
-                let t = t as f32;
-                let p = to_local(p);
+                // timestamps of touch events are in chart time, while mouse events have none and are spread over the frame
+                let t = if time.is_finite() { time as f32 / spd } else { t as f32 };
+                let p = Point::new(p.x, p.y);
                 match phase {
                     TouchPhase::Started => {
-                        self.trackers.insert(id, FlickTracker::new(res.dpi, t, p));
+                        self.trackers
+                            .insert(id, FlickTracker::new(self.flick_threshold, self.flick_min_distance, t, p));
                         touches
                             .entry(id)
                             .or_insert_with(|| Touch {
@@ -403,29 +987,33 @@ impl Judge {
                 }
             }
         }
-        let touches: Vec<Touch> = touches
-            .into_values()
-            .map(|mut it| {
-                it.time = if it.time.is_infinite() {
-                    f64::NEG_INFINITY
+        let mut touches: Vec<Touch> = touches.into_values().collect();
+        // keep the order stable so that replays are judged the same way
+        touches.sort_by_key(|it| it.id);
+        touches.retain(|touch| {
+            if touch.phase == TouchPhase::Started {
+                if exclusions.iter().any(|it| it.contains(touch.position)) {
+                    self.excluded_touches.insert(touch.id);
                 } else {
-                    #[cfg(target_os = "windows")]
-                    {
-                        it.time
-                    }
-                    #[cfg(not(target_os = "windows"))]
-                    {
-                        t as f64 - (uptime - it.time) * spd as f64
-                    }
-                };
-                it
-            })
-            .collect();
+                    self.excluded_touches.remove(&touch.id);
+                }
+            }
+            if !self.excluded_touches.contains(&touch.id) {
+                return true;
+            }
+            if matches!(touch.phase, TouchPhase::Ended | TouchPhase::Cancelled) {
+                self.excluded_touches.remove(&touch.id);
+            }
+            false
+        });
         // pos[line][touch]
+        // all lines are brought to now first, since the transform of a line depends on its parents
+        for line in &mut chart.lines {
+            line.object.set_time(t);
+        }
         let mut pos = Vec::<Vec<Option<Point>>>::with_capacity(chart.lines.len());
         for id in 0..pos.capacity() {
-            chart.lines[id].object.set_time(t);
-            let inv = chart.lines[id].now_transform(res, &chart.lines).try_inverse().unwrap();
+            let inv = chart.lines[id].now_transform(aspect_ratio, &chart.lines).try_inverse().unwrap();
             pos.push(
                 touches
                     .iter()
@@ -444,14 +1032,23 @@ impl Judge {
                     .collect(),
             );
         }
+        let input_offset = self.input_offset;
+        // when the input actually happened, with its latency compensated
         let time_of = |touch: &Touch| {
-            if touch.time.is_infinite() {
-                t
-            } else {
-                touch.time as f32
-            }
+            let time = if touch.time.is_infinite() { t } else { touch.time as f32 };
+            time - input_offset
         };
         let mut judgements = Vec::new();
+        // hold ticks to be committed, by the hold note and the number of its ticks done after this frame
+        let mut ticks = Vec::new();
+        // notes taken by a touch in this frame, which other touches leave alone
+        let mut claimed = HashSet::new();
+        // how far in time a touch can reach, beyond which notes are never taken
+        let reach = limit_bad + (X_DIFF_MAX / NOTE_WIDTH_RATIO_BASE - 1.).max(0.) * DIST_FACTOR;
+        #[cfg(test)]
+        let full_scan = self.full_scan;
+        #[cfg(not(test))]
+        let full_scan = false;
         // clicks & flicks
         for (id, touch) in touches.iter().enumerate() {
             let click = touch.phase == TouchPhase::Started;
@@ -461,14 +1058,25 @@ impl Judge {
                 continue;
             }
             let t = time_of(touch);
-            let mut closest = (None, X_DIFF_MAX, LIMIT_BAD, LIMIT_BAD + (X_DIFF_MAX / NOTE_WIDTH_RATIO_BASE - 1.).max(0.) * DIST_FACTOR);
-            for (line_id, ((line, pos), (idx, st))) in chart.lines.iter_mut().zip(pos.iter()).zip(self.notes.iter_mut()).enumerate() {
-                let Some(pos) = pos[id] else { continue; };
-                for id in &idx[*st..] {
+            let mut closest = (None, X_DIFF_MAX, limit_bad, reach);
+            for (line_id, ((line, pos), queue)) in chart.lines.iter_mut().zip(pos.iter()).zip(self.notes.iter()).enumerate() {
+                let Some(pos) = pos[id] else {
+                    continue;
+                };
+                // only the few notes around the touch in time are looked at
+                let candidates = if full_scan {
+                    queue.pending()
+                } else {
+                    queue.window(&line.notes, t - (limit_bad + EARLY_OFFSET) * spd, t + reach * spd)
+                };
+                for id in candidates {
                     let note = &mut line.notes[*id as usize];
                     if !matches!(note.judge, JudgeStatus::NotJudged | JudgeStatus::PreJudge) {
                         continue;
                     }
+                    if !self.permissive_touch && claimed.contains(&(line_id, *id)) {
+                        continue;
+                    }
                     if !click && matches!(note.kind, NoteKind::Click | NoteKind::Hold { .. }) {
                         continue;
                     }
@@ -485,25 +1093,32 @@ impl Judge {
                     }
                     if dt
                         > if matches!(note.kind, NoteKind::Click) {
-                            LIMIT_BAD - LIMIT_PERFECT * (dist - 0.9).max(0.)
+                            limit_bad - limit_perfect * (dist - 0.9).max(0.)
                         } else {
-                            LIMIT_GOOD
+                            limit_good
                         }
                     {
                         continue;
                     }
                     let dt = if matches!(note.kind, NoteKind::Flick | NoteKind::Drag) {
-                        dt + LIMIT_GOOD
+                        dt + limit_good
                     } else {
                         dt
                     };
                     let key = dt + (dist / NOTE_WIDTH_RATIO_BASE - 1.).max(0.) * DIST_FACTOR;
-                    if key < closest.3 {
+                    let better = if self.permissive_touch {
+                        key < closest.3
+                    } else {
+                        // closest in time first, then in distance
+                        closest.0.is_none() || dt < closest.2 - TIME_TIE_EPS || (dt <= closest.2 + TIME_TIE_EPS && dist < closest.1)
+                    };
+                    if better {
                         closest = (Some((line_id, *id)), dist, dt, key);
                     }
                 }
             }
             if let (Some((line_id, id)), _, dt, _) = closest {
+                claimed.insert((line_id, id));
                 let line = &mut chart.lines[line_id];
                 if matches!(line.notes[id as usize].kind, NoteKind::Drag) {
                     info!("reject by drag");
@@ -515,15 +1130,16 @@ impl Judge {
                     if matches!(note.kind, NoteKind::Flick) {
                         continue; // to next loop
                     }
-                    if dt <= LIMIT_GOOD || matches!(note.kind, NoteKind::Hold { .. }) {
+                    if dt <= limit_good || matches!(note.kind, NoteKind::Hold { .. }) {
                         match note.kind {
                             NoteKind::Click => {
                                 note.judge = JudgeStatus::Judged;
-                                judgements.push((if dt <= LIMIT_PERFECT { Judgement::Perfect } else { Judgement::Good }, line_id, id, Some(t)));
+                                judgements.push((if dt <= limit_perfect { Judgement::Perfect } else { Judgement::Good }, line_id, id, Some(t)));
                             }
                             NoteKind::Hold { .. } => {
-                                play_sfx(&mut res.sfx_click, &res.config);
-                                note.judge = JudgeStatus::Hold(dt <= LIMIT_PERFECT, t, t, false, f32::INFINITY);
+                                res.play_note_sfx(&note.kind, note.hitsound.as_deref());
+                                note.judge = JudgeStatus::Hold(dt <= limit_perfect, t, t, false, f32::INFINITY);
+                                self.hold_owners.insert((line_id, id), Some(touch.id));
                             }
                             _ => unreachable!(),
                         };
@@ -544,47 +1160,44 @@ impl Judge {
                 }
             }
         }
+        let mut keys_down = keys_down;
+        // keys carry no timestamp, so they're taken as pressed right now
+        let key_t = t - input_offset;
+        if key_flick {
+            if let Some((line_id, id)) = self.earliest_note(chart, |note| matches!(note.kind, NoteKind::Flick)) {
+                let note = &mut chart.lines[line_id].notes[id as usize];
+                if (key_t - note.time).abs() / spd <= limit_bad {
+                    // the flick takes up one of the key presses
+                    note.judge = JudgeStatus::PreJudge;
+                    keys_down -= 1;
+                }
+            }
+        }
         for _ in 0..keys_down {
-            // find the earliest not judged click / hold note
-            if let Some((line_id, id)) = chart
-                .lines
-                .iter()
-                .zip(self.notes.iter())
-                .enumerate()
-                .filter_map(|(line_id, (line, (idx, st)))| {
-                    idx[*st..]
-                        .iter()
-                        .cloned()
-                        .find(|id| {
-                            let note = &line.notes[*id as usize];
-                            matches!(note.judge, JudgeStatus::NotJudged) && matches!(note.kind, NoteKind::Click | NoteKind::Hold { .. })
-                        })
-                        .map(|id| (line_id, id))
-                })
-                .min_by_key(|(line_id, id)| chart.lines[*line_id].notes[*id as usize].time.not_nan())
-            {
+            if let Some((line_id, id)) = self.earliest_note(chart, |note| matches!(note.kind, NoteKind::Click | NoteKind::Hold { .. })) {
                 let note = &mut chart.lines[line_id].notes[id as usize];
-                let dt = (t - note.time).abs() / spd;
-                if dt <= if matches!(note.kind, NoteKind::Click) { LIMIT_BAD } else { LIMIT_GOOD } {
+                let dt = (key_t - note.time).abs() / spd;
+                if dt <= if matches!(note.kind, NoteKind::Click) { limit_bad } else { limit_good } {
                     match note.kind {
                         NoteKind::Click => {
                             note.judge = JudgeStatus::Judged;
                             judgements.push((
-                                if dt <= LIMIT_PERFECT {
+                                if dt <= limit_perfect {
                                     Judgement::Perfect
-                                } else if dt <= LIMIT_GOOD {
+                                } else if dt <= limit_good {
                                     Judgement::Good
                                 } else {
                                     Judgement::Bad
                                 },
                                 line_id,
                                 id,
-                                None,
+                                Some(key_t),
                             ));
                         }
                         NoteKind::Hold { .. } => {
-                            play_sfx(&mut res.sfx_click, &res.config);
-                            note.judge = JudgeStatus::Hold(dt <= LIMIT_PERFECT, t, (t - note.time) / spd, false, f32::INFINITY);
+                            res.play_note_sfx(&note.kind, note.hitsound.as_deref());
+                            note.judge = JudgeStatus::Hold(dt <= limit_perfect, key_t, key_t, false, f32::INFINITY);
+                            self.hold_owners.insert((line_id, id), None);
                         }
                         _ => unreachable!(),
                     };
@@ -593,28 +1206,50 @@ impl Judge {
                 break;
             }
         }
-        for (line_id, ((line, pos), (idx, st))) in chart.lines.iter_mut().zip(pos.iter()).zip(self.notes.iter()).enumerate() {
+        for (line_id, ((line, pos), queue)) in chart.lines.iter_mut().zip(pos.iter()).zip(self.notes.iter()).enumerate() {
             line.object.set_time(t);
-            for id in &idx[*st..] {
+            for id in queue.pending() {
                 let note = &mut line.notes[*id as usize];
                 if let NoteKind::Hold { end_time, .. } = &note.kind {
-                    if let JudgeStatus::Hold(.., ref mut pre_judge, ref mut up_time) = note.judge {
-                        if (*end_time - t) / spd <= LIMIT_BAD {
+                    if let JudgeStatus::Hold(_, _, hit_time, ref mut pre_judge, ref mut up_time) = note.judge {
+                        if (*end_time - t) / spd <= limit_bad {
                             *pre_judge = true;
                             continue;
                         }
                         let x = &mut note.object.translation.0;
                         x.set_time(t);
                         let x = x.now();
-                        if self.key_down_count == 0 && !pos.iter().any(|it| it.map_or(false, |it| (it.x - x).abs() <= X_DIFF_MAX)) {
+                        // only the touch that hit the head (or any key, if it's hit by keys) can keep holding
+                        let held = match self.hold_owners.get(&(line_id, *id)) {
+                            Some(Some(owner)) => touches.iter().zip(pos.iter()).any(|(touch, it)| {
+                                touch.id == *owner
+                                    && !matches!(touch.phase, TouchPhase::Ended | TouchPhase::Cancelled)
+                                    && it.map_or(false, |it| (it.x - x).abs() <= X_DIFF_MAX)
+                            }),
+                            Some(None) => self.key_down_count != 0,
+                            None => self.key_down_count != 0 || pos.iter().any(|it| it.map_or(false, |it| (it.x - x).abs() <= X_DIFF_MAX)),
+                        };
+                        if !held {
                             if t > *up_time + UP_TOLERANCE {
+                                let progress = (t - note.time) / (*end_time - note.time);
                                 note.judge = JudgeStatus::Judged;
-                                judgements.push((Judgement::Miss, line_id, *id, None));
+                                self.hold_owners.remove(&(line_id, *id));
+                                // ticks not held through are all missed
+                                ticks.push(((line_id, *id), hold_tick_count(note, self.hold_tick_interval), Judgement::Miss));
+                                judgements.push(if progress >= HOLD_RELEASE_GOOD_RATIO {
+                                    (Judgement::Good, line_id, *id, Some(hit_time))
+                                } else {
+                                    (Judgement::Miss, line_id, *id, None)
+                                });
                             } else if up_time.is_infinite() {
                                 *up_time = t;
                             }
                         } else {
                             *up_time = f32::INFINITY;
+                            if self.hold_tick_interval > 0. {
+                                let due = ((t - note.time) / self.hold_tick_interval).floor().max(0.) as u32;
+                                ticks.push(((line_id, *id), due.min(hold_tick_count(note, self.hold_tick_interval)), Judgement::Perfect));
+                            }
                         }
                         continue;
                     }
@@ -624,15 +1259,15 @@ impl Judge {
                 }
                 // process miss
                 let dt = (t - note.time) / spd;
-                if dt > LIMIT_BAD {
+                if dt > limit_bad {
                     note.judge = JudgeStatus::Judged;
                     judgements.push((Judgement::Miss, line_id, *id, None));
                     continue;
                 }
-                if -dt > LIMIT_BAD {
+                if -dt > limit_bad {
                     break;
                 }
-                if !matches!(note.kind, NoteKind::Drag) && (self.key_down_count == 0 || !matches!(note.kind, NoteKind::Flick)) {
+                if !matches!(note.kind, NoteKind::Drag) {
                     continue;
                 }
                 let dt = dt.abs();
@@ -643,7 +1278,7 @@ impl Judge {
                     || pos.iter().any(|it| {
                         it.map_or(false, |it| {
                             let dx = (it.x - x).abs();
-                            dx <= X_DIFF_MAX && dt <= (LIMIT_BAD - LIMIT_PERFECT * (dx - 0.9).max(0.))
+                            dx <= X_DIFF_MAX && dt <= (limit_bad - limit_perfect * (dx - 0.9).max(0.))
                         })
                     })
                 {
@@ -652,21 +1287,29 @@ impl Judge {
             }
         }
         // process pre-judge
-        for (line_id, (line, (idx, st))) in chart.lines.iter_mut().zip(self.notes.iter()).enumerate() {
+        for (line_id, (line, queue)) in chart.lines.iter_mut().zip(self.notes.iter()).enumerate() {
             line.object.set_time(t);
-            for id in &idx[*st..] {
+            // notes after this are neither due nor finishing
+            let candidates = if full_scan {
+                queue.pending()
+            } else {
+                queue.window(&line.notes, f32::NEG_INFINITY, t + limit_good)
+            };
+            for id in candidates {
                 let note = &mut line.notes[*id as usize];
                 if let JudgeStatus::Hold(perfect, .., diff, true, _) = note.judge {
                     if let NoteKind::Hold { end_time, .. } = &note.kind {
                         if *end_time <= t {
                             note.judge = JudgeStatus::Judged;
+                            self.hold_owners.remove(&(line_id, *id));
+                            ticks.push(((line_id, *id), hold_tick_count(note, self.hold_tick_interval), Judgement::Perfect));
                             judgements.push((if perfect { Judgement::Perfect } else { Judgement::Good }, line_id, *id, Some(diff)));
                             continue;
                         }
                     }
                 }
                 // TODO adjust
-                let ghost_t = t + LIMIT_GOOD;
+                let ghost_t = t + limit_good;
                 if matches!(note.kind, NoteKind::Click) {
                     if ghost_t < note.time {
                         break;
@@ -687,6 +1330,9 @@ impl Judge {
                 }
             }
         }
+        for (note, count, what) in ticks {
+            self.record_ticks(note, count, what);
+        }
         for (judgement, line_id, id, diff) in judgements {
             let line = &mut chart.lines[line_id];
             let note = &mut line.notes[id as usize];
@@ -694,27 +1340,24 @@ impl Judge {
             note.object.set_time(t);
             let line = &chart.lines[line_id];
             let note = &line.notes[id as usize];
-            let line_tr = line.now_transform(res, &chart.lines);
-            self.commit(
-                judgement,
-                if matches!(judgement, Judgement::Miss) {
-                    0.25
-                } else if matches!(note.kind, NoteKind::Drag | NoteKind::Flick) {
-                    0.
-                } else {
-                    (diff.unwrap_or(t) - note.time) / spd
-                },
-            );
+            let line_tr = line.now_transform(aspect_ratio, &chart.lines);
+            let diff = if matches!(judgement, Judgement::Miss) {
+                0.25
+            } else if matches!(note.kind, NoteKind::Drag | NoteKind::Flick) {
+                0.
+            } else {
+                (diff.unwrap_or(t) - note.time) / spd
+            };
+            self.record((line_id, id), &note.kind, judgement, diff);
+            if matches!(judgement, Judgement::Good | Judgement::Bad) {
+                self.last_judge_diff = Some((diff, t));
+            }
             if matches!(note.kind, NoteKind::Hold { .. }) {
                 continue;
             }
             if match judgement {
-                Judgement::Perfect => {
-                    res.with_model(line_tr * note.object.now(res), |res| res.emit_at_origin(note.rotation(line), res.res_pack.info.fx_perfect()));
-                    true
-                }
-                Judgement::Good => {
-                    res.with_model(line_tr * note.object.now(res), |res| res.emit_at_origin(note.rotation(line), res.res_pack.info.fx_good()));
+                Judgement::Perfect | Judgement::Good => {
+                    res.emit_hit(line_tr * note.object.now(aspect_ratio), note.rotation(line), judgement);
                     true
                 }
                 Judgement::Bad => {
@@ -729,9 +1372,9 @@ impl Judge {
                                 }
                                 let incline_sin = line.incline.now_opt().map(|it| it.to_radians().sin()).unwrap_or_default();
                                 mat *= note.now_transform(
-                                    res,
+                                    aspect_ratio,
                                     &line.ctrl_obj.borrow_mut(),
-                                    (note.height - line.height.now()) / res.aspect_ratio * note.speed,
+                                    (note.height - line.height.now()) / aspect_ratio * note.speed * res.config().flow_speed,
                                     incline_sin,
                                 );
                                 mat
@@ -741,93 +1384,211 @@ impl Judge {
                     false
                 }
                 _ => false,
-            } {
-                if let Some(sfx) = match note.kind {
-                    NoteKind::Click => Some(&mut res.sfx_click),
-                    NoteKind::Drag => Some(&mut res.sfx_drag),
-                    NoteKind::Flick => Some(&mut res.sfx_flick),
-                    _ => None,
-                } {
-                    play_sfx(sfx, &res.config);
-                }
-            }
-        }
-        for (line, (idx, st)) in chart.lines.iter().zip(self.notes.iter_mut()) {
-            while idx
-                .get(*st)
-                .map_or(false, |id| matches!(line.notes[*id as usize].judge, JudgeStatus::Judged))
+            } && !self.early_sfx.remove(&(line_id, id))
             {
-                *st += 1;
+                res.play_note_sfx(&note.kind, note.hitsound.as_deref());
             }
         }
+        for (line, queue) in chart.lines.iter().zip(self.notes.iter_mut()) {
+            queue.advance(&line.notes);
+        }
+        self.play_early_sfx(res, chart, false);
+        self.combo_feedback(res);
         self.last_time = t / spd;
     }
 
-    fn auto_play_update(&mut self, res: &mut Resource, chart: &mut Chart) {
-        let t = res.time;
-        let spd = res.config.speed;
+    /// Plays the miss sound and vibrates if combo broke during this frame, as enabled by `miss_sound` and `haptics`
+    fn combo_feedback(&mut self, res: &mut impl JudgeEnv) {
+        if !std::mem::take(&mut self.combo_broken) {
+            return;
+        }
+        if res.config().miss_sound {
+            res.play_miss_sfx();
+        }
+        if res.config().haptics {
+            vibrate(HAPTIC_PULSE_MS);
+        }
+    }
+
+    /// Plays hitsounds of upcoming notes up to `audio_latency` ahead of time. During autoplay every note qualifies, otherwise
+    /// only drags and flicks that are already pre-judged, since they're bound to be hit right at their time. Hits made by the player
+    /// can't be foreseen, so their hitsounds are still played as they happen.
+    fn play_early_sfx(&mut self, res: &mut impl JudgeEnv, chart: &Chart, autoplay: bool) {
+        if self.audio_latency <= 0. {
+            return;
+        }
+        let until = res.time() + self.audio_latency * res.config().speed;
+        for (line_id, (line, queue)) in chart.lines.iter().zip(self.notes.iter()).enumerate() {
+            for id in queue.pending() {
+                let note = &line.notes[*id as usize];
+                if note.time > until {
+                    break;
+                }
+                let due = if autoplay {
+                    matches!(note.judge, JudgeStatus::NotJudged)
+                } else {
+                    matches!(note.judge, JudgeStatus::PreJudge) && matches!(note.kind, NoteKind::Drag | NoteKind::Flick)
+                };
+                if due && self.early_sfx.insert((line_id, *id)) {
+                    res.play_note_sfx(&note.kind, note.hitsound.as_deref());
+                }
+            }
+        }
+    }
+
+    /// The earliest not judged note matching the given predicate, regardless of its position
+    fn earliest_note(&self, chart: &Chart, pred: impl Fn(&Note) -> bool) -> Option<(usize, u32)> {
+        chart
+            .lines
+            .iter()
+            .zip(self.notes.iter())
+            .enumerate()
+            .filter_map(|(line_id, (line, queue))| {
+                queue
+                    .pending()
+                    .iter()
+                    .cloned()
+                    .find(|id| {
+                        let note = &line.notes[*id as usize];
+                        matches!(note.judge, JudgeStatus::NotJudged) && pred(note)
+                    })
+                    .map(|id| (line_id, id))
+            })
+            .min_by_key(|(line_id, id)| chart.lines[*line_id].notes[*id as usize].time.not_nan())
+    }
+
+    fn auto_play_update(&mut self, res: &mut impl JudgeEnv, chart: &mut Chart) {
+        let t = res.time();
+        let spd = res.config().speed;
+        let aspect_ratio = res.aspect_ratio();
+        let jitter = self.autoplay_jitter;
+        let markers = res.config().autoplay_touches;
+        let duration = AUTOPLAY_TOUCH_DURATION * spd;
+        self.autoplay_touches.retain(|(_, time)| (0. ..=duration).contains(&(t - time)));
+        self.autoplay_holds.clear();
         let mut judgements = Vec::new();
-        for (line_id, (line, (idx, st))) in chart.lines.iter_mut().zip(self.notes.iter_mut()).enumerate() {
-            for id in &idx[*st..] {
+        let mut ticks = Vec::new();
+        // holds being held after this frame
+        let mut held = Vec::new();
+        for (line_id, (line, queue)) in chart.lines.iter_mut().zip(self.notes.iter_mut()).enumerate() {
+            for id in queue.pending() {
                 let note = &mut line.notes[*id as usize];
-                if let JudgeStatus::Hold(..) = note.judge {
+                if let JudgeStatus::Hold(perfect, _, diff, ..) = note.judge {
                     if let NoteKind::Hold { end_time, .. } = note.kind {
+                        let count = hold_tick_count(note, self.hold_tick_interval);
                         if t >= end_time {
                             note.judge = JudgeStatus::Judged;
-                            judgements.push((line_id, *id));
+                            ticks.push(((line_id, *id), count));
+                            judgements.push((line_id, *id, if perfect { Judgement::Perfect } else { Judgement::Good }, diff));
                             continue;
                         }
+                        if self.hold_tick_interval > 0. {
+                            ticks.push(((line_id, *id), (((t - note.time) / self.hold_tick_interval).floor().max(0.) as u32).min(count)));
+                        }
+                        held.push((line_id, *id));
                     }
                 }
                 if !matches!(note.judge, JudgeStatus::NotJudged) {
                     continue;
                 }
-                if note.time > t {
+                if note.time - jitter * spd > t {
                     break;
                 }
+                // drags and flicks are judged Perfect anyway as long as they're hit in time
+                let offset = if matches!(note.kind, NoteKind::Click | NoteKind::Hold { .. }) {
+                    autoplay_offset(line_id, *id) * jitter
+                } else {
+                    0.
+                };
+                if note.time + offset * spd > t {
+                    continue;
+                }
+                let what = if offset.abs() <= self.windows.perfect {
+                    Judgement::Perfect
+                } else if offset.abs() <= self.windows.good {
+                    Judgement::Good
+                } else {
+                    Judgement::Bad
+                };
                 note.judge = if matches!(note.kind, NoteKind::Hold { .. }) {
-                    play_sfx(&mut res.sfx_click, &res.config);
-                    JudgeStatus::Hold(true, t, (t - note.time) / spd, false, f32::INFINITY)
+                    if !self.early_sfx.remove(&(line_id, *id)) {
+                        res.play_note_sfx(&note.kind, note.hitsound.as_deref());
+                    }
+                    // a hold is always held on, even if it's hit late
+                    JudgeStatus::Hold(matches!(what, Judgement::Perfect), t, offset, false, f32::INFINITY)
                 } else {
-                    judgements.push((line_id, *id));
+                    judgements.push((line_id, *id, what, offset));
                     JudgeStatus::Judged
                 };
             }
-            while idx
-                .get(*st)
-                .map_or(false, |id| matches!(line.notes[*id as usize].judge, JudgeStatus::Judged))
-            {
-                *st += 1;
+            queue.advance(&line.notes);
+        }
+        for (note, count) in ticks {
+            self.record_ticks(note, count, Judgement::Perfect);
+        }
+        if markers {
+            for (line_id, id) in held {
+                let line = &mut chart.lines[line_id];
+                let note = &mut line.notes[id as usize];
+                line.object.set_time(t);
+                note.object.set_time(t);
+                let note_transform = note.object.now(aspect_ratio);
+                let line = &chart.lines[line_id];
+                self.autoplay_holds
+                    .push(res.to_screen(line.now_transform(aspect_ratio, &chart.lines) * note_transform));
             }
         }
-        for (line_id, id) in judgements.into_iter() {
-            self.commit(Judgement::Perfect, 0.);
-            let (note_transform, note_kind) = {
+        for (line_id, id, what, diff) in judgements.into_iter() {
+            self.record((line_id, id), &chart.lines[line_id].notes[id as usize].kind, what, diff);
+            if matches!(what, Judgement::Good | Judgement::Bad) {
+                self.last_judge_diff = Some((diff, t));
+            }
+            let (note_transform, note_kind, hitsound) = {
                 let line = &mut chart.lines[line_id];
                 let note = &mut line.notes[id as usize];
                 let nt = if matches!(note.kind, NoteKind::Hold { .. }) { t } else { note.time };
                 line.object.set_time(nt);
                 note.object.set_time(nt);
-                (note.object.now(res), note.kind.clone())
+                (note.object.now(aspect_ratio), note.kind.clone(), note.hitsound.clone())
             };
             let line = &chart.lines[line_id];
-            res.with_model(line.now_transform(res, &chart.lines) * note_transform, |res| {
-                res.emit_at_origin(line.notes[id as usize].rotation(line), res.res_pack.info.fx_perfect())
-            });
-            if let Some(sfx) = match note_kind {
-                NoteKind::Click => Some(&mut res.sfx_click),
-                NoteKind::Drag => Some(&mut res.sfx_drag),
-                NoteKind::Flick => Some(&mut res.sfx_flick),
-                _ => None,
-            } {
-                play_sfx(sfx, &res.config);
+            let model = line.now_transform(aspect_ratio, &chart.lines) * note_transform;
+            if markers {
+                self.autoplay_touches.push((res.to_screen(model), t));
+            }
+            if matches!(what, Judgement::Bad) {
+                continue;
+            }
+            res.emit_hit(model, line.notes[id as usize].rotation(line), what);
+            if !matches!(note_kind, NoteKind::Hold { .. }) && !self.early_sfx.remove(&(line_id, id)) {
+                res.play_note_sfx(&note_kind, hitsound.as_deref());
             }
         }
+        self.play_early_sfx(res, chart, true);
+    }
+
+    /// Draws touch markers of autoplay if enabled, in the same space as hit particles. Hits fade out, while holds being held stay.
+    pub fn render_autoplay_touches(&self, res: &Resource) {
+        if !res.config.autoplay || !res.config.autoplay_touches {
+            return;
+        }
+        let color = Color::new(1., 1., 1., 0.5 * res.alpha);
+        for (pt, time) in &self.autoplay_touches {
+            let p = ((res.time - time) / res.config.speed / AUTOPLAY_TOUCH_DURATION).clamp(0., 1.);
+            let alpha = color.a * (1. - p);
+            draw_circle(pt.x, -pt.y, AUTOPLAY_TOUCH_RADIUS * (1. + p * 0.5), Color { a: alpha, ..color });
+        }
+        for pt in &self.autoplay_holds {
+            draw_circle(pt.x, -pt.y, AUTOPLAY_TOUCH_RADIUS, color);
+        }
     }
 
     #[inline]
     pub fn result(&self) -> PlayResult {
-        self.inner.result()
+        PlayResult {
+            score: self.score(),
+            ..self.inner.result()
+        }
     }
 
     #[inline]
@@ -835,13 +1596,42 @@ impl Judge {
         self.inner.combo()
     }
 
+    /// The best combo reached so far in this run
+    pub fn max_combo(&self) -> u32 {
+        self.inner.max_combo()
+    }
+
     #[inline]
     pub fn counts(&self) -> [u32; 4] {
         self.inner.counts()
     }
+
+    /// Full combo state of the run so far. Every judgement counts, including holds released early (Good or Miss by how far they're
+    /// held), hold ticks and Bad notes.
+    pub fn fc_state(&self) -> FcState {
+        let counts = self.counts();
+        if counts[2] + counts[3] != 0 {
+            FcState::None
+        } else if counts[1] != 0 {
+            FcState::FullCombo
+        } else {
+            FcState::AllPerfect
+        }
+    }
+
+    /// Whether no note has been judged as Bad or Miss so far
+    pub fn is_full_combo(&self) -> bool {
+        self.fc_state() != FcState::None
+    }
+
+    /// Whether every note judged so far is a Perfect
+    pub fn is_all_perfect(&self) -> bool {
+        self.fc_state() == FcState::AllPerfect
+    }
 }
 
-struct Handler(Vec<Touch>, i32, u32);
+/// Touches and key events (key, whether it's pressed) of a frame
+struct Handler(Vec<Touch>, Vec<(KeyCode, bool)>);
 impl Handler {
     fn finalize(&mut self) {
         if is_mouse_button_down(MouseButton::Left) {
@@ -868,6 +1658,9 @@ fn button_to_id(button: MouseButton) -> u64 {
 impl EventHandler for Handler {
     fn update(&mut self, _: &mut miniquad::Context) {}
     fn draw(&mut self, _: &mut miniquad::Context) {}
+    fn window_minimized_event(&mut self, _: &mut miniquad::Context) {
+        MINIMIZED.with(|it| it.set(true));
+    }
     fn touch_event(&mut self, _: &mut miniquad::Context, phase: miniquad::TouchPhase, id: u64, x: f32, y: f32, time: f64) {
         self.0.push(Touch {
             id,
@@ -895,15 +1688,14 @@ impl EventHandler for Handler {
         });
     }
 
-    fn key_down_event(&mut self, _ctx: &mut miniquad::Context, _keycode: KeyCode, _keymods: miniquad::KeyMods, repeat: bool) {
+    fn key_down_event(&mut self, _ctx: &mut miniquad::Context, keycode: KeyCode, _keymods: miniquad::KeyMods, repeat: bool) {
         if !repeat {
-            self.1 += 1;
-            self.2 += 1;
+            self.1.push((keycode, true));
         }
     }
 
-    fn key_up_event(&mut self, _ctx: &mut miniquad::Context, _keycode: KeyCode, _keymods: miniquad::KeyMods) {
-        self.1 -= 1;
+    fn key_up_event(&mut self, _ctx: &mut miniquad::Context, keycode: KeyCode, _keymods: miniquad::KeyMods) {
+        self.1.push((keycode, false));
     }
 }
 
@@ -918,7 +1710,99 @@ pub struct PlayResult {
     pub late: u32,
 }
 
-pub fn icon_index(score: u32, full_combo: bool) -> usize {
+/// Hit offset statistics, in seconds. Negative offsets are early hits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OffsetStats {
+    pub mean: f32,
+    pub std_dev: f32,
+    pub early: u32,
+    pub late: u32,
+}
+
+/// A judged note, as reported by [`Judge::drain_events`]
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct JudgeEvent {
+    /// Index of the judge line in the chart
+    pub line: usize,
+    /// Index of the note in its judge line
+    pub note: u32,
+    pub judgement: Judgement,
+    /// Signed offset in seconds, negative for early. Drags and flicks are always 0, and misses are 0.25.
+    pub diff: f32,
+}
+
+/// Judgement of a note, along with the offset it's committed with
+struct JudgeRecord {
+    note: (usize, u32),
+    what: Judgement,
+    diff: f32,
+    /// Whether this is a tick of the hold note rather than the note itself
+    tick: bool,
+}
+
+/// Judgement counts of each note kind, indexed by [`Judgement`]. Holds are counted by their final results.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DetailedCounts {
+    pub tap: [u32; 4],
+    pub drag: [u32; 4],
+    pub hold: [u32; 4],
+    pub flick: [u32; 4],
+}
+
+impl DetailedCounts {
+    fn add(&mut self, kind: &NoteKind, what: Judgement) {
+        let row = match kind {
+            NoteKind::Click => &mut self.tap,
+            NoteKind::Drag => &mut self.drag,
+            NoteKind::Hold { .. } => &mut self.hold,
+            NoteKind::Flick => &mut self.flick,
+        };
+        row[what as usize] += 1;
+    }
+}
+
+impl fmt::Display for DetailedCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<6}{:>8}{:>8}{:>8}{:>8}", "", "Perfect", "Good", "Bad", "Miss")?;
+        for (name, row) in [("Tap", &self.tap), ("Drag", &self.drag), ("Hold", &self.hold), ("Flick", &self.flick)] {
+            writeln!(f, "{name:<6}{:>8}{:>8}{:>8}{:>8}", row[0], row[1], row[2], row[3])?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the name of a key used in keyboard play: a letter, a digit, or one of `Space`, `Semicolon`, `Comma`, `Period`, `Slash`, `Apostrophe`,
+/// `LeftShift` and `RightShift`. Names are case-insensitive.
+pub fn parse_key(name: &str) -> Option<KeyCode> {
+    use KeyCode::*;
+    const LETTERS: [KeyCode; 26] = [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z];
+    const DIGITS: [KeyCode; 10] = [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    let name = name.to_ascii_lowercase();
+    if let [c] = name.as_bytes() {
+        match c {
+            b'a'..=b'z' => return Some(LETTERS[(c - b'a') as usize]),
+            b'0'..=b'9' => return Some(DIGITS[(c - b'0') as usize]),
+            _ => {}
+        }
+    }
+    Some(match name.as_str() {
+        "space" => Space,
+        "semicolon" | ";" => Semicolon,
+        "comma" | "," => Comma,
+        "period" | "." => Period,
+        "slash" | "/" => Slash,
+        "apostrophe" | "'" => Apostrophe,
+        "leftshift" => LeftShift,
+        "rightshift" => RightShift,
+        _ => return None,
+    })
+}
+
+pub const RANK_NAMES: [&str; 8] = ["F", "C", "B", "A", "S", "V", "V", "φ"];
+
+/// Rank of a score out of `max_score`, whose thresholds are set for a max of 1,000,000
+pub fn icon_index(score: u32, max_score: u32, full_combo: bool) -> usize {
+    let score = (score as u64 * 1_000_000 / max_score.max(1) as u64) as u32;
     match (score, full_combo) {
         (x, _) if x < 700000 => 0,
         (x, _) if x < 820000 => 1,
@@ -930,3 +1814,515 @@ pub fn icon_index(score: u32, full_combo: bool) -> usize {
         (_, true) => 6,
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::core::{Anim, AnimVector, BpmList, ChartExtra, ChartSettings, JudgeLine, JudgeLineCache, JudgeLineKind, Object};
+
+    /// Judging surroundings without graphics or audio, where hitsounds are counted instead of played
+    pub(crate) struct TestEnv {
+        pub config: Config,
+        pub time: f32,
+        pub sfx: u32,
+    }
+
+    impl TestEnv {
+        pub fn new(config: Config) -> Self {
+            Self { config, time: 0., sfx: 0 }
+        }
+    }
+
+    impl JudgeEnv for TestEnv {
+        fn config(&self) -> &Config {
+            &self.config
+        }
+
+        fn time(&self) -> f32 {
+            self.time
+        }
+
+        fn aspect_ratio(&self) -> f32 {
+            16. / 9.
+        }
+
+        fn play_note_sfx(&mut self, _kind: &NoteKind, _hitsound: Option<&str>) {
+            self.sfx += 1;
+        }
+
+        fn play_miss_sfx(&mut self) {}
+
+        fn emit_hit(&mut self, _model: Matrix, _rotation: f32, _what: Judgement) {}
+
+        fn to_screen(&self, model: Matrix) -> Point {
+            model.transform_point(&Point::default())
+        }
+    }
+
+    /// A note at `time` and `x` along the line
+    pub(crate) fn note(kind: NoteKind, time: f32, x: f32) -> Note {
+        Note {
+            object: Object {
+                translation: AnimVector(Anim::fixed(x), Anim::default()),
+                ..Default::default()
+            },
+            kind,
+            time,
+            height: time,
+            speed: 1.,
+            above: true,
+            multiple_hint: false,
+            fake: false,
+            visible_time: f32::INFINITY,
+            hitsound: None,
+            judge: JudgeStatus::NotJudged,
+        }
+    }
+
+    /// A line lying still across the center of the screen
+    pub(crate) fn line(mut notes: Vec<Note>) -> JudgeLine {
+        let cache = JudgeLineCache::new(&mut notes);
+        JudgeLine {
+            object: Object::default(),
+            ctrl_obj: RefCell::default(),
+            kind: JudgeLineKind::Normal,
+            height: Anim::default(),
+            incline: Anim::default(),
+            notes,
+            color: Anim::default(),
+            parent: None,
+            z_index: 0,
+            show_below: true,
+            attach_ui: None,
+            anchor: [0.5, 0.5],
+
+            cache,
+        }
+    }
+
+    pub(crate) fn chart(lines: Vec<JudgeLine>) -> Chart {
+        Chart::new(0., lines, BpmList::new(vec![(0., 60.)]), ChartSettings::default(), ChartExtra::default())
+    }
+
+    /// A touch at `x` on the center line, happening at chart time `time`
+    pub(crate) fn touch(id: u64, phase: TouchPhase, x: f32, time: f32) -> Touch {
+        Touch {
+            id,
+            phase,
+            position: vec2(x, 0.),
+            time: time as f64,
+        }
+    }
+
+    /// A touch tapping down and lifting up right away at chart time `time`
+    pub(crate) fn tap(id: u64, x: f32, time: f32) -> Vec<(f32, Vec<Touch>)> {
+        vec![
+            (time, vec![touch(id, TouchPhase::Started, x, time)]),
+            (time + 0.01, vec![touch(id, TouchPhase::Ended, x, time + 0.01)]),
+        ]
+    }
+
+    /// Feeds a single frame of touches into the judge at chart time `time`
+    pub(crate) fn step(judge: &mut Judge, env: &mut TestEnv, chart: &mut Chart, time: f32, touches: Vec<Touch>) {
+        env.time = time;
+        judge.update(env, chart, &mut Vec::new(), JudgeInput::from_touches(touches));
+    }
+
+    /// Plays scripted frames in order of time, then lets the chart run out until `end` so that every note left gets missed
+    pub(crate) fn play(judge: &mut Judge, env: &mut TestEnv, chart: &mut Chart, mut frames: Vec<(f32, Vec<Touch>)>, end: f32) {
+        frames.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (time, touches) in frames {
+            step(judge, env, chart, time, touches);
+        }
+        step(judge, env, chart, end, Vec::new());
+    }
+
+    /// Judges a chart of a single line with the scripted frames under the default config
+    pub(crate) fn judge_notes(notes: Vec<Note>, frames: Vec<(f32, Vec<Touch>)>) -> Judge {
+        let mut chart = chart(vec![line(notes)]);
+        let mut judge = Judge::new(&chart, &Config::default()).unwrap();
+        let mut env = TestEnv::new(Config::default());
+        play(&mut judge, &mut env, &mut chart, frames, 10.);
+        judge
+    }
+
+    #[test]
+    fn perfect() {
+        let judge = judge_notes(vec![note(NoteKind::Click, 1., 0.), note(NoteKind::Click, 2., 0.)], [tap(1, 0., 1.), tap(2, 0., 2.03)].concat());
+        assert_eq!(judge.counts(), [2, 0, 0, 0]);
+        assert_eq!(judge.combo(), 2);
+        assert_eq!(judge.score(), 1_000_000);
+    }
+
+    #[test]
+    fn good() {
+        // 120ms early is out of the perfect window (80ms) but within the good one (160ms)
+        let judge = judge_notes(vec![note(NoteKind::Click, 1., 0.)], tap(1, 0., 0.88));
+        assert_eq!(judge.counts(), [0, 1, 0, 0]);
+        assert_eq!(judge.combo(), 1);
+        // 90% of an accuracy of 65%, plus 10% of a full combo
+        assert_eq!(judge.score(), 685_000);
+    }
+
+    #[test]
+    fn miss() {
+        let judge = judge_notes(vec![note(NoteKind::Click, 1., 0.)], Vec::new());
+        assert_eq!(judge.counts(), [0, 0, 0, 1]);
+        assert_eq!(judge.combo(), 0);
+        assert_eq!(judge.score(), 0);
+    }
+
+    #[test]
+    fn mixed() {
+        let judge = judge_notes(
+            vec![
+                note(NoteKind::Click, 1., 0.),
+                note(NoteKind::Click, 2., 0.),
+                note(NoteKind::Click, 3., 0.),
+            ],
+            [tap(1, 0., 1.), tap(2, 0., 1.88)].concat(),
+        );
+        assert_eq!(judge.counts(), [1, 1, 0, 1]);
+        assert_eq!(judge.combo(), 0);
+        assert_eq!(judge.max_combo(), 2);
+        // accuracy is (1 + 0.65) / 3 = 55%, max combo is 2 of 3
+        assert_eq!(judge.score(), 561_667);
+    }
+
+    #[test]
+    fn double_tap() {
+        // two fingers on a single note, at once and then one after the other
+        let judge = judge_notes(
+            vec![note(NoteKind::Click, 1., 0.)],
+            vec![
+                (1., vec![touch(1, TouchPhase::Started, 0., 1.), touch(2, TouchPhase::Started, 0.02, 1.)]),
+                (1.05, vec![touch(3, TouchPhase::Started, 0., 1.05)]),
+            ],
+        );
+        assert_eq!(judge.counts(), [1, 0, 0, 0]);
+        assert_eq!(judge.combo(), 1);
+        assert_eq!(judge.score(), 1_000_000);
+    }
+
+    /// Plays the frames on a single line under the config, returning the judge and which notes are judged in the order of time
+    fn judge_cluster(config: Config, notes: Vec<Note>, frames: Vec<(f32, Vec<Touch>)>, end: f32) -> (Judge, Vec<bool>) {
+        let mut chart = chart(vec![line(notes)]);
+        let mut judge = Judge::new(&chart, &config).unwrap();
+        let mut env = TestEnv::new(config);
+        play(&mut judge, &mut env, &mut chart, frames, end);
+        let mut notes: Vec<_> = chart.lines[0].notes.iter().collect();
+        notes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let judged = notes.into_iter().map(|it| !matches!(it.judge, JudgeStatus::NotJudged)).collect();
+        (judge, judged)
+    }
+
+    #[test]
+    fn touch_claims_one_note() {
+        let cluster = || vec![note(NoteKind::Click, 1., 0.), note(NoteKind::Click, 1.01, 0.02)];
+        // a single touch takes one of the two notes, leaving the other to be missed
+        let (judge, _) = judge_cluster(Config::default(), cluster(), tap(1, 0., 1.), 10.);
+        assert_eq!(judge.counts(), [1, 0, 0, 1]);
+        // two fingers take one each
+        let frames = vec![(1., vec![touch(1, TouchPhase::Started, 0., 1.), touch(2, TouchPhase::Started, 0.02, 1.)])];
+        let (judge, _) = judge_cluster(Config::default(), cluster(), frames, 10.);
+        assert_eq!(judge.counts(), [2, 0, 0, 0]);
+        // even when both are too early, where the first note is kept around after its bad
+        let frames = vec![(0.8, vec![touch(1, TouchPhase::Started, 0., 0.8), touch(2, TouchPhase::Started, 0.02, 0.8)])];
+        let (judge, _) = judge_cluster(Config::default(), cluster(), frames.clone(), 10.);
+        assert_eq!(judge.counts(), [0, 0, 2, 0]);
+
+        // permissively, the second finger lands on the first note again, and the second note is missed
+        let permissive = Config {
+            permissive_touch: true,
+            ..Default::default()
+        };
+        let (judge, _) = judge_cluster(permissive, cluster(), frames, 10.);
+        assert_eq!(judge.counts(), [0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn permissive_touch() {
+        // the first note is right on time but off to the side, the second one is under the finger but a bit late
+        let cluster = || vec![note(NoteKind::Click, 1., 0.2), note(NoteKind::Click, 1.06, 0.)];
+        let (_, judged) = judge_cluster(Config::default(), cluster(), tap(1, 0., 1.), 1.02);
+        assert_eq!(judged, [true, false]);
+        let permissive = Config {
+            permissive_touch: true,
+            ..Default::default()
+        };
+        let (_, judged) = judge_cluster(permissive, cluster(), tap(1, 0., 1.), 1.02);
+        assert_eq!(judged, [false, true]);
+    }
+
+    #[test]
+    fn practice_seek() {
+        let mut chart = chart(vec![line((1..=5).map(|it| note(NoteKind::Click, it as f32, 0.)).collect())]);
+        let mut judge = Judge::new(&chart, &Config::default()).unwrap();
+        let mut env = TestEnv::new(Config::default());
+        judge.start_from(&mut chart, 2.5, None);
+        // skipping over a note of the practice run misses it, but not those before the run
+        judge.seek(&mut chart, 3.5, false);
+        assert_eq!(judge.counts(), [0, 0, 0, 1]);
+        // back to the checkpoint, from where a perfect run gets the full score
+        judge.seek(&mut chart, 2.5, false);
+        assert_eq!(judge.counts(), [0, 0, 0, 0]);
+        play(&mut judge, &mut env, &mut chart, [tap(1, 0., 3.), tap(2, 0., 4.), tap(3, 0., 5.)].concat(), 10.);
+        assert_eq!(judge.counts(), [3, 0, 0, 0]);
+        assert_eq!(judge.score(), 1_000_000);
+
+        // out of practice, the whole chart is judged again
+        judge.end_practice();
+        judge.reset();
+        chart.reset();
+        play(&mut judge, &mut env, &mut chart, Vec::new(), 10.);
+        assert_eq!(judge.counts(), [0, 0, 0, 5]);
+    }
+
+    #[test]
+    fn autoplay_loop() {
+        let config = Config {
+            autoplay: true,
+            ..Default::default()
+        };
+        let mut chart = chart(vec![line((1..=5).map(|it| note(NoteKind::Click, it as f32, 0.)).collect())]);
+        let mut judge = Judge::new(&chart, &config).unwrap();
+        let mut env = TestEnv::new(config);
+        // looping over the second and third notes, twice
+        for _ in 0..2 {
+            judge.start_from(&mut chart, 1.5, Some(3.5));
+            for frame in 90..210 {
+                step(&mut judge, &mut env, &mut chart, frame as f32 / 60., Vec::new());
+            }
+            assert_eq!(judge.counts(), [2, 0, 0, 0]);
+            assert_eq!(judge.score(), 1_000_000);
+        }
+    }
+
+    #[test]
+    fn fc_state_transitions() {
+        let mut chart = chart(vec![line(vec![
+            note(NoteKind::Click, 1., 0.),
+            note(NoteKind::Click, 2., 0.),
+            note(NoteKind::Click, 3., 0.),
+            note(NoteKind::Click, 4., 0.),
+        ])]);
+        let mut judge = Judge::new(&chart, &Config::default()).unwrap();
+        let mut env = TestEnv::new(Config::default());
+        assert_eq!(judge.fc_state(), FcState::AllPerfect);
+        // a Perfect, then a Good which drops AP to FC, and a Perfect after it which doesn't bring AP back
+        for ((time, touches), expected) in [tap(1, 0., 1.), tap(2, 0., 1.88), tap(3, 0., 3.)].concat().into_iter().zip([
+            FcState::AllPerfect,
+            FcState::AllPerfect,
+            FcState::FullCombo,
+            FcState::FullCombo,
+            FcState::FullCombo,
+            FcState::FullCombo,
+        ]) {
+            step(&mut judge, &mut env, &mut chart, time, touches);
+            assert_eq!(judge.fc_state(), expected, "at {time}");
+        }
+        // the last note is left to be missed
+        step(&mut judge, &mut env, &mut chart, 4.2, Vec::new());
+        assert_eq!(judge.fc_state(), FcState::FullCombo);
+        step(&mut judge, &mut env, &mut chart, 4.3, Vec::new());
+        assert_eq!(judge.fc_state(), FcState::None);
+        assert_eq!(judge.counts(), [2, 1, 0, 1]);
+    }
+
+    #[test]
+    fn hold_early_release() {
+        // a 2s hold with a tick every 0.25s, that is 7 ticks along with the hold itself
+        let config = Config {
+            hold_tick_interval: 0.25,
+            ..Default::default()
+        };
+        let hold = || {
+            note(
+                NoteKind::Hold {
+                    end_time: 3.,
+                    end_height: 3.,
+                },
+                1.,
+                0.,
+            )
+        };
+        // holding from the head at 1s, released at `release`
+        let frames = |release: f32| -> Vec<_> {
+            (60..=240)
+                .map(|frame| {
+                    let time = frame as f32 / 60.;
+                    let phase = if frame == 60 {
+                        TouchPhase::Started
+                    } else if time < release {
+                        TouchPhase::Moved
+                    } else if time - 1. / 60. < release {
+                        TouchPhase::Ended
+                    } else {
+                        return (time, Vec::new());
+                    };
+                    (time, vec![touch(1, phase, 0., time)])
+                })
+                .collect()
+        };
+
+        let (judge, _) = judge_cluster(config.clone(), vec![hold()], frames(3.5), 10.);
+        assert_eq!(judge.counts(), [8, 0, 0, 0]);
+        assert_eq!(judge.combo(), 8);
+
+        // released at 30%, after the ticks at 1.25s and 1.5s
+        let (judge, _) = judge_cluster(config, vec![hold()], frames(1.6), 10.);
+        let ticks: Vec<_> = judge.history.iter().filter(|it| it.tick).map(|it| it.what as u8).collect();
+        assert_eq!(ticks, [0, 0, 3, 3, 3, 3, 3]);
+        assert_eq!(judge.counts(), [2, 0, 0, 6]);
+        assert_eq!(judge.combo(), 0);
+        assert_eq!(judge.max_combo(), 2);
+    }
+
+    /// Whether a touch sampled at 120Hz along the points makes a flick under the default config
+    fn flicks(points: impl IntoIterator<Item = Point>) -> bool {
+        let config = Config::default();
+        let mut points = points.into_iter();
+        let mut tracker = FlickTracker::new(config.flick_threshold, config.flick_min_distance, 0., points.next().unwrap());
+        for (frame, point) in points.enumerate() {
+            tracker.push((frame + 1) as f32 / 120., point);
+            if tracker.flicked {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn flick_traces() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        // half the threshold speed for half a second, covering a long distance though
+        assert!(!flicks((0..60).map(|frame| Point::new(frame as f32 / 120. * 0.5, 0.))));
+        // three times the threshold speed for a tenth of a second
+        assert!(flicks((0..12).map(|frame| Point::new(0., frame as f32 / 120. * 3.5))));
+        // a shaky finger moves fast between samples, but never far enough
+        let mut rng = StdRng::seed_from_u64(45);
+        assert!(!flicks((0..240).map(|_| Point::new(rng.gen_range(-0.005..0.005), rng.gen_range(-0.005..0.005)))));
+    }
+
+    #[test]
+    fn autoplay_max_score() {
+        for mode in [ScoreMode::Standard, ScoreMode::Classic, ScoreMode::AccuracyWeighted] {
+            let config = Config {
+                autoplay: true,
+                score_mode: mode,
+                ..Default::default()
+            };
+            let mut chart = chart(vec![line(vec![
+                note(NoteKind::Click, 1., 0.),
+                note(NoteKind::Drag, 1.5, 0.3),
+                note(
+                    NoteKind::Hold {
+                        end_time: 3.,
+                        end_height: 3.,
+                    },
+                    2.,
+                    -0.3,
+                ),
+                note(NoteKind::Flick, 3.5, 0.),
+            ])]);
+            let mut judge = Judge::new(&chart, &config).unwrap();
+            let mut env = TestEnv::new(config);
+            for frame in 0..5 * 60 {
+                step(&mut judge, &mut env, &mut chart, frame as f32 / 60., Vec::new());
+            }
+            assert_eq!(judge.counts(), [4, 0, 0, 0], "{mode:?}");
+            assert_eq!(judge.score(), mode.max_score(), "{mode:?}");
+            assert_eq!(format!("{}", judge.score()).len(), judge.score_digits(), "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn windowed_matching() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(48);
+        let lines: Vec<_> = (0..3)
+            .map(|_| {
+                line(
+                    (0..150)
+                        .map(|_| {
+                            let time = rng.gen_range(1.0..60.0);
+                            let kind = match rng.gen_range(0..4) {
+                                0 => NoteKind::Click,
+                                1 => NoteKind::Drag,
+                                2 => NoteKind::Flick,
+                                _ => {
+                                    let end_time = time + rng.gen_range(0.2..1.5);
+                                    NoteKind::Hold {
+                                        end_time,
+                                        end_height: end_time,
+                                    }
+                                }
+                            };
+                            note(kind, time, rng.gen_range(-0.8..0.8))
+                        })
+                        .collect(),
+                )
+            })
+            .collect();
+        // touches played on most notes with some offsets, and some stray taps, as (start, end, x, speed of x)
+        let mut gestures = Vec::new();
+        for note in lines.iter().flat_map(|it| it.notes.iter()) {
+            if rng.gen_bool(0.15) {
+                continue;
+            }
+            let x = note.object.translation.0.now() + rng.gen_range(-0.1..0.1);
+            let start = note.time + rng.gen_range(-0.25..0.2);
+            gestures.push(match note.kind {
+                NoteKind::Click => (start, start + 0.03, x, 0.),
+                NoteKind::Drag => (start, start + 0.2, x, 0.),
+                NoteKind::Flick => (start, start + 0.15, x, if rng.gen() { 4. } else { -4. }),
+                NoteKind::Hold { end_time, .. } => (start, end_time + rng.gen_range(-0.3..0.1), x, 0.),
+            });
+        }
+        for _ in 0..100 {
+            let start = rng.gen_range(0.0..60.0);
+            gestures.push((start, start + 0.03, rng.gen_range(-1.0..1.0), 0.));
+        }
+        let frames: Vec<_> = (0..62 * 120)
+            .map(|frame| {
+                let time = frame as f32 / 120.;
+                let touches = gestures
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (start, end, ..))| *start <= time && time < end + 1. / 120.)
+                    .map(|(id, (start, end, x, vx))| {
+                        let phase = if time < start + 1. / 120. {
+                            TouchPhase::Started
+                        } else if time >= *end {
+                            TouchPhase::Ended
+                        } else {
+                            TouchPhase::Moved
+                        };
+                        touch(id as u64, phase, x + vx * (time - start), time)
+                    })
+                    .collect();
+                (time, touches)
+            })
+            .collect();
+
+        let mut chart = chart(lines);
+        let run = |chart: &mut Chart, full_scan: bool| {
+            chart
+                .lines
+                .iter_mut()
+                .flat_map(|it| it.notes.iter_mut())
+                .for_each(|it| it.judge = JudgeStatus::NotJudged);
+            let mut judge = Judge::new(chart, &Config::default()).unwrap();
+            judge.full_scan = full_scan;
+            play(&mut judge, &mut TestEnv::new(Config::default()), chart, frames.clone(), 65.);
+            let history: Vec<_> = judge.history.iter().map(|it| (it.note, it.what as u8, it.diff, it.tick)).collect();
+            (history, judge.counts())
+        };
+        let (expected, counts) = run(&mut chart, true);
+        // every kind of judgement shows up
+        assert!(counts.iter().all(|it| *it > 0), "{counts:?}");
+        assert_eq!(run(&mut chart, false), (expected, counts));
+    }
+}
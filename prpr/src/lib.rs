@@ -9,6 +9,8 @@ pub mod judge;
 pub mod l10n;
 pub mod parse;
 pub mod particle;
+pub mod player;
+pub mod replay;
 pub mod scene;
 pub mod task;
 pub mod time;
@@ -1,6 +1,9 @@
 mod extra;
 pub use extra::parse_extra;
 
+mod osu;
+pub use osu::parse_osu;
+
 mod pec;
 pub use pec::parse_pec;
 
@@ -8,54 +11,106 @@ mod pgr;
 pub use pgr::parse_phigros;
 
 mod rpe;
-pub use rpe::{parse_rpe, RPE_HEIGHT, RPE_WIDTH};
+pub use rpe::{parse_rpe, serialize_rpe, RPE_HEIGHT, RPE_WIDTH};
 
-pub(crate) fn process_lines(v: &mut [crate::core::JudgeLine]) {
-    use crate::ext::NotNanExt;
-    let mut times = Vec::new();
-    // TODO optimize using k-merge sort
-    let sorts = v
-        .iter()
-        .map(|line| {
-            let mut idx: Vec<usize> = (0..line.notes.len()).collect();
-            idx.sort_by_key(|id| line.notes[*id].time.not_nan());
-            idx
-        })
-        .collect::<Vec<_>>();
-    for (line, idx) in v.iter_mut().zip(sorts.iter()) {
-        let v = &mut line.notes;
-        let mut i = 0;
-        while i < v.len() {
-            times.push(v[idx[i]].time.not_nan());
-            let mut j = i + 1;
-            while j < v.len() && v[idx[j]].time == v[idx[i]].time {
-                j += 1;
-            }
-            if j != i + 1 {
-                times.push(v[idx[i]].time.not_nan());
-            }
-            i = j;
+/// Guesses the format of a textual chart. Binary charts should be checked beforehand since they are not valid UTF-8.
+pub fn detect_format(text: &str) -> crate::info::ChartFormat {
+    use crate::info::ChartFormat;
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if text.starts_with("osu file format") {
+        ChartFormat::Osu
+    } else if text.starts_with('{') {
+        if text.contains("\"META\"") || text.contains("\"BPMList\"") {
+            ChartFormat::Rpe
+        } else {
+            // pgr charts should contain `formatVersion`, but we don't reject those without it
+            ChartFormat::Pgr
         }
+    } else {
+        // PEC charts start with a bare number (the offset). Anything else is also left to the PEC parser, which reports the offending line
+        ChartFormat::Pec
     }
-    times.sort();
-    let mut mt = Vec::new();
-    if !times.is_empty() {
-        for i in 0..(times.len() - 1) {
-            // since times are generated in the same way, theoretically we can compare them directly
-            if times[i] == times[i + 1] && (i == 0 || times[i - 1] != times[i]) {
-                mt.push(*times[i]);
-            }
-        }
+}
+
+/// Options shared by the chart parsers
+#[derive(Clone, Copy, Debug)]
+pub struct ParseOptions {
+    /// Whether a malformed note or event fails the whole chart. If not, it's dropped and reported as a warning instead
+    pub strict: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self { strict: true }
     }
-    for (line, idx) in v.iter_mut().zip(sorts.iter()) {
-        let mut i = 0;
-        for id in idx {
-            let note = &mut line.notes[*id];
-            let time = note.time;
-            while i < mt.len() && mt[i] < time {
-                i += 1;
-            }
-            if i < mt.len() && mt[i] == time {
+}
+
+/// Deserializes JSON, prefixing errors with the path of the failing element, like `judgeLineList[12].notes[3]: missing field `end``
+pub(crate) fn parse_json<T: serde::de::DeserializeOwned>(source: &str) -> anyhow::Result<T> {
+    Ok(serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(source))?)
+}
+
+/// Like [`parse_json`], but in lenient mode `sanitize` gets to drop malformed entries first, reporting each of them as a warning
+pub(crate) fn parse_json_with<T: serde::de::DeserializeOwned>(
+    source: &str,
+    options: ParseOptions,
+    sanitize: impl FnOnce(&mut serde_json::Value, &mut Vec<String>),
+) -> anyhow::Result<(T, Vec<String>)> {
+    if options.strict {
+        return Ok((parse_json(source)?, Vec::new()));
+    }
+    let mut value: serde_json::Value = serde_json::from_str(source)?;
+    let mut warnings = Vec::new();
+    sanitize(&mut value, &mut warnings);
+    Ok((serde_path_to_error::deserialize(value)?, warnings))
+}
+
+/// Drops the elements of the array `parent[key]` that can't be deserialized as `T`
+pub(crate) fn retain_valid<T: serde::de::DeserializeOwned>(parent: &mut serde_json::Value, key: &str, path: &str, warnings: &mut Vec<String>) {
+    let Some(items) = parent.get_mut(key).and_then(|it| it.as_array_mut()) else {
+        return;
+    };
+    let mut index = 0;
+    items.retain(|item| {
+        let result = serde_json::from_value::<T>(item.clone());
+        if let Err(err) = &result {
+            warnings.push(format!("Skipped {path}.{key}[{index}]: {err}"));
+        }
+        index += 1;
+        result.is_ok()
+    });
+}
+
+/// Iterates over the elements of the array `parent[key]` along with their paths
+pub(crate) fn array_items<'a>(parent: &'a mut serde_json::Value, key: &str, path: &str) -> impl Iterator<Item = (String, &'a mut serde_json::Value)> {
+    let prefix = if path.is_empty() { key.to_owned() } else { format!("{path}.{key}") };
+    parent
+        .get_mut(key)
+        .and_then(|it| it.as_array_mut())
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .map(move |(id, it)| (format!("{prefix}[{id}]"), it))
+}
+
+/// Marks notes at the same time as simultaneous, for formats without exact beats. See [`process_lines_by`]
+pub(crate) fn process_lines(v: &mut [crate::core::JudgeLine]) {
+    use crate::ext::NotNanExt;
+    // since times are generated in the same way, theoretically we can compare them directly
+    let times: Vec<Vec<_>> = v.iter().map(|line| line.notes.iter().map(|it| it.time.not_nan()).collect()).collect();
+    process_lines_by(v, &times);
+}
+
+/// Marks notes sharing a key (e.g. the exact beat) with any other note, on the same line or not, as simultaneous.
+/// `keys[i][j]` is the key of `v[i].notes[j]`.
+pub(crate) fn process_lines_by<K: Ord>(v: &mut [crate::core::JudgeLine], keys: &[Vec<K>]) {
+    let mut sorted: Vec<&K> = keys.iter().flatten().collect();
+    sorted.sort();
+    let mut shared: Vec<&K> = sorted.windows(2).filter(|it| it[0] == it[1]).map(|it| it[0]).collect();
+    shared.dedup();
+    for (line, keys) in v.iter_mut().zip(keys) {
+        for (note, key) in line.notes.iter_mut().zip(keys) {
+            if shared.binary_search(&key).is_ok() {
                 note.multiple_hint = true;
             }
         }
@@ -83,3 +138,79 @@ pub const RPE_TWEEN_MAP: [crate::core::TweenId; 30] = {
         e(Bounce, InOut), e(Elastic, InOut),
     ]
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::{Beat, NoteKind},
+        info::ChartFormat,
+        judge::tests::{line, note},
+    };
+
+    #[test]
+    fn detect_formats() {
+        let rpe = r#"{"BPMList":[{"bpm":120.0,"startTime":[0,0,1]}],"META":{"RPEVersion":140,"offset":0},"judgeLineList":[]}"#;
+        let pgr = r#"{"formatVersion":3,"offset":0.0,"judgeLineList":[]}"#;
+        let pec = "0\nbp 0 120\nn1 0 1 0 1 0\n";
+        let osu = "osu file format v14\n\n[General]\nMode: 3\n";
+        assert!(matches!(detect_format(rpe), ChartFormat::Rpe));
+        assert!(matches!(detect_format(pgr), ChartFormat::Pgr));
+        assert!(matches!(detect_format(pec), ChartFormat::Pec));
+        assert!(matches!(detect_format(osu), ChartFormat::Osu));
+
+        // BOMs and leading blanks don't get in the way
+        assert!(matches!(detect_format(&format!("\u{feff}\r\n  {rpe}")), ChartFormat::Rpe));
+        assert!(matches!(detect_format(&format!("\u{feff}{osu}")), ChartFormat::Osu));
+        assert!(matches!(detect_format(&format!("\n\t{pgr}")), ChartFormat::Pgr));
+    }
+
+    #[test]
+    fn detect_ambiguous_formats() {
+        // either RPE key is enough
+        assert!(matches!(detect_format(r#"{"META":{}}"#), ChartFormat::Rpe));
+        assert!(matches!(detect_format(r#"{"BPMList":[]}"#), ChartFormat::Rpe));
+        // JSON without RPE keys is taken as pgr, even without `formatVersion`
+        assert!(matches!(detect_format("{}"), ChartFormat::Pgr));
+        assert!(matches!(detect_format(r#"{"offset":0,"judgeLineList":[]}"#), ChartFormat::Pgr));
+        // the osu header has to come first, and anything unrecognized is left to the PEC parser to report
+        assert!(matches!(detect_format("[General]\nosu file format v14"), ChartFormat::Pec));
+        assert!(matches!(detect_format(""), ChartFormat::Pec));
+        assert!(matches!(detect_format("\u{feff}"), ChartFormat::Pec));
+        assert!(matches!(detect_format("<xml/>"), ChartFormat::Pec));
+    }
+
+    #[test]
+    fn simultaneous_by_beat() {
+        // the same beat 1/3 reached through different conversions, so the times differ in the last bit
+        let (a, b) = (1. / 3., 1. / 3. + f32::EPSILON);
+        assert_ne!(a, b);
+        let mut lines = vec![
+            line(vec![note(NoteKind::Click, a, 0.), note(NoteKind::Click, 2., 0.)]),
+            line(vec![note(NoteKind::Click, b, 0.)]),
+        ];
+        process_lines_by(&mut lines, &[vec![Beat::new(1, 3), Beat::new(2, 1)], vec![Beat::new(2, 6)]]);
+        assert!(lines[0].notes[0].multiple_hint);
+        assert!(!lines[0].notes[1].multiple_hint);
+        assert!(lines[1].notes[0].multiple_hint);
+
+        // while by times they aren't
+        let mut lines = vec![line(vec![note(NoteKind::Click, a, 0.)]), line(vec![note(NoteKind::Click, b, 0.)])];
+        process_lines(&mut lines);
+        assert!(!lines[0].notes[0].multiple_hint);
+        assert!(!lines[1].notes[0].multiple_hint);
+    }
+
+    #[test]
+    fn simultaneous_on_one_line() {
+        let mut lines = vec![line(vec![
+            note(NoteKind::Click, 1., -0.5),
+            note(NoteKind::Click, 1., 0.5),
+            note(NoteKind::Drag, 1.5, 0.),
+        ])];
+        process_lines(&mut lines);
+        let hints: Vec<_> = lines[0].notes.iter().map(|it| (it.time, it.multiple_hint)).collect();
+        assert_eq!(hints.iter().filter(|it| it.1).count(), 2);
+        assert!(hints.iter().all(|(time, hint)| *hint == (*time == 1.)));
+    }
+}
@@ -1,10 +1,11 @@
+use super::parse_json;
 use crate::{
     core::{Anim, BpmList, ChartExtra, ClampedTween, Effect, Keyframe, StaticTween, Triple, Tweenable, Uniform, Video, EPS},
     ext::ScaleType,
     fs::FileSystem,
 };
-use anyhow::{anyhow, Context, Result};
-use macroquad::prelude::{Color, Vec2};
+use anyhow::{Context, Result};
+use macroquad::prelude::{warn, Color, Vec2};
 use serde::Deserialize;
 use std::{collections::HashMap, path::Path, rc::Rc};
 
@@ -143,7 +144,17 @@ struct Extra {
     videos: Vec<ExtVideo>,
 }
 
-async fn parse_effect(r: &mut BpmList, rpe: ExtEffect, fs: &mut dyn FileSystem) -> Result<Effect> {
+async fn parse_effect(r: &mut BpmList, rpe: ExtEffect, fs: &mut dyn FileSystem) -> Result<Option<Effect>> {
+    let string;
+    let shader = if let Some(path) = rpe.shader.strip_prefix('/') {
+        string = String::from_utf8(fs.load_file(path).await?).with_context(|| format!("Cannot load shader from {path}"))?;
+        &string
+    } else if let Some(preset) = Effect::get_preset(&rpe.shader) {
+        preset
+    } else {
+        warn!("Unknown preset shader {}, ignoring the effect", rpe.shader);
+        return Ok(None);
+    };
     let range = r.time(&rpe.start)..r.time(&rpe.end);
     let vars = rpe
         .vars
@@ -156,28 +167,19 @@ async fn parse_effect(r: &mut BpmList, rpe: ExtEffect, fs: &mut dyn FileSystem)
             })
         })
         .collect::<Result<_>>()?;
-    let string;
-    Effect::new(
-        range,
-        if let Some(path) = rpe.shader.strip_prefix('/') {
-            string = String::from_utf8(fs.load_file(path).await?).with_context(|| format!("Cannot load shader from {path}"))?;
-            &string
-        } else {
-            Effect::get_preset(&rpe.shader).ok_or_else(|| anyhow!("Cannot find preset shader {}", rpe.shader))?
-        },
-        vars,
-        rpe.global,
-    )
+    Effect::new(range, shader, vars, rpe.global).map(Some)
 }
 
 pub async fn parse_extra(source: &str, fs: &mut dyn FileSystem, ffmpeg: Option<&Path>) -> Result<ChartExtra> {
-    let ext: Extra = serde_json::from_str(source).context("Failed to parse JSON")?;
+    let ext: Extra = parse_json(source).context("Failed to parse JSON")?;
     let mut r: BpmList = ext.bpm.into();
     let mut effects = Vec::new();
     let mut global_effects = Vec::new();
     for (id, effect) in ext.effects.into_iter().enumerate() {
-        (if effect.global { &mut global_effects } else { &mut effects })
-            .push(parse_effect(&mut r, effect, fs).await.with_context(|| format!("In effect #{id}"))?);
+        let global = effect.global;
+        if let Some(effect) = parse_effect(&mut r, effect, fs).await.with_context(|| format!("In effect #{id}"))? {
+            (if global { &mut global_effects } else { &mut effects }).push(effect);
+        }
     }
     let mut videos = Vec::new();
     if let Some(ffmpeg) = ffmpeg {
@@ -0,0 +1,305 @@
+use super::process_lines;
+use crate::{
+    core::{
+        Anim, AnimFloat, AnimVector, BpmList, Chart, ChartExtra, ChartSettings, JudgeLine, JudgeLineCache, JudgeLineKind, Keyframe, Note, NoteKind,
+        Object,
+    },
+    ext::NotNanExt,
+    judge::JudgeStatus,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use macroquad::prelude::info;
+use std::cell::RefCell;
+
+const SPEED: f32 = 1.8;
+const LINE_Y: f32 = -0.6;
+const LANE_WIDTH: f32 = 1.2;
+
+struct OsuNote {
+    column: usize,
+    time: f32,
+    end_time: Option<f32>,
+}
+
+fn parse_f32(s: Option<&str>, desc: &str) -> Result<f32> {
+    s.ok_or_else(|| anyhow!("Missing {desc}"))?
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid {desc}"))
+}
+
+fn lane_x(column: usize, keys: usize) -> f32 {
+    ((column as f32 + 0.5) / keys as f32 - 0.5) * LANE_WIDTH
+}
+
+fn parse_judge_line(mut notes: Vec<Note>, max_time: f32) -> JudgeLine {
+    let height = AnimFloat::new(vec![Keyframe::new(0., 0., 2), Keyframe::new(max_time, max_time * SPEED, 0)]);
+    let cache = JudgeLineCache::new(&mut notes);
+    JudgeLine {
+        object: Object {
+            translation: AnimVector(AnimFloat::default(), AnimFloat::fixed(LINE_Y)),
+            ..Default::default()
+        },
+        ctrl_obj: RefCell::default(),
+        kind: JudgeLineKind::Normal,
+        height,
+        incline: AnimFloat::default(),
+        notes,
+        color: Anim::default(),
+        parent: None,
+        z_index: 0,
+        show_below: false,
+        attach_ui: None,
+        anchor: [0.5, 0.5],
+
+        cache,
+    }
+}
+
+pub fn parse_osu(source: &str, extra: ChartExtra) -> Result<Chart> {
+    let mut section = "";
+    let mut mode = None;
+    let mut keys = None;
+    let mut title = None;
+    let mut version = None;
+    let mut timing_points = Vec::new();
+    let mut notes = Vec::new();
+    for (id, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = &line[1..line.len() - 1];
+            continue;
+        }
+        (|| -> Result<()> {
+            match section {
+                "General" | "Difficulty" | "Metadata" => {
+                    let Some((key, value)) = line.split_once(':') else {
+                        return Ok(());
+                    };
+                    let value = value.trim();
+                    match key.trim() {
+                        "Mode" => mode = Some(value.parse::<u8>().context("Invalid mode")?),
+                        "CircleSize" => keys = Some(value.parse::<f32>().context("Invalid key count")?.round() as usize),
+                        "Title" => title = Some(value.to_owned()),
+                        "Version" => version = Some(value.to_owned()),
+                        _ => {}
+                    }
+                }
+                "TimingPoints" => {
+                    let mut it = line.split(',');
+                    let time = parse_f32(it.next(), "time")?;
+                    let beat_length = parse_f32(it.next(), "beat length")?;
+                    // inherited timing points only change slider velocity, which mania ignores
+                    let uninherited = it.nth(4).map_or(true, |it| it.trim() != "0") && beat_length > 0.;
+                    if uninherited {
+                        timing_points.push((time / 1000., beat_length / 1000.));
+                    }
+                }
+                "HitObjects" => {
+                    let mut it = line.split(',');
+                    let x = parse_f32(it.next(), "x")?;
+                    let time = parse_f32(it.nth(1), "time")? / 1000.;
+                    let kind = parse_f32(it.next(), "type")? as u32;
+                    let end_time = if kind & 128 != 0 {
+                        Some(parse_f32(it.nth(1).and_then(|it| it.split(':').next()), "end time")? / 1000.)
+                    } else {
+                        None
+                    };
+                    notes.push((x, time, end_time));
+                }
+                _ => {}
+            }
+            Ok(())
+        })()
+        .with_context(|| anyhow!("On line #{}", id + 1))?;
+    }
+    if mode != Some(3) {
+        bail!("Only osu!mania charts are supported");
+    }
+    let keys = keys.ok_or_else(|| anyhow!("Missing key count (CircleSize)"))?;
+    if !(4..=6).contains(&keys) {
+        bail!("Unsupported key count: {keys}K, only 4K to 6K charts are supported");
+    }
+    info!("Loading osu!mania chart {} [{}] ({keys}K)", title.unwrap_or_default(), version.unwrap_or_default());
+    let notes: Vec<_> = notes
+        .into_iter()
+        .map(|(x, time, end_time)| OsuNote {
+            column: ((x * keys as f32 / 512.).floor().max(0.) as usize).min(keys - 1),
+            time,
+            end_time,
+        })
+        .collect();
+
+    timing_points.sort_by_key(|it| it.0.not_nan());
+    let mut bpm_list = Vec::new();
+    let mut beats = 0.;
+    let mut last: Option<(f32, f32)> = None;
+    for (time, beat_length) in timing_points {
+        match last {
+            Some((last_time, last_length)) => beats += (time - last_time) / last_length,
+            None => {
+                // extend the first timing point back to time 0
+                beats = time / beat_length;
+                bpm_list.push((0., 60. / beat_length));
+            }
+        }
+        if beats > 0. {
+            bpm_list.push((beats, 60. / beat_length));
+        }
+        last = Some((time, beat_length));
+    }
+    if bpm_list.is_empty() {
+        bail!("No uninherited timing point found");
+    }
+
+    let max_time = *notes.iter().map(|it| it.end_time.unwrap_or(it.time).not_nan()).max().unwrap_or_default() + 1.;
+    // all lanes share a single judge line, with notes placed at their lane
+    let notes = notes
+        .into_iter()
+        .map(|note| Note {
+            object: Object {
+                translation: AnimVector(AnimFloat::fixed(lane_x(note.column, keys)), AnimFloat::default()),
+                ..Default::default()
+            },
+            kind: match note.end_time {
+                Some(end_time) => NoteKind::Hold {
+                    end_time,
+                    end_height: end_time * SPEED,
+                },
+                None => NoteKind::Click,
+            },
+            time: note.time,
+            height: note.time * SPEED,
+            speed: 1.0,
+
+            above: true,
+            multiple_hint: false,
+            fake: false,
+            visible_time: f32::INFINITY,
+            hitsound: None,
+            judge: JudgeStatus::NotJudged,
+        })
+        .collect();
+    let mut lines = vec![parse_judge_line(notes, max_time)];
+    process_lines(&mut lines);
+    Ok(Chart::new(0., lines, BpmList::new(bpm_list), ChartSettings::default(), extra))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4K chart at 120 BPM speeding up to 240 BPM at 5s, with an inherited timing point in between which should be ignored
+    const CHART_4K: &str = "osu file format v14
+
+[General]
+Mode: 3
+
+[Metadata]
+Title:Fixture
+Version:4K
+
+[Difficulty]
+CircleSize:4
+
+[TimingPoints]
+1000,500,4,2,0,100,1,0
+3000,-50,4,2,0,100,0,0
+5000,250,4,2,0,100,1,0
+
+[HitObjects]
+64,192,1000,1,0,0:0:0:0:
+192,192,1500,128,0,2500:0:0:0:0:
+320,192,2000,1,0,0:0:0:0:
+448,192,6000,128,0,7000:0:0:0:0:
+";
+
+    /// A 6K chart with a note on each column from left to right, the last one being a hold
+    const CHART_6K: &str = "osu file format v14
+
+[General]
+Mode: 3
+
+[Difficulty]
+CircleSize:6
+
+[TimingPoints]
+0,600,4,2,0,100,1,0
+
+[HitObjects]
+42,192,1000,1,0,0:0:0:0:
+128,192,1200,1,0,0:0:0:0:
+213,192,1400,1,0,0:0:0:0:
+298,192,1600,1,0,0:0:0:0:
+384,192,1800,1,0,0:0:0:0:
+469,192,2000,128,0,2400:0:0:0:0:
+";
+
+    /// Time, hold length (zero for taps) and x of each note in the order of time
+    fn notes(chart: &Chart) -> Vec<(f32, f32, f32)> {
+        let mut res: Vec<_> = chart.lines[0]
+            .notes
+            .iter()
+            .map(|note| {
+                let length = match note.kind {
+                    NoteKind::Hold { end_time, .. } => end_time - note.time,
+                    _ => 0.,
+                };
+                (note.time, length, note.object.translation.0.now())
+            })
+            .collect();
+        res.sort_by(|a, b| a.0.total_cmp(&b.0));
+        res
+    }
+
+    #[test]
+    fn four_keys() {
+        let chart = parse_osu(CHART_4K, ChartExtra::default()).unwrap();
+        assert_eq!(chart.lines.len(), 1);
+        let notes = notes(&chart);
+        assert_eq!(notes.len(), 4);
+        for ((time, length, x), expected) in notes.into_iter().zip([(1., 0., 0), (1.5, 1., 1), (2., 0., 2), (6., 1., 3)]) {
+            assert!((time - expected.0).abs() < 1e-4 && (length - expected.1).abs() < 1e-4, "{time} {length}");
+            assert_eq!(x, lane_x(expected.2, 4));
+        }
+        let bpm_list = chart.bpm_list.borrow();
+        assert_eq!(bpm_list.ranges(), [(0., 120.), (2., 120.), (10., 240.)]);
+        assert!((bpm_list.time_beats(12.) - 5.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn six_keys() {
+        let chart = parse_osu(CHART_6K, ChartExtra::default()).unwrap();
+        let notes = notes(&chart);
+        assert_eq!(notes.len(), 6);
+        for (column, (time, length, x)) in notes.into_iter().enumerate() {
+            assert!((time - (1. + column as f32 * 0.2)).abs() < 1e-4, "{time}");
+            assert_eq!(length > 0., column == 5);
+            assert_eq!(x, lane_x(column, 6));
+        }
+    }
+
+    #[test]
+    fn unsupported_charts() {
+        for keys in [1, 3, 7, 8, 10] {
+            let source = CHART_6K.replace("CircleSize:6", &format!("CircleSize:{keys}"));
+            let err = parse_osu(&source, ChartExtra::default()).err().unwrap().to_string();
+            assert!(err.contains(&format!("Unsupported key count: {keys}K")), "{err}");
+        }
+        let source = CHART_4K.replace("Mode: 3", "Mode: 0");
+        assert!(parse_osu(&source, ChartExtra::default()).is_err());
+    }
+
+    #[test]
+    fn stats() {
+        let stats = parse_osu(CHART_4K, ChartExtra::default()).unwrap().stats();
+        let notes = stats.notes;
+        assert_eq!((notes.taps, notes.drags, notes.holds, notes.flicks, notes.total), (2, 0, 2, 0, 4));
+        // the line's y, two keyframes for height and one for each note
+        assert_eq!((stats.fake_notes, stats.peak_nps, stats.lines, stats.events), (0, 2, 1, 7));
+        assert!((notes.last_note_time - 7.).abs() < 1e-4 && (stats.duration - 7.).abs() < 1e-4);
+    }
+}
@@ -1,8 +1,8 @@
-use super::{process_lines, RPE_TWEEN_MAP};
+use super::{process_lines, ParseOptions, RPE_TWEEN_MAP};
 use crate::{
     core::{
         Anim, AnimFloat, AnimVector, BpmList, Chart, ChartExtra, ChartSettings, JudgeLine, JudgeLineCache, JudgeLineKind, Keyframe, Note, NoteKind,
-        Object, TweenId, EPS,
+        Object, StaticTween, TweenId, EPS,
     },
     ext::NotNanExt,
     judge::JudgeStatus,
@@ -20,31 +20,33 @@ trait Take {
 
 impl<'a, T: Iterator<Item = &'a str>> Take for T {
     fn take_f32(&mut self) -> Result<f32> {
-        self.next()
-            .ok_or_else(|| anyhow!("Unexpected end of line"))
-            .and_then(|it| -> Result<f32> { Ok(it.parse()?) })
-            .context("Expected f32")
+        let it = self.next().ok_or_else(|| anyhow!("Unexpected end of line, expected f32"))?;
+        let value: f32 = it.parse().with_context(|| format!("Expected f32, got `{it}`"))?;
+        if !value.is_finite() {
+            bail!("Expected finite f32, got `{it}`");
+        }
+        Ok(value)
     }
 
     fn take_usize(&mut self) -> Result<usize> {
-        self.next()
-            .ok_or_else(|| anyhow!("Unexpected end of line"))
-            .and_then(|it| -> Result<usize> { Ok(it.parse()?) })
-            .context("Expected usize")
+        let it = self.next().ok_or_else(|| anyhow!("Unexpected end of line, expected usize"))?;
+        it.parse().with_context(|| format!("Expected usize, got `{it}`"))
     }
 
     fn take_tween(&mut self) -> Result<TweenId> {
-        self.next()
-            .ok_or_else(|| anyhow!("Unexpected end of line"))
-            .and_then(|it| -> Result<u8> {
-                let t = it.parse::<u8>()?;
-                Ok(RPE_TWEEN_MAP.get(t as usize).copied().unwrap_or(RPE_TWEEN_MAP[0]))
-            })
-            .context("Expected tween")
+        let it = self.next().ok_or_else(|| anyhow!("Unexpected end of line, expected tween"))?;
+        let t = it.parse::<u8>().with_context(|| format!("Expected tween, got `{it}`"))?;
+        Ok(RPE_TWEEN_MAP.get(t as usize).copied().unwrap_or(RPE_TWEEN_MAP[0]))
     }
 
     fn take_time(&mut self, r: &mut BpmList) -> Result<f32> {
-        self.take_f32().map(|it| r.time_beats(it))
+        // beats are parsed as f64 to keep fractional beats precise
+        let it = self.next().ok_or_else(|| anyhow!("Unexpected end of line, expected beats"))?;
+        let beats: f64 = it.parse().with_context(|| format!("Expected beats, got `{it}`"))?;
+        if !beats.is_finite() {
+            bail!("Expected finite beats, got `{it}`");
+        }
+        Ok(r.time_of(beats))
     }
 }
 
@@ -99,16 +101,25 @@ fn sanitize_events(events: &mut [PECEvent], id: usize, desc: &str) {
 
 fn parse_events(mut events: Vec<PECEvent>, id: usize, desc: &str) -> Result<AnimFloat> {
     sanitize_events(&mut events, id, desc);
-    let mut kfs = Vec::new();
+    let mut kfs = Vec::<Keyframe<f32>>::new();
     for e in events {
+        // commands at the same timestamp replace each other, the last one wins
+        let last = kfs.last_mut().filter(|it| it.time == e.start_time);
         if e.start_time == e.end_time {
-            kfs.push(Keyframe::new(e.start_time, e.end, 0));
+            if let Some(last) = last {
+                last.value = e.end;
+            } else {
+                kfs.push(Keyframe::new(e.start_time, e.end, 0));
+            }
         } else {
-            if kfs.is_empty() {
-                bail!("Failed to parse {desc} events: interpolating event found before a concrete value appears");
+            if let Some(last) = last {
+                last.tween = StaticTween::get_rc(e.easing);
+            } else {
+                let Some(last) = kfs.last() else {
+                    bail!("Failed to parse {desc} events: interpolating event found before a concrete value appears");
+                };
+                kfs.push(Keyframe::new(e.start_time, last.value, e.easing));
             }
-            assert!(!kfs.is_empty());
-            kfs.push(Keyframe::new(e.start_time, kfs.last().unwrap().value, e.easing));
             kfs.push(Keyframe::new(e.end_time, e.end, 0));
         }
     }
@@ -171,12 +182,13 @@ fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: f32) -> Result<J
         z_index: 0,
         show_below: false,
         attach_ui: None,
+        anchor: [0.5, 0.5],
 
         cache,
     })
 }
 
-pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
+pub fn parse_pec(source: &str, extra: ChartExtra, options: ParseOptions) -> Result<(Chart, Vec<String>)> {
     let mut offset = None;
     let mut r = None;
     let mut lines = Vec::new();
@@ -191,15 +203,18 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
         }
         &mut lines[id]
     }
-    fn ensure_bpm<'a>(r: &'a mut Option<BpmList>, bpm_list: &mut Vec<(f32, f32)>) -> &'a mut BpmList {
+    fn ensure_bpm<'a>(r: &'a mut Option<BpmList>, bpm_list: &mut Vec<(f32, f32)>) -> Result<&'a mut BpmList> {
         if r.is_none() {
+            if bpm_list.is_empty() {
+                bail!("No bpm event (bp) found before the first note or event");
+            }
             *r = Some(BpmList::new(std::mem::take(bpm_list)));
         }
-        r.as_mut().unwrap()
+        Ok(r.as_mut().unwrap())
     }
     macro_rules! bpm {
         () => {
-            ensure_bpm(&mut r, &mut bpm_list)
+            ensure_bpm(&mut r, &mut bpm_list)?
         };
     }
     macro_rules! last_note {
@@ -211,6 +226,9 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
         }};
     }
     let mut inner = |line: &str| -> Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
         let mut it = line.split_whitespace();
         if offset.is_none() {
             offset = Some(it.take_f32()? / 1000. - 0.15);
@@ -227,7 +245,20 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
                     if r.is_some() {
                         bail!("Bpm events (bp) should be contiguous and in the head of the file");
                     }
-                    bpm_list.push((it.take_f32()?, it.take_f32()?));
+                    let beats = it.take_f32()?;
+                    let bpm = it.take_f32()?;
+                    if bpm <= 0. {
+                        bail!("Bpm must be positive, got {bpm}");
+                    }
+                    if beats < 0. {
+                        bail!("Bpm event can't start at negative beat {beats}");
+                    }
+                    if let Some((last, _)) = bpm_list.last() {
+                        if beats <= *last {
+                            bail!("Bpm events (bp) should be in increasing order of beats, got {beats} after {last}");
+                        }
+                    }
+                    bpm_list.push((beats, bpm));
                 }
                 'n' if cs.len() == 2 && ('1'..='4').contains(&cs[1]) => {
                     let r = bpm!();
@@ -266,6 +297,8 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
                         above,
                         multiple_hint: false,
                         fake,
+                        visible_time: f32::INFINITY,
+                        hitsound: None,
                         judge: JudgeStatus::NotJudged,
                     });
                     if it.next() == Some("#") {
@@ -335,8 +368,18 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
         }
         Ok(())
     };
-    for (id, line) in source.lines().enumerate() {
-        inner(line).with_context(|| anyhow!("On line #{}", id + 1))?;
+    // files from Windows editors may come with a BOM and CR line endings
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source).replace("\r\n", "\n");
+    let source_lines: Vec<_> = source.split(['\n', '\r']).collect();
+    let mut warnings = Vec::new();
+    for (id, line) in source_lines.into_iter().enumerate() {
+        if let Err(err) = inner(line) {
+            let err = err.context(format!("On line #{}: {}", id + 1, line.trim()));
+            if options.strict {
+                return Err(err);
+            }
+            warnings.push(format!("Skipped {err:#}"));
+        }
     }
     let max_time = *lines
         .iter()
@@ -361,15 +404,118 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
         .map(|(id, line)| parse_judge_line(line, id, max_time).with_context(|| format!("In judge line #{id}")))
         .collect::<Result<Vec<_>>>()?;
     process_lines(&mut lines);
-    ensure_bpm(&mut r, &mut bpm_list);
-    Ok(Chart::new(
-        offset.unwrap(),
-        lines,
-        r.unwrap(),
-        ChartSettings {
-            pe_alpha_extension: true,
-            ..Default::default()
-        },
-        extra,
+    ensure_bpm(&mut r, &mut bpm_list)?;
+    Ok((
+        Chart::new(
+            offset.ok_or_else(|| anyhow!("Empty chart"))?,
+            lines,
+            r.unwrap(),
+            ChartSettings {
+                pe_alpha_extension: true,
+                ..Default::default()
+            },
+            extra,
+        ),
+        warnings,
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Chart {
+        parse_pec(source, ChartExtra::default(), ParseOptions::default()).unwrap().0
+    }
+
+    /// Samples (x, y, rotation, alpha) of the first line at `time`
+    fn sample(chart: &mut Chart, time: f32) -> [f32; 4] {
+        let object = &mut chart.lines[0].object;
+        object.set_time(time);
+        let translation = object.translation.now();
+        [translation.x, translation.y, object.rotation.now(), object.alpha.now()]
+    }
+
+    // Every command on a single line at 60 BPM, so that beats are seconds. Instant commands follow animated ones, beats are fractional
+    // and there are two `cp`s at the same time, where the latter should win.
+    const ALL_COMMANDS: &str = "0
+bp 0 60
+cv 0 0 5.85
+cp 0 0 1024 700
+cd 0 0 0
+ca 0 0 255
+cm 0 1 2.5 2048 1400 1
+cp 0 3 0 0
+cp 0 3 1024 700
+cr 0 1.5 2 90 1
+cd 0 2.25 45
+cf 0 1 2 0
+ca 0 2.5 127.5
+n1 0 1.5 512 1 0
+";
+
+    #[test]
+    fn all_commands() {
+        let mut chart = parse(ALL_COMMANDS);
+        let expected = [
+            (0.5, [0., 0., 0., 1.]),
+            (1.5, [1. / 3., 1. / 3., 0., 0.5]),
+            (1.75, [0.5, 0.5, -45., 0.25]),
+            (2.2, [0.8, 0.8, -90., 0.]),
+            (2.75, [1., 1., -45., 0.5]),
+            (3.5, [0., 0., -45., 0.5]),
+        ];
+        for (time, expected) in expected {
+            let got = sample(&mut chart, time);
+            assert!(got.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-4), "at {time}: {got:?} != {expected:?}");
+        }
+        let note = &chart.lines[0].notes[0];
+        assert_eq!(note.time, 1.5);
+        assert_eq!(note.object.translation.0.now(), 0.5);
+    }
+
+    #[test]
+    fn windows_line_endings() {
+        let mut expected = parse(ALL_COMMANDS);
+        for source in [
+            format!("\u{feff}{}\r\n\r\n", ALL_COMMANDS.replace('\n', "\r\n")),
+            format!("\u{feff}{}", ALL_COMMANDS.replace('\n', "\r")),
+            ALL_COMMANDS.replace('\n', "\n\n  \n"),
+        ] {
+            let mut chart = parse(&source);
+            assert_eq!(chart.offset, expected.offset);
+            assert_eq!(chart.lines[0].notes.len(), 1);
+            for time in [0.5, 1.75, 2.75, 3.5] {
+                assert_eq!(sample(&mut chart, time), sample(&mut expected, time));
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_command() {
+        let source = format!("\u{feff}{}", ALL_COMMANDS.replace("cd 0 2.25 45", "cd 0 2.25 4x5").replace('\n', "\r\n"));
+        let err = format!("{:#}", parse_pec(&source, ChartExtra::default(), ParseOptions::default()).err().unwrap());
+        assert!(err.contains("On line #11: cd 0 2.25 4x5"), "{err}");
+        assert!(err.contains("`4x5`"), "{err}");
+
+        // a truncated last command is only skipped when lenient
+        let source = format!("{ALL_COMMANDS}n1 0 3");
+        assert!(parse_pec(&source, ChartExtra::default(), ParseOptions::default()).is_err());
+        let (chart, warnings) = parse_pec(&source, ChartExtra::default(), ParseOptions { strict: false }).unwrap();
+        assert_eq!(chart.lines[0].notes.len(), 1);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("On line #15"), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn stats() {
+        let stats = parse(ALL_COMMANDS).stats();
+        let notes = stats.notes;
+        assert_eq!((notes.taps, notes.drags, notes.holds, notes.flicks, notes.total), (1, 0, 0, 0, 1));
+        // four keyframes for each of alpha, rotate and the coordinates, the line scale, two for height and one for the note
+        assert_eq!((stats.fake_notes, stats.peak_nps, stats.lines, stats.events), (0, 1, 1, 20));
+        assert_eq!(notes.last_note_time, 1.5);
+        // the last `cp` outlasts the note
+        assert!((stats.duration - 3.).abs() < 1e-4);
+    }
+}
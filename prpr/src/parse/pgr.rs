@@ -1,4 +1,4 @@
-use super::process_lines;
+use super::{array_items, parse_json_with, process_lines, retain_valid, ParseOptions};
 use crate::{
     core::{
         Anim, AnimFloat, AnimVector, BpmList, Chart, ChartExtra, ChartSettings, JudgeLine, JudgeLineCache, JudgeLineKind, Keyframe, Note, NoteKind,
@@ -43,6 +43,8 @@ pub struct PgrNote {
     hold_time: f32,
     speed: f32,
     floor_position: f32,
+    #[serde(default)]
+    is_fake: u8,
 }
 
 #[derive(Deserialize)]
@@ -64,6 +66,7 @@ struct PgrJudgeLine {
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PgrChart {
+    format_version: Option<u32>,
     offset: f32,
     judge_line_list: Vec<PgrJudgeLine>,
 }
@@ -121,8 +124,19 @@ fn parse_float_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimFloat> {
     Ok(AnimFloat::new(kfs))
 }
 
-fn parse_move_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
+fn parse_move_events(r: f32, mut pgr: Vec<PgrEvent>, format_version: u32) -> Result<AnimVector> {
     validate_events!(pgr);
+    if format_version == 1 {
+        // both coordinates are packed into a single value, as x * 1000 + y, in 880x520 units
+        fn unpack(value: f32) -> (f32, f32) {
+            let y = value % 1000.;
+            ((value - y) / 1000. / 880., y / 520.)
+        }
+        for e in &mut pgr {
+            (e.start, e.start2) = unpack(e.start);
+            (e.end, e.end2) = unpack(e.end);
+        }
+    }
     let mut kf1 = Vec::<Keyframe<f32>>::new();
     let mut kf2 = Vec::<Keyframe<f32>>::new();
     for e in pgr {
@@ -148,7 +162,7 @@ fn parse_move_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
     Ok(AnimVector(AnimFloat::new(kf1), AnimFloat::new(kf2)))
 }
 
-fn parse_notes(r: f32, mut pgr: Vec<PgrNote>, speed: &mut AnimFloat, height: &mut AnimFloat, above: bool) -> Result<Vec<Note>> {
+fn parse_notes(r: f32, mut pgr: Vec<PgrNote>, speed: &mut AnimFloat, height: &mut AnimFloat, above: bool, format_version: u32) -> Result<Vec<Note>> {
     // is_sorted is unstable...
     if pgr.is_empty() {
         return Ok(Vec::new());
@@ -181,22 +195,30 @@ fn parse_notes(r: f32, mut pgr: Vec<PgrNote>, speed: &mut AnimFloat, height: &mu
                 } else {
                     pgr.speed
                 },
-                height: pgr.floor_position / HEIGHT_RATIO,
+                // floor positions in version 1 are unreliable, so we derive them from speed events instead
+                height: if format_version == 1 {
+                    height.set_time(time);
+                    height.now()
+                } else {
+                    pgr.floor_position / HEIGHT_RATIO
+                },
 
                 above,
                 multiple_hint: false,
-                fake: false,
+                fake: pgr.is_fake != 0,
+                visible_time: f32::INFINITY,
+                hitsound: None,
                 judge: JudgeStatus::NotJudged,
             })
         })
         .collect()
 }
 
-fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
+fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32, format_version: u32) -> Result<JudgeLine> {
     let r = 60. / pgr.bpm / 32.;
     let (mut speed, mut height) = parse_speed_events(r, pgr.speed_events, max_time).context("Failed to parse speed events")?;
-    let notes_above = parse_notes(r, pgr.notes_above, &mut speed, &mut height, true).context("Failed to parse notes above")?;
-    let mut notes_below = parse_notes(r, pgr.notes_below, &mut speed, &mut height, false).context("Failed to parse notes below")?;
+    let notes_above = parse_notes(r, pgr.notes_above, &mut speed, &mut height, true, format_version).context("Failed to parse notes above")?;
+    let mut notes_below = parse_notes(r, pgr.notes_below, &mut speed, &mut height, false, format_version).context("Failed to parse notes below")?;
     let mut notes = notes_above;
     notes.append(&mut notes_below);
     let cache = JudgeLineCache::new(&mut notes);
@@ -204,7 +226,7 @@ fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
         object: Object {
             alpha: parse_float_events(r, pgr.alpha_events).context("Failed to parse alpha events")?,
             rotation: parse_float_events(r, pgr.rotate_events).context("Failed to parse rotate events")?,
-            translation: parse_move_events(r, pgr.move_events).context("Failed to parse move events")?,
+            translation: parse_move_events(r, pgr.move_events, format_version).context("Failed to parse move events")?,
             ..Default::default()
         },
         ctrl_obj: RefCell::default(),
@@ -217,13 +239,29 @@ fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
         z_index: 0,
         show_below: true,
         attach_ui: None,
+        anchor: [0.5, 0.5],
 
         cache,
     })
 }
 
-pub fn parse_phigros(source: &str, extra: ChartExtra) -> Result<Chart> {
-    let pgr: PgrChart = serde_json::from_str(source).context("Failed to parse JSON")?;
+fn sanitize_pgr(value: &mut serde_json::Value, warnings: &mut Vec<String>) {
+    for (path, line) in array_items(value, "judgeLineList", "") {
+        for key in ["judgeLineDisappearEvents", "judgeLineRotateEvents", "judgeLineMoveEvents"] {
+            retain_valid::<PgrEvent>(line, key, &path, warnings);
+        }
+        retain_valid::<PgrSpeedEvent>(line, "speedEvents", &path, warnings);
+        retain_valid::<PgrNote>(line, "notesAbove", &path, warnings);
+        retain_valid::<PgrNote>(line, "notesBelow", &path, warnings);
+    }
+}
+
+pub fn parse_phigros(source: &str, extra: ChartExtra, options: ParseOptions) -> Result<(Chart, Vec<String>)> {
+    let (pgr, warnings): (PgrChart, _) = parse_json_with(source, options, sanitize_pgr).context("Failed to parse JSON")?;
+    let format_version = pgr.format_version.unwrap_or(3);
+    if !matches!(format_version, 1 | 3) {
+        bail!("Unsupported format version: {format_version}");
+    }
     let max_time = *pgr
         .judge_line_list
         .iter()
@@ -243,8 +281,100 @@ pub fn parse_phigros(source: &str, extra: ChartExtra) -> Result<Chart> {
         .judge_line_list
         .into_iter()
         .enumerate()
-        .map(|(id, pgr)| parse_judge_line(pgr, max_time).with_context(|| format!("In judge line #{id}")))
+        .map(|(id, pgr)| parse_judge_line(pgr, max_time, format_version).with_context(|| format!("In judge line #{id}")))
         .collect::<Result<Vec<_>>>()?;
     process_lines(&mut lines);
-    Ok(Chart::new(pgr.offset, lines, BpmList::default(), ChartSettings::default(), extra))
+    Ok((Chart::new(pgr.offset, lines, BpmList::default(), ChartSettings::default(), extra), warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A line moving from the center to (0.75, 0.25) in the first second at 120 BPM, with a note on it every half a second
+    const CHART_V3: &str = r#"{"formatVersion":3,"offset":0,"judgeLineList":[{"bpm":120,
+        "judgeLineDisappearEvents":[{"startTime":-999999,"endTime":1000000000,"start":1,"end":1}],
+        "judgeLineRotateEvents":[{"startTime":-999999,"endTime":1000000000,"start":0,"end":0}],
+        "judgeLineMoveEvents":[
+            {"startTime":-999999,"endTime":0,"start":0.5,"end":0.5,"start2":0.5,"end2":0.5},
+            {"startTime":0,"endTime":64,"start":0.5,"end":0.75,"start2":0.5,"end2":0.25},
+            {"startTime":64,"endTime":1000000000,"start":0.75,"end":0.75,"start2":0.25,"end2":0.25}],
+        "speedEvents":[{"startTime":0,"endTime":1000000000,"value":1}],
+        "notesAbove":[
+            {"type":1,"time":64,"positionX":1,"holdTime":0,"speed":1,"floorPosition":1},
+            {"type":3,"time":128,"positionX":-2,"holdTime":32,"speed":1,"floorPosition":2}],
+        "notesBelow":[{"type":2,"time":96,"positionX":0,"holdTime":0,"speed":1,"floorPosition":1.5}]}]}"#;
+
+    /// The same chart in version 1, where positions are packed as x * 1000 + y in 880x520 units and floor positions are left out
+    const CHART_V1: &str = r#"{"formatVersion":1,"offset":0,"judgeLineList":[{"bpm":120,
+        "judgeLineDisappearEvents":[{"startTime":-999999,"endTime":1000000000,"start":1,"end":1}],
+        "judgeLineRotateEvents":[{"startTime":-999999,"endTime":1000000000,"start":0,"end":0}],
+        "judgeLineMoveEvents":[
+            {"startTime":-999999,"endTime":0,"start":440260,"end":440260},
+            {"startTime":0,"endTime":64,"start":440260,"end":660130},
+            {"startTime":64,"endTime":1000000000,"start":660130,"end":660130}],
+        "speedEvents":[{"startTime":0,"endTime":1000000000,"value":1}],
+        "notesAbove":[
+            {"type":1,"time":64,"positionX":1,"holdTime":0,"speed":1,"floorPosition":0},
+            {"type":3,"time":128,"positionX":-2,"holdTime":32,"speed":1,"floorPosition":0}],
+        "notesBelow":[{"type":2,"time":96,"positionX":0,"holdTime":0,"speed":1,"floorPosition":0}]}]}"#;
+
+    fn parse(source: &str) -> Chart {
+        parse_phigros(source, ChartExtra::default(), ParseOptions::default()).unwrap().0
+    }
+
+    /// Line positions at the sampled times, and the time, x and height of each note
+    fn sample(chart: &mut Chart) -> (Vec<(f32, f32)>, Vec<(f32, f32, f32)>) {
+        let line = &mut chart.lines[0];
+        let positions = [0., 0.25, 0.5, 1., 2.]
+            .into_iter()
+            .map(|time| {
+                line.object.set_time(time);
+                let now = line.object.translation.now();
+                (now.x, now.y)
+            })
+            .collect();
+        let mut notes: Vec<_> = line.notes.iter().map(|it| (it.time, it.object.translation.0.now(), it.height)).collect();
+        notes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        (positions, notes)
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-4, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn format_versions() {
+        let (positions, notes) = sample(&mut parse(CHART_V3));
+        let (positions_v1, notes_v1) = sample(&mut parse(CHART_V1));
+        assert_close(&positions.iter().flat_map(|it| [it.0, it.1]).collect::<Vec<_>>(), &[0., 0., 0.125, -0.125, 0.25, -0.25, 0.5, -0.5, 0.5, -0.5]);
+        assert_close(
+            &positions.iter().flat_map(|it| [it.0, it.1]).collect::<Vec<_>>(),
+            &positions_v1.iter().flat_map(|it| [it.0, it.1]).collect::<Vec<_>>(),
+        );
+        assert_eq!(notes.iter().map(|it| it.0).collect::<Vec<_>>(), [1., 1.5, 2.]);
+        assert_close(
+            &notes.iter().flat_map(|it| [it.0, it.1, it.2]).collect::<Vec<_>>(),
+            &notes_v1.iter().flat_map(|it| [it.0, it.1, it.2]).collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn unknown_format_version() {
+        let source = CHART_V3.replace(r#""formatVersion":3"#, r#""formatVersion":2"#);
+        assert!(parse_phigros(&source, ChartExtra::default(), ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn stats() {
+        let stats = parse(CHART_V3).stats();
+        let notes = stats.notes;
+        assert_eq!((notes.taps, notes.drags, notes.holds, notes.flicks, notes.total), (1, 1, 1, 0, 3));
+        // one alpha and one rotate keyframe, three for each coordinate, two for height and one for each note
+        assert_eq!((stats.fake_notes, stats.peak_nps, stats.lines, stats.events), (0, 2, 1, 13));
+        assert!((notes.last_note_time - 2.5).abs() < 1e-4 && (stats.duration - 2.5).abs() < 1e-4);
+    }
 }
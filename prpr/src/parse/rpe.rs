@@ -1,23 +1,24 @@
-use super::{process_lines, RPE_TWEEN_MAP};
+use super::{array_items, parse_json_with, process_lines_by, retain_valid, ParseOptions, RPE_TWEEN_MAP};
 use crate::{
     core::{
-        Anim, AnimFloat, AnimVector, BezierTween, BpmList, Chart, ChartExtra, ChartSettings, ClampedTween, CtrlObject, JudgeLine, JudgeLineCache,
-        JudgeLineKind, Keyframe, Note, NoteKind, Object, StaticTween, Triple, TweenFunction, Tweenable, UIElement, EPS, HEIGHT_RATIO,
+        Anim, AnimFloat, AnimVector, Beat, BezierTween, BpmList, Chart, ChartExtra, ChartSettings, ClampedTween, CtrlObject, JudgeLine,
+        JudgeLineCache, JudgeLineKind, Keyframe, Note, NoteKind, Object, StaticTween, Triple, TweenFunction, TweenId, Tweenable, UIElement, EPS,
+        HEIGHT_RATIO,
     },
     ext::NotNanExt,
     fs::FileSystem,
     judge::JudgeStatus,
 };
 use anyhow::{bail, Context, Result};
-use macroquad::prelude::{Color, WHITE};
-use serde::Deserialize;
+use macroquad::prelude::{warn, Color, WHITE};
+use serde::{Deserialize, Serialize};
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 pub const RPE_WIDTH: f32 = 1350.;
 pub const RPE_HEIGHT: f32 = 900.;
 const SPEED_RATIO: f32 = 10. / 45. / HEIGHT_RATIO;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEBpmItem {
     bpm: f32,
@@ -33,7 +34,11 @@ fn f32_one() -> f32 {
     1.
 }
 
-#[derive(Deserialize)]
+fn default_anchor() -> [f32; 2] {
+    [0.5, 0.5]
+}
+
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEEvent<T = f32> {
     // TODO linkgroup
@@ -52,7 +57,7 @@ struct RPEEvent<T = f32> {
     end_time: Triple,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPECtrlEvent {
     easing: u8,
@@ -61,7 +66,7 @@ struct RPECtrlEvent {
     value: HashMap<String, f32>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPESpeedEvent {
     // TODO linkgroup
@@ -71,36 +76,49 @@ struct RPESpeedEvent {
     end: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEEventLayer {
+    #[serde(skip_serializing_if = "Option::is_none")]
     alpha_events: Option<Vec<RPEEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     move_x_events: Option<Vec<RPEEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     move_y_events: Option<Vec<RPEEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     rotate_events: Option<Vec<RPEEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     speed_events: Option<Vec<RPESpeedEvent>>,
 }
 
-#[derive(Clone, Deserialize)]
-struct RGBColor(u8, u8, u8);
+// some charts have channels out of 0..=255, so we parse them as floats and clamp
+#[derive(Clone, Deserialize, Serialize)]
+struct RGBColor(f32, f32, f32);
 impl From<RGBColor> for Color {
     fn from(RGBColor(r, g, b): RGBColor) -> Self {
-        Self::from_rgba(r, g, b, 255)
+        let ch = |it: f32| it.clamp(0., 255.) / 255.;
+        Self::new(ch(r), ch(g), ch(b), 1.)
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEExtendedEvents {
+    #[serde(skip_serializing_if = "Option::is_none")]
     color_events: Option<Vec<RPEEvent<RGBColor>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     text_events: Option<Vec<RPEEvent<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     scale_x_events: Option<Vec<RPEEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     scale_y_events: Option<Vec<RPEEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     incline_events: Option<Vec<RPEEvent>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     paint_events: Option<Vec<RPEEvent>>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPENote {
     // TODO above == 0? what does that even mean?
@@ -116,9 +134,11 @@ struct RPENote {
     speed: f32,
     is_fake: u8,
     visible_time: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hitsound: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEJudgeLine {
     // TODO group
@@ -135,8 +155,10 @@ struct RPEJudgeLine {
     is_cover: u8,
     #[serde(default)]
     z_order: i32,
-    #[serde(rename = "attachUI")]
+    #[serde(rename = "attachUI", skip_serializing_if = "Option::is_none")]
     attach_ui: Option<UIElement>,
+    #[serde(default = "default_anchor")]
+    anchor: [f32; 2],
 
     #[serde(default)]
     pos_control: Vec<RPECtrlEvent>,
@@ -146,15 +168,17 @@ struct RPEJudgeLine {
     alpha_control: Vec<RPECtrlEvent>,
     #[serde(default)]
     y_control: Vec<RPECtrlEvent>,
+    #[serde(default)]
+    skew_control: Vec<RPECtrlEvent>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEMetadata {
     offset: i32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEChart {
     #[serde(rename = "META")]
@@ -178,20 +202,35 @@ fn parse_events<T: Tweenable, V: Clone + Into<T>>(
     default: Option<T>,
     bezier_map: &BezierMap,
 ) -> Result<Anim<T>> {
+    let mut rpe: Vec<_> = rpe.iter().collect();
+    rpe.sort_by_key(|e| (e.start_time.beat(), e.end_time.beat()));
     let mut kfs = Vec::new();
     if let Some(default) = default {
-        if rpe[0].start_time.beats() != 0.0 {
+        if rpe[0].start_time.beat().num() != 0 {
             kfs.push(Keyframe::new(0.0, default, 0));
         }
     }
+    let mut last_end = f32::NEG_INFINITY;
+    let mut overlaps = 0;
     for e in rpe {
+        let mut start_time = r.time(&e.start_time);
+        let end_time = r.time(&e.end_time);
+        if start_time < last_end {
+            // same as PEC, the overlapping part of the later event is dropped
+            overlaps += 1;
+            start_time = last_end;
+        }
+        last_end = end_time.max(last_end);
         kfs.push(Keyframe {
-            time: r.time(&e.start_time),
+            time: start_time,
             value: e.start.clone().into(),
             tween: {
                 let tween = RPE_TWEEN_MAP.get(e.easing_type.max(1) as usize).copied().unwrap_or(RPE_TWEEN_MAP[0]);
                 if e.bezier != 0 {
-                    Rc::clone(&bezier_map[&bezier_key(e)])
+                    bezier_map.get(&bezier_key(e)).map(Rc::clone).unwrap_or_else(|| {
+                        let p = &e.bezier_points;
+                        Rc::new(BezierTween::new((p[0], p[1]), (p[2], p[3])))
+                    })
                 } else if e.easing_left.abs() < EPS && (e.easing_right - 1.0).abs() < EPS {
                     StaticTween::get_rc(tween)
                 } else {
@@ -199,13 +238,16 @@ fn parse_events<T: Tweenable, V: Clone + Into<T>>(
                 }
             },
         });
-        kfs.push(Keyframe::new(r.time(&e.end_time), e.end.clone().into(), 0));
+        kfs.push(Keyframe::new(end_time.max(start_time), e.end.clone().into(), 0));
+    }
+    if overlaps != 0 {
+        warn!("{overlaps} overlapping events detected, later ones are clipped to start after the earlier ones end");
     }
     Ok(Anim::new(kfs))
 }
 
 fn parse_speed_events(r: &mut BpmList, rpe: &[RPEEventLayer], max_time: f32) -> Result<AnimFloat> {
-    let rpe: Vec<_> = rpe.iter().filter_map(|it| it.speed_events.as_ref()).collect();
+    let rpe: Vec<_> = rpe.iter().filter_map(|it| it.speed_events.as_ref()).filter(|it| !it.is_empty()).collect();
     if rpe.is_empty() {
         // TODO or is it?
         return Ok(AnimFloat::default());
@@ -214,6 +256,10 @@ fn parse_speed_events(r: &mut BpmList, rpe: &[RPEEventLayer], max_time: f32) ->
         .into_iter()
         .map(|it| {
             let mut kfs = Vec::new();
+            // the speed before the first event is the start value of it, which should be integrated as well
+            if r.time(&it[0].start_time) > 0. {
+                kfs.push(Keyframe::new(0., it[0].start, 0));
+            }
             for e in it {
                 kfs.push(Keyframe::new(r.time(&e.start_time), e.start, 2));
                 kfs.push(Keyframe::new(r.time(&e.end_time), e.end, 0));
@@ -272,24 +318,23 @@ fn parse_speed_events(r: &mut BpmList, rpe: &[RPEEventLayer], max_time: f32) ->
     Ok(AnimFloat::new(kfs))
 }
 
-fn parse_notes(r: &mut BpmList, rpe: Vec<RPENote>, height: &mut AnimFloat) -> Result<Vec<Note>> {
+/// Parses notes along with their exact beats
+fn parse_notes(r: &mut BpmList, mut rpe: Vec<RPENote>, height: &mut AnimFloat) -> Result<Vec<(Note, Beat)>> {
+    // sort on exact beats, float times may tie-break differently
+    rpe.sort_by_key(|it| it.start_time.beat());
     rpe.into_iter()
         .map(|note| {
-            let time = r.time(&note.start_time);
+            let beat = note.start_time.beat();
+            let time = r.time_of_beat(beat);
             height.set_time(time);
             let note_height = height.now();
             let y_offset = note.y_offset * 2. / RPE_HEIGHT * note.speed;
-            Ok(Note {
+            let note = Note {
                 object: Object {
-                    alpha: if note.visible_time >= time {
-                        if note.alpha >= 255 {
-                            AnimFloat::default()
-                        } else {
-                            AnimFloat::fixed(note.alpha as f32 / 255.)
-                        }
+                    alpha: if note.alpha >= 255 {
+                        AnimFloat::default()
                     } else {
-                        let alpha = note.alpha.min(255) as f32 / 255.;
-                        AnimFloat::new(vec![Keyframe::new(0.0, 0.0, 0), Keyframe::new(time - note.visible_time, alpha, 0)])
+                        AnimFloat::fixed(note.alpha as f32 / 255.)
                     },
                     translation: AnimVector(AnimFloat::fixed(note.position_x / (RPE_WIDTH / 2.)), AnimFloat::fixed(y_offset)),
                     scale: AnimVector(
@@ -323,15 +368,19 @@ fn parse_notes(r: &mut BpmList, rpe: Vec<RPENote>, height: &mut AnimFloat) -> Re
                 above: note.above == 1,
                 multiple_hint: false,
                 fake: note.is_fake != 0,
+                visible_time: if note.visible_time >= time { f32::INFINITY } else { note.visible_time },
+                hitsound: note.hitsound.filter(|it| !it.is_empty()),
                 judge: JudgeStatus::NotJudged,
-            })
+            };
+            Ok((note, beat))
         })
         .collect()
 }
 
-fn parse_ctrl_events(rpe: &[RPECtrlEvent], key: &str) -> AnimFloat {
-    let vals: Vec<_> = rpe.iter().map(|it| it.value[key]).collect();
-    if rpe.is_empty() || (rpe.len() == 2 && rpe[0].easing == 1 && (vals[0] - 1.).abs() < 1e-4) {
+fn parse_ctrl_events(rpe: &[RPECtrlEvent], key: &str, identity: f32) -> AnimFloat {
+    let vals: Vec<_> = rpe.iter().map(|it| it.value.get(key).copied().unwrap_or(identity)).collect();
+    // RPE always emits an identity list, which is left out so that normal charts don't pay for it
+    if rpe.is_empty() || vals.iter().all(|it| (it - identity).abs() < 1e-4) {
         return AnimFloat::default();
     }
     AnimFloat::new(
@@ -342,7 +391,14 @@ fn parse_ctrl_events(rpe: &[RPECtrlEvent], key: &str) -> AnimFloat {
     )
 }
 
-async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs: &mut dyn FileSystem, bezier_map: &BezierMap) -> Result<JudgeLine> {
+/// Parses a judge line, along with the exact beat of each of its notes
+async fn parse_judge_line(
+    r: &mut BpmList,
+    rpe: RPEJudgeLine,
+    max_time: f32,
+    fs: &mut dyn FileSystem,
+    bezier_map: &BezierMap,
+) -> Result<(JudgeLine, Vec<Beat>)> {
     let event_layers: Vec<_> = rpe.event_layers.into_iter().flatten().collect();
     fn events_with_factor(
         r: &mut BpmList,
@@ -354,7 +410,12 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
     ) -> Result<AnimFloat> {
         let anis: Vec<_> = event_layers
             .iter()
-            .filter_map(|it| get(it).as_ref().map(|es| parse_events(r, es, None, bezier_map)))
+            .filter_map(|it| {
+                get(it)
+                    .as_ref()
+                    .filter(|es| !es.is_empty())
+                    .map(|es| parse_events(r, es, None, bezier_map))
+            })
             .collect::<Result<_>>()
             .with_context(|| format!("Failed to parse {desc} events"))?;
         let mut res = AnimFloat::chain(anis);
@@ -363,8 +424,11 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
     }
     let mut height = parse_speed_events(r, &event_layers, max_time)?;
     let mut notes = parse_notes(r, rpe.notes.unwrap_or_default(), &mut height)?;
+    // put beats in the order the cache keeps notes in, on which the sort below is stable
+    notes.sort_by_key(|(note, _)| JudgeLineCache::order(note));
+    let (mut notes, beats): (Vec<_>, Vec<_>) = notes.into_iter().unzip();
     let cache = JudgeLineCache::new(&mut notes);
-    Ok(JudgeLine {
+    let line = JudgeLine {
         object: Object {
             alpha: events_with_factor(r, &event_layers, |it| &it.alpha_events, 1. / 255., "alpha", bezier_map)?,
             rotation: events_with_factor(r, &event_layers, |it| &it.rotate_events, -1., "rotate", bezier_map)?,
@@ -407,8 +471,9 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
                                         1.
                                     },
                                 bezier_map,
-                            )?,
-                            parse(r, &e.scale_y_events, factor, bezier_map)?,
+                            )
+                            .context("Failed to parse scale X events")?,
+                            parse(r, &e.scale_y_events, factor, bezier_map).context("Failed to parse scale Y events")?,
                         ))
                     })
                     .transpose()?
@@ -416,10 +481,11 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
             },
         },
         ctrl_obj: RefCell::new(CtrlObject {
-            alpha: parse_ctrl_events(&rpe.alpha_control, "alpha"),
-            size: parse_ctrl_events(&rpe.size_control, "size"),
-            pos: parse_ctrl_events(&rpe.pos_control, "pos"),
-            y: parse_ctrl_events(&rpe.y_control, "y"),
+            alpha: parse_ctrl_events(&rpe.alpha_control, "alpha", 1.),
+            size: parse_ctrl_events(&rpe.size_control, "size", 1.),
+            pos: parse_ctrl_events(&rpe.pos_control, "pos", 1.),
+            y: parse_ctrl_events(&rpe.y_control, "y", 1.),
+            skew: parse_ctrl_events(&rpe.skew_control, "skew", 0.),
         }),
         height,
         incline: if let Some(events) = rpe.extended.as_ref().and_then(|e| e.incline_events.as_ref()) {
@@ -436,6 +502,9 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
             } else {
                 JudgeLineKind::Normal
             }
+        } else if rpe.texture.to_lowercase().ends_with(".gif") {
+            // frames are decoded later in `Chart::load_textures`
+            JudgeLineKind::TextureGif(Vec::new(), rpe.texture.clone())
         } else {
             JudgeLineKind::Texture(
                 image::load_from_memory(
@@ -463,16 +532,17 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
         z_index: rpe.z_order,
         show_below: rpe.is_cover != 1,
         attach_ui: rpe.attach_ui,
+        anchor: rpe.anchor,
 
         cache,
-    })
+    };
+    Ok((line, beats))
 }
 
 fn add_bezier<T>(map: &mut BezierMap, event: &RPEEvent<T>) {
     if event.bezier != 0 {
         let p = &event.bezier_points;
-        let int = |p: f32| (p * 100.).round() as i16;
-        map.entry(((int(p[0]) * 100 + int(p[1])) as u16, int(p[2]), int(p[3])))
+        map.entry(bezier_key(event))
             .or_insert_with(|| Rc::new(BezierTween::new((p[0], p[1]), (p[2], p[3]))));
     }
 }
@@ -496,10 +566,49 @@ fn get_bezier_map(rpe: &RPEChart) -> BezierMap {
     map
 }
 
-pub async fn parse_rpe(source: &str, fs: &mut dyn FileSystem, extra: ChartExtra) -> Result<Chart> {
-    let rpe: RPEChart = serde_json::from_str(source).context("Failed to parse JSON")?;
+fn check_parents(lines: &[JudgeLine]) -> Result<()> {
+    for id in 0..lines.len() {
+        let mut visited = vec![false; lines.len()];
+        visited[id] = true;
+        let mut cur = id;
+        while let Some(parent) = lines[cur].parent {
+            if parent >= lines.len() {
+                bail!("Judge line #{cur} has an invalid father index {}", parent as isize);
+            }
+            if visited[parent] {
+                bail!("Cyclic father chain found starting from judge line #{id}");
+            }
+            visited[parent] = true;
+            cur = parent;
+        }
+    }
+    Ok(())
+}
+
+fn sanitize_rpe(value: &mut serde_json::Value, warnings: &mut Vec<String>) {
+    for (path, line) in array_items(value, "judgeLineList", "") {
+        retain_valid::<RPENote>(line, "notes", &path, warnings);
+        for (path, layer) in array_items(line, "eventLayers", &path) {
+            for key in ["alphaEvents", "moveXEvents", "moveYEvents", "rotateEvents"] {
+                retain_valid::<RPEEvent>(layer, key, &path, warnings);
+            }
+            retain_valid::<RPESpeedEvent>(layer, "speedEvents", &path, warnings);
+        }
+        if let Some(extended) = line.get_mut("extended") {
+            let path = format!("{path}.extended");
+            for key in ["scaleXEvents", "scaleYEvents", "inclineEvents", "paintEvents"] {
+                retain_valid::<RPEEvent>(extended, key, &path, warnings);
+            }
+            retain_valid::<RPEEvent<RGBColor>>(extended, "colorEvents", &path, warnings);
+            retain_valid::<RPEEvent<String>>(extended, "textEvents", &path, warnings);
+        }
+    }
+}
+
+pub async fn parse_rpe(source: &str, fs: &mut dyn FileSystem, extra: ChartExtra, options: ParseOptions) -> Result<(Chart, Vec<String>)> {
+    let (rpe, warnings): (RPEChart, _) = parse_json_with(source, options, sanitize_rpe).context("Failed to parse JSON")?;
     let bezier_map = get_bezier_map(&rpe);
-    let mut r = BpmList::new(rpe.bpm_list.into_iter().map(|it| (it.start_time.beats(), it.bpm)).collect());
+    let mut r = BpmList::with_beats(rpe.bpm_list.into_iter().map(|it| (it.start_time.beat(), it.bpm)).collect());
     fn vec<T>(v: &Option<Vec<T>>) -> impl Iterator<Item = &T> {
         v.iter().flat_map(|it| it.iter())
     }
@@ -534,16 +643,465 @@ pub async fn parse_rpe(source: &str, fs: &mut dyn FileSystem, extra: ChartExtra)
             )
         })
         .max().unwrap_or_default() + 1.;
+    // holds on covered lines emerge from the line instead of popping in whole
+    let hold_partial_cover = rpe.judge_line_list.iter().any(|it| it.is_cover == 1);
     // don't want to add a whole crate for a mere join_all...
     let mut lines = Vec::new();
+    let mut beats = Vec::new();
     for (id, rpe) in rpe.judge_line_list.into_iter().enumerate() {
         let name = rpe.name.clone();
-        lines.push(
-            parse_judge_line(&mut r, rpe, max_time, fs, &bezier_map)
-                .await
-                .with_context(move || format!("In judge line #{id} ({name})"))?,
-        );
+        let (line, line_beats) = parse_judge_line(&mut r, rpe, max_time, fs, &bezier_map)
+            .await
+            .with_context(move || format!("In judge line #{id} ({name})"))?;
+        lines.push(line);
+        beats.push(line_beats);
+    }
+    check_parents(&lines)?;
+    // simultaneous notes are told by exact beats, their times may differ in the last bits
+    process_lines_by(&mut lines, &beats);
+    Ok((
+        Chart::new(
+            rpe.meta.offset as f32 / 1000.0,
+            lines,
+            r,
+            ChartSettings {
+                hold_partial_cover,
+                ..Default::default()
+            },
+            extra,
+        ),
+        warnings,
+    ))
+}
+
+fn rpe_easing(tween: TweenId) -> i32 {
+    // the first two entries are both linear. Tweens unknown to RPE (e.g. quint in-out) fall back to linear
+    RPE_TWEEN_MAP.iter().skip(1).position(|it| *it == tween).map_or(1, |it| it as i32 + 1)
+}
+
+fn serialize_events<T: Tweenable, V>(r: &BpmList, anim: &Anim<T>, value: impl Fn(&T) -> V) -> Option<Vec<RPEEvent<V>>> {
+    let kfs = &anim.keyframes;
+    let event = |start: &Keyframe<T>, end_time: Triple, end: &T| RPEEvent {
+        easing_left: 0.,
+        easing_right: 1.,
+        bezier: 0,
+        bezier_points: [0.; 4],
+        easing_type: 1,
+        start: value(&start.value),
+        end: value(end),
+        start_time: Triple::from_beats(r.beat(start.time)),
+        end_time,
+    };
+    if let [kf] = &kfs[..] {
+        return Some(vec![event(kf, Triple::from_beats(r.beat(kf.time) + 1.), &kf.value)]);
+    }
+    let events: Vec<_> = kfs
+        .windows(2)
+        .filter(|it| it[1].time > it[0].time)
+        .map(|it| {
+            let (start, end) = (&it[0], &it[1]);
+            let tween = start.tween.as_any();
+            if let Some(t) = tween.downcast_ref::<StaticTween>() {
+                match t.0 {
+                    0 => event(start, Triple::from_beats(r.beat(end.time)), &start.value),
+                    1 => RPEEvent {
+                        start: value(&end.value),
+                        ..event(start, Triple::from_beats(r.beat(end.time)), &end.value)
+                    },
+                    id => RPEEvent {
+                        easing_type: rpe_easing(id),
+                        ..event(start, Triple::from_beats(r.beat(end.time)), &end.value)
+                    },
+                }
+            } else if let Some(t) = tween.downcast_ref::<ClampedTween>() {
+                RPEEvent {
+                    easing_type: rpe_easing(t.0),
+                    easing_left: t.1.start,
+                    easing_right: t.1.end,
+                    ..event(start, Triple::from_beats(r.beat(end.time)), &end.value)
+                }
+            } else if let Some(t) = tween.downcast_ref::<BezierTween>() {
+                RPEEvent {
+                    bezier: 1,
+                    bezier_points: [t.p1.0, t.p1.1, t.p2.0, t.p2.1],
+                    ..event(start, Triple::from_beats(r.beat(end.time)), &end.value)
+                }
+            } else {
+                event(start, Triple::from_beats(r.beat(end.time)), &end.value)
+            }
+        })
+        .collect();
+    if events.is_empty() {
+        None
+    } else {
+        Some(events)
+    }
+}
+
+fn serialize_layers<T: Tweenable>(anim: &Anim<T>) -> Vec<&Anim<T>> {
+    let mut res = vec![anim];
+    let mut cur = anim;
+    while let Some(next) = &cur.next {
+        res.push(next);
+        cur = next;
+    }
+    res
+}
+
+// speed is stored as its integral (height), so every segment is exported with its average speed
+fn serialize_speed_events(r: &BpmList, height: &AnimFloat) -> Option<Vec<RPESpeedEvent>> {
+    let events: Vec<_> = height
+        .keyframes
+        .windows(2)
+        .filter(|it| it[1].time > it[0].time)
+        .map(|it| {
+            let speed = (it[1].value - it[0].value) / (it[1].time - it[0].time) / SPEED_RATIO;
+            RPESpeedEvent {
+                start_time: Triple::from_beats(r.beat(it[0].time)),
+                end_time: Triple::from_beats(r.beat(it[1].time)),
+                start: speed,
+                end: speed,
+            }
+        })
+        .collect();
+    if events.is_empty() {
+        None
+    } else {
+        Some(events)
+    }
+}
+
+fn serialize_ctrl_events(anim: &AnimFloat, key: &str) -> Vec<RPECtrlEvent> {
+    anim.keyframes
+        .iter()
+        .map(|kf| RPECtrlEvent {
+            easing: kf.tween.as_any().downcast_ref::<StaticTween>().map_or(1, |it| rpe_easing(it.0)) as u8,
+            x: kf.time,
+            value: HashMap::from([(key.to_owned(), kf.value)]),
+        })
+        .collect()
+}
+
+fn serialize_note(r: &BpmList, note: &Note) -> RPENote {
+    const ALWAYS_VISIBLE: f32 = 999999.;
+    let fixed = |anim: &AnimFloat, default: f32| anim.keyframes.first().map_or(default, |it| it.value);
+    let alpha = fixed(&note.object.alpha, 1.);
+    let speed = note.speed;
+    RPENote {
+        kind: match note.kind {
+            NoteKind::Click => 1,
+            NoteKind::Hold { .. } => 2,
+            NoteKind::Flick => 3,
+            NoteKind::Drag => 4,
+        },
+        above: note.above as u8,
+        start_time: Triple::from_beats(r.beat(note.time)),
+        end_time: Triple::from_beats(r.beat(match note.kind {
+            NoteKind::Hold { end_time, .. } => end_time,
+            _ => note.time,
+        })),
+        position_x: fixed(&note.object.translation.0, 0.) * (RPE_WIDTH / 2.),
+        y_offset: if speed == 0. {
+            0.
+        } else {
+            fixed(&note.object.translation.1, 0.) * (RPE_HEIGHT / 2.) / speed
+        },
+        alpha: (alpha * 255.).round().clamp(0., 255.) as u16,
+        size: fixed(&note.object.scale.0, 1.),
+        speed,
+        is_fake: note.fake as u8,
+        visible_time: note.visible_time.min(ALWAYS_VISIBLE),
+        hitsound: note.hitsound.clone(),
+    }
+}
+
+fn serialize_judge_line(r: &BpmList, id: usize, line: &JudgeLine) -> RPEJudgeLine {
+    let texture = match &line.kind {
+        JudgeLineKind::Texture(_, path) | JudgeLineKind::TextureGif(_, path) => path.clone(),
+        _ => "line.png".to_owned(),
+    };
+    let is_line = texture == "line.png";
+    let scale_factor = if is_line { 1. } else { 2.57 / RPE_WIDTH };
+    let scale_x_factor = scale_factor
+        * if is_line && !matches!(line.kind, JudgeLineKind::Text(_)) && line.attach_ui.is_none() {
+            0.5
+        } else {
+            1.
+        };
+
+    let alpha = serialize_layers(&line.object.alpha);
+    let move_x = serialize_layers(&line.object.translation.0);
+    let move_y = serialize_layers(&line.object.translation.1);
+    let rotate = serialize_layers(&line.object.rotation);
+    let layer_count = alpha.len().max(move_x.len()).max(move_y.len()).max(rotate.len());
+    let event_layers = (0..layer_count)
+        .map(|i| {
+            Some(RPEEventLayer {
+                alpha_events: alpha.get(i).and_then(|it| serialize_events(r, it, |v| v * 255.)),
+                move_x_events: move_x.get(i).and_then(|it| serialize_events(r, it, |v| v * (RPE_WIDTH / 2.))),
+                move_y_events: move_y.get(i).and_then(|it| serialize_events(r, it, |v| v * (RPE_HEIGHT / 2.))),
+                rotate_events: rotate.get(i).and_then(|it| serialize_events(r, it, |v| -v)),
+                speed_events: if i == 0 { serialize_speed_events(r, &line.height) } else { None },
+            })
+        })
+        .collect();
+    let ctrl_obj = line.ctrl_obj.borrow();
+    RPEJudgeLine {
+        name: format!("Line #{id}"),
+        texture,
+        parent: Some(line.parent.map_or(-1, |it| it as isize)),
+        event_layers,
+        extended: Some(RPEExtendedEvents {
+            color_events: serialize_events(r, &line.color, |c| RGBColor(c.r * 255., c.g * 255., c.b * 255.)),
+            text_events: match &line.kind {
+                JudgeLineKind::Text(anim) => serialize_events(r, anim, String::clone),
+                _ => None,
+            },
+            scale_x_events: serialize_events(r, &line.object.scale.0, |v| v / scale_x_factor),
+            scale_y_events: serialize_events(r, &line.object.scale.1, |v| v / scale_factor),
+            incline_events: serialize_events(r, &line.incline, |v| *v),
+            paint_events: match &line.kind {
+                JudgeLineKind::Paint(anim, _) => serialize_events(r, anim, |v| *v),
+                _ => None,
+            },
+        }),
+        notes: Some(line.notes.iter().map(|note| serialize_note(r, note)).collect()),
+        is_cover: !line.show_below as u8,
+        z_order: line.z_index,
+        attach_ui: line.attach_ui,
+        anchor: line.anchor,
+
+        pos_control: serialize_ctrl_events(&ctrl_obj.pos, "pos"),
+        size_control: serialize_ctrl_events(&ctrl_obj.size, "size"),
+        alpha_control: serialize_ctrl_events(&ctrl_obj.alpha, "alpha"),
+        y_control: serialize_ctrl_events(&ctrl_obj.y, "y"),
+        skew_control: serialize_ctrl_events(&ctrl_obj.skew, "skew"),
+    }
+}
+
+/// Exports a chart, no matter which format it's parsed from, as RPE JSON
+pub fn serialize_rpe(chart: &Chart) -> Result<String> {
+    let r = chart.bpm_list.borrow();
+    let rpe = RPEChart {
+        meta: RPEMetadata {
+            offset: (chart.offset * 1000.).round() as i32,
+        },
+        bpm_list: r
+            .ranges()
+            .into_iter()
+            .map(|(beats, bpm)| RPEBpmItem {
+                bpm,
+                start_time: Triple::from_beats(beats),
+            })
+            .collect(),
+        judge_line_list: chart
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(id, line)| serialize_judge_line(&r, id, line))
+            .collect(),
+    };
+    Ok(serde_json::to_string(&rpe)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::any::Any;
+
+    /// File system of charts without external files
+    struct NoFileSystem;
+
+    #[async_trait]
+    impl FileSystem for NoFileSystem {
+        async fn load_file(&mut self, path: &str) -> Result<Vec<u8>> {
+            bail!("No file {path}")
+        }
+
+        async fn exists(&mut self, _path: &str) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn list_root(&self) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        fn clone_box(&self) -> Box<dyn FileSystem> {
+            Box::new(NoFileSystem)
+        }
+
+        fn as_any(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    fn parse(source: &str) -> (Chart, Vec<String>) {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime
+            .block_on(parse_rpe(source, &mut NoFileSystem, ChartExtra::default(), ParseOptions::default()))
+            .unwrap()
+    }
+
+    /// A line moving, turning and fading across a BPM change, with a note of each kind, and a child line under it
+    const CHART: &str = r#"{"META":{"offset":20},"BPMList":[{"bpm":120,"startTime":[0,0,1]},{"bpm":180,"startTime":[4,0,1]}],
+        "judgeLineList":[{"Name":"main","Texture":"line.png","father":-1,"isCover":1,"eventLayers":[{
+            "alphaEvents":[
+                {"easingType":1,"start":255,"end":0,"startTime":[0,0,1],"endTime":[2,0,1]},
+                {"easingType":2,"start":0,"end":255,"startTime":[2,0,1],"endTime":[6,0,1]}],
+            "moveXEvents":[{"easingType":1,"start":-200,"end":300,"startTime":[0,0,1],"endTime":[3,1,2]}],
+            "moveYEvents":[{"easingType":3,"start":0,"end":-150,"startTime":[1,0,1],"endTime":[5,0,1]}],
+            "rotateEvents":[{"easingType":1,"start":0,"end":90,"startTime":[0,0,1],"endTime":[4,0,1]}],
+            "speedEvents":[
+                {"start":10,"end":10,"startTime":[0,0,1],"endTime":[3,0,1]},
+                {"start":5,"end":5,"startTime":[3,0,1],"endTime":[6,0,1]}]}],
+        "notes":[
+            {"type":1,"above":1,"startTime":[1,0,1],"endTime":[1,0,1],"positionX":100,"yOffset":0,"alpha":255,"size":1,"speed":1,
+                "isFake":0,"visibleTime":999999},
+            {"type":2,"above":1,"startTime":[2,1,4],"endTime":[3,0,1],"positionX":-300,"yOffset":0,"alpha":255,"size":1,"speed":1.5,
+                "isFake":0,"visibleTime":999999},
+            {"type":3,"above":0,"startTime":[4,2,3],"endTime":[4,2,3],"positionX":0,"yOffset":0,"alpha":128,"size":1.5,"speed":1,
+                "isFake":0,"visibleTime":0.5,"hitsound":"flick.ogg"},
+            {"type":4,"above":1,"startTime":[5,0,1],"endTime":[5,0,1],"positionX":250,"yOffset":0,"alpha":255,"size":1,"speed":1,
+                "isFake":1,"visibleTime":999999}]},
+        {"Name":"child","Texture":"line.png","father":0,"isCover":1,"eventLayers":[{
+            "moveXEvents":[{"easingType":1,"start":0,"end":100,"startTime":[0,0,1],"endTime":[2,0,1]}],
+            "speedEvents":[{"start":8,"end":8,"startTime":[0,0,1],"endTime":[1,0,1]}]}],
+        "notes":[{"type":1,"above":1,"startTime":[1,1,2],"endTime":[1,1,2],"positionX":-50,"yOffset":0,"alpha":255,"size":1,"speed":1,
+            "isFake":0,"visibleTime":999999}]}]}"#;
+
+    /// Line states at some times and properties of notes in the order of time, flattened for comparison
+    fn snapshot(chart: &mut Chart) -> Vec<f32> {
+        let mut res = Vec::new();
+        for line in &mut chart.lines {
+            for time in [0., 0.25, 0.9, 1.6, 2.3, 3.1, 4.] {
+                line.object.set_time(time);
+                line.height.set_time(time);
+                let translation = line.object.translation.now();
+                res.extend([
+                    translation.x,
+                    translation.y,
+                    line.object.rotation.now(),
+                    line.object.alpha.now(),
+                    line.height.now(),
+                ]);
+            }
+            let mut notes: Vec<_> = line.notes.iter().collect();
+            notes.sort_by(|a, b| a.time.total_cmp(&b.time));
+            for note in notes {
+                let (end_time, end_height) = match note.kind {
+                    NoteKind::Hold { end_time, end_height } => (end_time, end_height),
+                    _ => (note.time, note.height),
+                };
+                res.extend([
+                    note.kind.order() as f32,
+                    note.time,
+                    end_time,
+                    note.height,
+                    end_height,
+                    note.object.translation.0.now(),
+                    note.object.alpha.now_opt().unwrap_or(1.),
+                    note.object.scale.0.now_opt().unwrap_or(1.),
+                    note.speed,
+                    note.above as u8 as f32,
+                    note.fake as u8 as f32,
+                    note.visible_time.min(1e6),
+                ]);
+            }
+        }
+        res
+    }
+
+    fn hitsounds(chart: &Chart) -> Vec<Option<&str>> {
+        let mut res: Vec<_> = chart
+            .lines
+            .iter()
+            .flat_map(|it| it.notes.iter())
+            .map(|it| it.hitsound.as_deref())
+            .collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let (mut chart, warnings) = parse(CHART);
+        assert!(warnings.is_empty());
+        let (mut again, _) = parse(&serialize_rpe(&chart).unwrap());
+        assert_eq!(again.offset, chart.offset);
+        assert_eq!(again.bpm_list.borrow().ranges(), chart.bpm_list.borrow().ranges());
+        assert_eq!(again.lines.iter().map(|it| it.parent).collect::<Vec<_>>(), [None, Some(0)]);
+        assert_eq!(hitsounds(&again), hitsounds(&chart));
+        assert!(hitsounds(&chart).contains(&Some("flick.ogg")));
+        let (expected, got) = (snapshot(&mut chart), snapshot(&mut again));
+        assert_eq!(expected.len(), got.len());
+        for (i, (a, b)) in expected.iter().zip(&got).enumerate() {
+            assert!((a - b).abs() <= 1e-3 * a.abs().max(1.), "#{i}: {a} != {b}");
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let (chart, _) = parse(CHART);
+        let stats = chart.stats();
+        let notes = stats.notes;
+        // the drag is fake, and left out everywhere but `fake_notes`
+        assert_eq!((notes.taps, notes.drags, notes.holds, notes.flicks, notes.total), (2, 0, 1, 1, 4));
+        assert_eq!((stats.fake_notes, stats.peak_nps, stats.lines, stats.events), (1, 3, 2, 31));
+        assert!((notes.last_note_time - (2. + 2. / 9.)).abs() < 1e-4, "{}", notes.last_note_time);
+        // alpha events end at beat 6, which is 2s + 2 beats at 180 BPM
+        assert!((stats.duration - 8. / 3.).abs() < 1e-4, "{}", stats.duration);
+    }
+
+    /// Alpha events out of order, where each one starts before the previous one ends
+    const OVERLAPPING_ALPHA: &str = r#"{"META":{"offset":0},"BPMList":[{"bpm":60,"startTime":[0,0,1]}],
+        "judgeLineList":[{"Name":"fade","Texture":"line.png","father":-1,"isCover":1,"eventLayers":[{
+            "alphaEvents":[
+                {"easingType":1,"start":0,"end":255,"startTime":[1,0,1],"endTime":[3,0,1]},
+                {"easingType":1,"start":255,"end":0,"startTime":[0,0,1],"endTime":[2,0,1]},
+                {"easingType":1,"start":255,"end":0,"startTime":[2,1,2],"endTime":[4,0,1]}]}],
+        "notes":[]}]}"#;
+
+    #[test]
+    fn overlapping_alpha_events() {
+        let (mut chart, _) = parse(OVERLAPPING_ALPHA);
+        let alpha = &mut chart.lines[0].object.alpha;
+        // the second and third events are clipped to start at 2s and 3s respectively
+        for (time, expected) in [(0.5, 0.75), (1., 0.5), (2., 0.), (2.5, 0.5), (3., 1.), (3.5, 0.5), (5., 0.)] {
+            alpha.set_time(time);
+            assert!((alpha.now() - expected).abs() < 1e-4, "at {time}: {} != {expected}", alpha.now());
+        }
+    }
+
+    /// At 60 BPM, a speed of 10 from beat 1, which also applies before it, doubling at beat 2 and slowing down to 0 from beat 4 to 6
+    const SPEED_CHANGES: &str = r#"{"META":{"offset":0},"BPMList":[{"bpm":60,"startTime":[0,0,1]}],
+        "judgeLineList":[{"Name":"speed","Texture":"line.png","father":-1,"isCover":1,"eventLayers":[{
+            "speedEvents":[
+                {"start":10,"end":10,"startTime":[1,0,1],"endTime":[2,0,1]},
+                {"start":20,"end":20,"startTime":[2,0,1],"endTime":[4,0,1]},
+                {"start":20,"end":0,"startTime":[4,0,1],"endTime":[6,0,1]}]}],
+        "notes":[
+            {"type":1,"above":1,"startTime":[0,1,2],"endTime":[0,1,2],"positionX":0,"yOffset":0,"alpha":255,"size":1,"speed":1,
+                "isFake":0,"visibleTime":999999},
+            {"type":1,"above":1,"startTime":[3,0,1],"endTime":[3,0,1],"positionX":0,"yOffset":0,"alpha":255,"size":1,"speed":1,
+                "isFake":0,"visibleTime":999999},
+            {"type":1,"above":1,"startTime":[5,0,1],"endTime":[5,0,1],"positionX":0,"yOffset":0,"alpha":255,"size":1,"speed":1,
+                "isFake":0,"visibleTime":999999}]}]}"#;
+
+    #[test]
+    fn speed_changes() {
+        let (mut chart, _) = parse(SPEED_CHANGES);
+        let line = &mut chart.lines[0];
+        // travel distances in units of speed * seconds
+        for (time, expected) in [(0.5, 5.), (1., 10.), (2., 20.), (3., 40.), (4., 60.), (5., 75.), (6., 80.)] {
+            line.height.set_time(time);
+            let got = line.height.now() / SPEED_RATIO;
+            assert!((got - expected).abs() < 1e-2, "at {time}: {got} != {expected}");
+        }
+        let mut notes: Vec<_> = line.notes.iter().map(|it| (it.time, it.height / SPEED_RATIO)).collect();
+        notes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for ((time, got), expected) in notes.into_iter().zip([5., 40., 75.]) {
+            assert!((got - expected).abs() < 1e-2, "note at {time}: {got} != {expected}");
+        }
     }
-    process_lines(&mut lines);
-    Ok(Chart::new(rpe.meta.offset as f32 / 1000.0, lines, r, ChartSettings::default(), extra))
 }
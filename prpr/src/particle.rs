@@ -396,6 +396,8 @@ struct CpuParticle {
     frame: u16,
     initial_size: f32,
     color: Color,
+    // emitted since the last update, which shouldn't age by the elapsed frame time yet
+    fresh: bool,
 }
 
 pub struct Emitter {
@@ -615,6 +617,7 @@ impl Emitter {
             frame: 0,
             initial_size: r,
             color: self.config.base_color,
+            fresh: true,
         });
     }
 
@@ -639,9 +642,13 @@ impl Emitter {
                 ((self.time_passed - self.last_emit_time) / gap) as usize
             };
 
-            for _ in 0..spawn_amount {
+            // keep the leftover time so that the emission rate doesn't depend on frame time
+            if gap < 0.001 {
                 self.last_emit_time = self.time_passed;
-
+            } else {
+                self.last_emit_time += spawn_amount as f32 * gap;
+            }
+            for _ in 0..spawn_amount {
                 if self.particles_spawned < self.config.amount as u64 {
                     self.emit_particle(vec2(0.0, 0.0));
                 }
@@ -659,6 +666,7 @@ impl Emitter {
         }
 
         for (gpu, cpu) in self.gpu_particles.iter_mut().zip(&mut self.cpu_counterpart) {
+            let dt = if std::mem::take(&mut cpu.fresh) { 0. } else { dt };
             // TODO: this is not quite the way to apply acceleration, this is not
             // fps independent and just wrong
             cpu.velocity += cpu.velocity * self.config.linear_accel * dt;
@@ -717,14 +725,23 @@ impl Emitter {
         self.bindings.vertex_buffers[1].update(ctx, &self.gpu_particles[..]);
     }
 
-    /// Immediately emit N particles, ignoring "emitting" and "amount" params of EmitterConfig
+    /// Immediately emit N particles, ignoring "emitting" and "amount" params of EmitterConfig.
+    ///
+    /// The burst size doesn't depend on frame time, and the particles start aging from the next [`Emitter::draw`].
     pub fn emit(&mut self, pos: Vec2, n: usize) {
         for _ in 0..n {
             self.emit_particle(pos);
-            self.particles_spawned += 1;
         }
     }
 
+    /// Removes all particles alive, as if nothing has been emitted yet
+    pub fn clear(&mut self) {
+        self.gpu_particles.clear();
+        self.cpu_counterpart.clear();
+        self.last_emit_time = 0.0;
+        self.time_passed = 0.0;
+    }
+
     fn perform_render_pass(&mut self, quad_gl: &QuadGl, ctx: &mut Context) {
         ctx.apply_bindings(&self.bindings);
         ctx.apply_uniforms(&shader::Uniforms {
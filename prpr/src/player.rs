@@ -0,0 +1,102 @@
+//! Bare chart playback, for embedding prpr into other macroquad apps without going through scenes
+
+use crate::{
+    core::{BadNote, Chart, Resource},
+    ext::screen_aspect,
+    judge::{FcState, Judge, JudgeInput},
+    scene::draw_background,
+    ui::Ui,
+};
+use macroquad::prelude::*;
+
+/// Plays an already loaded chart. Timing is left to the caller, who feeds the chart time into [`GamePlayer::update`] every frame.
+pub struct GamePlayer {
+    pub res: Resource,
+    pub chart: Chart,
+    pub judge: Judge,
+    pub(crate) bad_notes: Vec<BadNote>,
+}
+
+impl GamePlayer {
+    pub fn new(res: Resource, chart: Chart, judge: Judge) -> Self {
+        Self {
+            res,
+            chart,
+            judge,
+            bad_notes: Vec::new(),
+        }
+    }
+
+    /// Judge line color reflecting the current full combo / all perfect state
+    pub fn judge_line_color(judge: &Judge, res: &Resource) -> Color {
+        let (perfect, good) = res.judge_line_colors();
+        let mut color = match judge.fc_state() {
+            FcState::AllPerfect => perfect,
+            FcState::FullCombo => good,
+            FcState::None => WHITE,
+        };
+        color.a *= res.alpha;
+        color
+    }
+
+    /// Advances to the given chart time (offset already applied), judging live input
+    pub fn update(&mut self, time: f32) {
+        self.res.time = time;
+        let input = self.judge.live_input(&self.res);
+        self.feed(input);
+        self.update_chart();
+    }
+
+    /// Judges one frame of input at the current `res.time`
+    pub fn feed(&mut self, input: JudgeInput) {
+        self.judge.update(&mut self.res, &mut self.chart, &mut self.bad_notes, input);
+    }
+
+    /// Updates the judge line color and the chart itself, to be called once per frame after judging
+    pub fn update_chart(&mut self) {
+        self.res.judge_line_color = Self::judge_line_color(&self.judge, &self.res);
+        self.chart.update(&mut self.res);
+    }
+
+    /// Renders the chart onto the resource's camera. `dt` is the real time elapsed since the last call, used by particles.
+    pub fn render(&mut self, ui: &mut Ui, dt: f32) {
+        if self.res.update_size((screen_width() as u32, screen_height() as u32)) {
+            set_camera(&self.res.camera);
+        }
+        push_camera_state();
+        self.render_background(self.res.camera.render_target);
+        pop_camera_state();
+        self.render_chart(ui);
+        self.render_overlay(dt);
+    }
+
+    /// Clears the target and draws the background. The caller is expected to save and restore the camera state around this.
+    pub fn render_background(&self, target: Option<RenderTarget>) {
+        set_camera(&Camera2D {
+            zoom: vec2(1., -screen_aspect()),
+            render_target: target,
+            ..Default::default()
+        });
+        clear_background(BLACK);
+        // half of the playfield dim, which keeps the default look (0.3 and 0.6)
+        draw_background(*self.res.background, self.res.background_dim() / 2.);
+    }
+
+    /// Draws the playfield dim and the chart
+    pub fn render_chart(&mut self, ui: &mut Ui) {
+        let res = &mut self.res;
+        let h = 1. / res.aspect_ratio;
+        draw_rectangle(-1., -h, 2., h * 2., Color::new(0., 0., 0., res.alpha * res.background_dim()));
+        self.chart.render(ui, res);
+    }
+
+    /// Draws what goes above the chart: bad notes, autoplay touches and particles
+    pub fn render_overlay(&mut self, dt: f32) {
+        let res = &mut self.res;
+        self.bad_notes.retain(|dummy| dummy.render(res));
+        self.judge.render_autoplay_touches(res);
+        if res.config.particle {
+            res.emitter.draw(dt);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{
+    bin::{BinaryData, BinaryReader, BinaryWriter},
+    core::{Chart, Resource},
+    judge::{Judge, JudgeInput},
+};
+use anyhow::{bail, Result};
+use macroquad::prelude::{vec2, Rect, Touch, TouchPhase};
+use std::io::{Read, Write};
+
+const REPLAY_VERSION: u8 = 2;
+
+/// Judge input of a single frame, along with the chart time it's fed at
+pub struct ReplayFrame {
+    pub time: f32,
+    pub input: JudgeInput,
+}
+
+/// A recorded run, which can be played back by feeding its frames into [`crate::judge::Judge::update`]
+#[derive(Default)]
+pub struct Replay {
+    /// Final score of the recorded run
+    pub score: u32,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    pub fn load(bytes: &[u8]) -> Result<Self> {
+        BinaryReader::new(bytes).read()
+    }
+
+    pub fn save(&self) -> Result<Vec<u8>> {
+        let mut w = BinaryWriter::new(Vec::new());
+        w.write(self)?;
+        Ok(w.0)
+    }
+
+    /// Judges the whole run again from the start and returns the final score, e.g. to check the recorded one.
+    ///
+    /// Judging depends on nothing but the chart, the config and the frames, each fed at its recorded time with no clock involved, so the
+    /// same score comes out every time. The config has to be the one the run is recorded with (speed, judge windows, input offset and
+    /// so on), with autoplay off. What may still differ is floating point math across platforms: line transforms and easings go through
+    /// `sin`, `cos` and `powf` of the platform's math library, which aren't guaranteed to agree in the last bit, and a difference there
+    /// can flip a note right at the edge of a judge window. Touch times are also kept as `f32` in replays.
+    pub fn rejudge(&self, judge: &mut Judge, res: &mut Resource, chart: &mut Chart) -> u32 {
+        judge.reset();
+        chart.reset();
+        let mut bad_notes = Vec::new();
+        for frame in &self.frames {
+            res.time = frame.time;
+            judge.update(res, chart, &mut bad_notes, frame.input.clone());
+        }
+        judge.score()
+    }
+}
+
+impl BinaryData for Touch {
+    fn read_binary<R: Read>(r: &mut BinaryReader<R>) -> Result<Self> {
+        let id = r.uleb()?;
+        let phase = match r.read::<u8>()? {
+            0 => TouchPhase::Started,
+            1 => TouchPhase::Stationary,
+            2 => TouchPhase::Moved,
+            3 => TouchPhase::Ended,
+            4 => TouchPhase::Cancelled,
+            x => bail!("Invalid touch phase: {x}"),
+        };
+        let position = vec2(r.read()?, r.read()?);
+        let time = r.read::<f32>()? as f64;
+        Ok(Self { id, phase, position, time })
+    }
+
+    fn write_binary<W: Write>(&self, w: &mut BinaryWriter<W>) -> Result<()> {
+        w.uleb(self.id)?;
+        w.write_val(match self.phase {
+            TouchPhase::Started => 0u8,
+            TouchPhase::Stationary => 1,
+            TouchPhase::Moved => 2,
+            TouchPhase::Ended => 3,
+            TouchPhase::Cancelled => 4,
+        })?;
+        w.write_val(self.position.x)?;
+        w.write_val(self.position.y)?;
+        w.write_val(self.time as f32)?;
+        Ok(())
+    }
+}
+
+impl BinaryData for Rect {
+    fn read_binary<R: Read>(r: &mut BinaryReader<R>) -> Result<Self> {
+        Ok(Self::new(r.read()?, r.read()?, r.read()?, r.read()?))
+    }
+
+    fn write_binary<W: Write>(&self, w: &mut BinaryWriter<W>) -> Result<()> {
+        w.write_val(self.x)?;
+        w.write_val(self.y)?;
+        w.write_val(self.w)?;
+        w.write_val(self.h)?;
+        Ok(())
+    }
+}
+
+impl BinaryData for JudgeInput {
+    fn read_binary<R: Read>(r: &mut BinaryReader<R>) -> Result<Self> {
+        Ok(Self {
+            touches: r.array()?,
+            events: r.array()?,
+            key_delta: r.read()?,
+            keys_down: r.uleb()? as u32,
+            exclusions: r.array()?,
+        })
+    }
+
+    fn write_binary<W: Write>(&self, w: &mut BinaryWriter<W>) -> Result<()> {
+        w.array(&self.touches)?;
+        w.array(&self.events)?;
+        w.write_val(self.key_delta)?;
+        w.uleb(self.keys_down as _)?;
+        w.array(&self.exclusions)?;
+        Ok(())
+    }
+}
+
+impl BinaryData for ReplayFrame {
+    fn read_binary<R: Read>(r: &mut BinaryReader<R>) -> Result<Self> {
+        Ok(Self {
+            time: r.read()?,
+            input: r.read()?,
+        })
+    }
+
+    fn write_binary<W: Write>(&self, w: &mut BinaryWriter<W>) -> Result<()> {
+        w.write_val(self.time)?;
+        w.write(&self.input)?;
+        Ok(())
+    }
+}
+
+impl BinaryData for Replay {
+    fn read_binary<R: Read>(r: &mut BinaryReader<R>) -> Result<Self> {
+        let version = r.read::<u8>()?;
+        if version != REPLAY_VERSION {
+            bail!("Unsupported replay version: {version}");
+        }
+        Ok(Self {
+            score: r.uleb()? as u32,
+            frames: r.array()?,
+        })
+    }
+
+    fn write_binary<W: Write>(&self, w: &mut BinaryWriter<W>) -> Result<()> {
+        w.write_val(REPLAY_VERSION)?;
+        w.uleb(self.score as _)?;
+        w.array(&self.frames)?;
+        Ok(())
+    }
+}
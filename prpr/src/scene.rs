@@ -1,5 +1,8 @@
 crate::tl_file!("scene" ttl);
 
+mod calibration;
+pub use calibration::CalibrationScene;
+
 mod ending;
 pub use ending::{EndingScene, RecordUpdateState};
 
@@ -9,8 +12,11 @@ pub use game::{GameMode, GameScene, SimpleRecord, FFMPEG_PATH};
 mod loading;
 pub use loading::{BasicPlayer, LoadingScene};
 
+mod select;
+pub use select::SelectScene;
+
 use crate::{
-    ext::{draw_image, poll_future, screen_aspect, LocalTask, SafeTexture, ScaleType},
+    ext::{draw_image, draw_text_aligned, poll_future, screen_aspect, LocalTask, SafeTexture, ScaleType},
     judge::Judge,
     time::TimeManager,
     ui::{BillBoard, Dialog, Message, MessageHandle, MessageKind, Ui},
@@ -20,16 +26,24 @@ use cfg_if::cfg_if;
 use macroquad::prelude::*;
 use std::{any::Any, cell::RefCell, future::Future, sync::Mutex};
 
+/// Transition requested by the top scene of [`Main`] through [`Scene::next_scene`]
 #[derive(Default)]
 pub enum NextScene {
+    /// Stay on the current scene
     #[default]
     None,
+    /// Pop the current scene, going back to the one below, which is entered again
     Pop,
+    /// Pop the given number of scenes
     PopN(usize),
+    /// Pop the current scene, handing the result to [`Scene::on_result`] of the one below before it's entered again
     PopWithResult(Box<dyn Any>),
+    /// Pop the given number of scenes, handing the result to the one that becomes the top
     PopNWithResult(usize, Box<dyn Any>),
     Exit,
+    /// Push a scene on top of the current one, which gets back to where it was after the new scene is popped
     Overlay(Box<dyn Scene>),
+    /// Replace the current scene with a new one
     Replace(Box<dyn Scene>),
 }
 
@@ -321,6 +335,7 @@ impl<F: FnMut() -> Option<RenderTarget>> RenderTargetChooser for F {
     }
 }
 
+/// Drives a stack of scenes, of which only the top one is updated and rendered
 pub struct Main {
     pub scenes: Vec<Box<dyn Scene>>,
     times: Vec<f64>,
@@ -330,6 +345,8 @@ pub struct Main {
     last_update_time: f64,
     should_exit: bool,
     pub show_billboard: bool,
+    /// Draws the framerate and frame time at the top left corner, toggled by F3
+    pub show_fps: bool,
     touches: Option<Vec<Touch>>,
 }
 
@@ -354,6 +371,7 @@ impl Main {
             last_update_time,
             should_exit: false,
             show_billboard: true,
+            show_fps: false,
             touches: None,
         })
     }
@@ -408,6 +426,13 @@ impl Main {
             }
         }
         Judge::on_new_frame();
+        if is_key_pressed(KeyCode::F3) {
+            self.show_fps ^= true;
+        }
+        // mobile platforms pause through `Main::pause` instead. Resuming is left to the scene so that it needs an explicit action.
+        if Judge::take_minimized() && cfg!(not(any(target_os = "android", target_os = "ios"))) {
+            self.scenes.last_mut().unwrap().pause(&mut self.tm)?;
+        }
         let mut touches = Judge::get_touches();
         touches.iter_mut().for_each(f);
         if !touches.is_empty() {
@@ -474,6 +499,11 @@ impl Main {
                 guard.0.render(ui, t);
             });
         }
+        if self.show_fps {
+            let dt = get_frame_time();
+            let text = format!("{} FPS / {:.1}ms", (1. / dt).round() as u32, dt * 1000.);
+            draw_text_aligned(ui, &text, -1. + 0.02, -ui.top + 0.02, (0., 0.), 0.35, Color::new(1., 1., 1., 0.8));
+        }
         DIALOG.with(|it| {
             if let Some(dialog) = it.borrow_mut().as_mut() {
                 dialog.render(ui, self.tm.now() as _);
@@ -497,11 +527,14 @@ impl Main {
     }
 }
 
-fn draw_background(tex: Texture2D) {
+/// Dim of backgrounds outside of the playfield
+pub const BACKGROUND_DIM: f32 = 0.3;
+
+pub fn draw_background(tex: Texture2D, dim: f32) {
     let asp = screen_aspect();
     let top = 1. / asp;
     draw_image(tex, Rect::new(-1., -top, 2., top * 2.), ScaleType::CropCenter);
-    draw_rectangle(-1., -top, 2., top * 2., Color::new(0., 0., 0., 0.3));
+    draw_rectangle(-1., -top, 2., top * 2., Color::new(0., 0., 0., dim));
 }
 
 fn draw_illustration(tex: Texture2D, x: f32, y: f32, w: f32, h: f32, color: Color) -> Rect {
@@ -0,0 +1,212 @@
+crate::tl_file!("game");
+
+use super::{NextScene, Scene};
+use crate::{
+    config::Config,
+    ext::{create_audio_manger, screen_aspect, semi_white},
+    judge::Judge,
+    time::TimeManager,
+    ui::Ui,
+};
+use anyhow::Result;
+use macroquad::prelude::*;
+use sasa::{AudioClip, AudioManager, Music, MusicParams};
+
+const CALIBRATION_TAPS: usize = 20;
+// cali.ogg clicks at 1s in every 2s loop
+const CLICK_PERIOD: f64 = 2.;
+const CLICK_AT: f64 = 1.;
+const MAX_DIFF: f64 = 0.5;
+// taps further than this from the median are discarded as outliers
+const OUTLIER_RANGE: f32 = 0.08;
+// half width of the offset distribution bar, in seconds
+const BAR_RANGE: f32 = 0.2;
+
+pub struct CalibrationScene {
+    audio: AudioManager,
+    cali: Music,
+    cali_tm: TimeManager,
+    target: Option<RenderTarget>,
+
+    diffs: Vec<f32>,
+    next_scene: Option<NextScene>,
+}
+
+impl CalibrationScene {
+    pub async fn new(config: &Config) -> Result<Self> {
+        let mut audio = create_audio_manger(config)?;
+        let cali = audio.create_music(
+            AudioClip::new(load_file("cali.ogg").await?)?,
+            MusicParams {
+                loop_: true,
+                amplifier: config.volume_music,
+                ..Default::default()
+            },
+        )?;
+        let mut cali_tm = TimeManager::new(1., true);
+        cali_tm.force = 3e-2;
+        Ok(Self {
+            audio,
+            cali,
+            cali_tm,
+            target: None,
+
+            diffs: Vec::new(),
+            next_scene: None,
+        })
+    }
+
+    /// The median signed tap error with outliers discarded, available once enough taps are recorded
+    pub fn suggested_offset(&self) -> Option<f32> {
+        if self.diffs.len() < CALIBRATION_TAPS {
+            return None;
+        }
+        fn median(mut values: Vec<f32>) -> f32 {
+            values.sort_by(f32::total_cmp);
+            let mid = values.len() / 2;
+            if values.len() % 2 == 0 {
+                (values[mid - 1] + values[mid]) / 2.
+            } else {
+                values[mid]
+            }
+        }
+        let center = median(self.diffs.clone());
+        let kept: Vec<_> = self.diffs.iter().copied().filter(|it| (it - center).abs() <= OUTLIER_RANGE).collect();
+        Some(if kept.is_empty() { center } else { median(kept) })
+    }
+
+    fn retry(&mut self) {
+        self.diffs.clear();
+    }
+
+    fn confirm(&mut self) {
+        if let Some(offset) = self.suggested_offset() {
+            self.next_scene = Some(NextScene::PopWithResult(Box::new(Some(offset))));
+        }
+    }
+
+    fn tap(&mut self) {
+        let t = self.cali_tm.now();
+        let diff = t - (((t - CLICK_AT) / CLICK_PERIOD).round() * CLICK_PERIOD + CLICK_AT);
+        if diff.abs() <= MAX_DIFF {
+            self.diffs.push(diff as f32);
+        }
+    }
+}
+
+impl Scene for CalibrationScene {
+    fn enter(&mut self, _tm: &mut TimeManager, target: Option<RenderTarget>) -> Result<()> {
+        self.target = target;
+        self.cali.seek_to(0.)?;
+        self.cali.play()?;
+        self.cali_tm.reset();
+        Ok(())
+    }
+
+    fn pause(&mut self, _tm: &mut TimeManager) -> Result<()> {
+        self.cali_tm.pause();
+        self.cali.pause()?;
+        Ok(())
+    }
+
+    fn resume(&mut self, _tm: &mut TimeManager) -> Result<()> {
+        self.cali_tm.resume();
+        self.cali.play()?;
+        Ok(())
+    }
+
+    fn update(&mut self, _tm: &mut TimeManager) -> Result<()> {
+        self.audio.recover_if_needed()?;
+        if !self.cali.paused() {
+            let pos = self.cali.position() as f64;
+            let now = self.cali_tm.now();
+            if now > CLICK_PERIOD {
+                self.cali_tm.seek_to(now - CLICK_PERIOD);
+                self.cali_tm.dont_wait();
+            }
+            let now = self.cali_tm.now();
+            if now - pos >= -1. {
+                self.cali_tm.update(pos);
+            }
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            self.next_scene = Some(NextScene::PopWithResult(Box::new(None::<f32>)));
+            return Ok(());
+        }
+        if self.suggested_offset().is_some() {
+            // touches are handled by the buttons
+            if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::KpEnter) {
+                self.confirm();
+            } else if is_key_pressed(KeyCode::R) {
+                self.retry();
+            }
+            return Ok(());
+        }
+        let tapped = Judge::get_touches().iter().any(|it| it.phase == TouchPhase::Started)
+            || get_last_key_pressed().map_or(false, |it| !matches!(it, KeyCode::Escape));
+        if tapped {
+            self.tap();
+        }
+        Ok(())
+    }
+
+    fn render(&mut self, _tm: &mut TimeManager, ui: &mut Ui) -> Result<()> {
+        set_camera(&Camera2D {
+            zoom: vec2(1., -screen_aspect()),
+            render_target: self.target,
+            ..Default::default()
+        });
+        clear_background(BLACK);
+        ui.text(tl!("calibration")).pos(0., -0.3).anchor(0.5, 0.5).size(0.8).draw();
+
+        // live distribution of tap offsets, early to the left
+        let (bar_w, bar_y) = (0.8, 0.3);
+        ui.fill_rect(Rect::new(-bar_w, bar_y - 0.002, bar_w * 2., 0.004), semi_white(0.4));
+        ui.fill_rect(Rect::new(-0.002, bar_y - 0.03, 0.004, 0.06), semi_white(0.7));
+        for diff in &self.diffs {
+            let x = (diff / BAR_RANGE).clamp(-1., 1.) * bar_w;
+            ui.fill_rect(Rect::new(x - 0.003, bar_y - 0.02, 0.006, 0.04), Color::new(0.45, 0.75, 1., 0.6));
+        }
+
+        if let Some(offset) = self.suggested_offset() {
+            let x = (offset / BAR_RANGE).clamp(-1., 1.) * bar_w;
+            ui.fill_rect(Rect::new(x - 0.004, bar_y - 0.04, 0.008, 0.08), Color::new(1., 0.85, 0.3, 1.));
+            ui.text(tl!("calibration-result", "offset" => (offset * 1000.).round() as i32))
+                .pos(0., 0.)
+                .anchor(0.5, 0.5)
+                .size(0.6)
+                .draw();
+            ui.text(tl!("calibration-confirm"))
+                .pos(0., 0.1)
+                .anchor(0.5, 0.5)
+                .size(0.4)
+                .color(semi_white(0.7))
+                .draw();
+            let (w, h, spacing) = (0.3, 0.08, 0.02);
+            if ui.button("retry", Rect::new(-w - spacing / 2., 0.16, w, h), tl!("calibration-retry")) {
+                self.retry();
+            }
+            if ui.button("save", Rect::new(spacing / 2., 0.16, w, h), tl!("offset-save")) {
+                self.confirm();
+            }
+        } else {
+            ui.text(tl!("calibration-hint")).pos(0., 0.).anchor(0.5, 0.5).size(0.5).draw();
+            ui.text(format!("{}/{CALIBRATION_TAPS}", self.diffs.len()))
+                .pos(0., 0.15)
+                .anchor(0.5, 0.5)
+                .size(0.5)
+                .color(semi_white(0.7))
+                .draw();
+        }
+        Ok(())
+    }
+
+    fn next_scene(&mut self, _tm: &mut TimeManager) -> NextScene {
+        if let Some(next) = self.next_scene.take() {
+            let _ = self.cali.pause();
+            next
+        } else {
+            NextScene::None
+        }
+    }
+}
@@ -1,22 +1,23 @@
 crate::tl_file!("ending");
 
-use super::{draw_background, draw_illustration, game::SimpleRecord, loading::UploadFn, NextScene, Scene};
+use super::{draw_background, draw_illustration, game::SimpleRecord, loading::UploadFn, NextScene, Scene, BACKGROUND_DIM};
 use crate::{
-    config::Config,
+    config::{Config, ScoreMode},
     ext::{
-        create_audio_manger, draw_parallelogram, draw_parallelogram_ex, draw_text_aligned, screen_aspect, SafeTexture, ScaleType, PARALLELOGRAM_SLOPE,
+        create_audio_manger, draw_parallelogram, draw_parallelogram_ex, draw_text_aligned, draw_text_aligned_with_font, screen_aspect, SafeTexture,
+        ScaleType, PARALLELOGRAM_SLOPE,
     },
     info::ChartInfo,
-    judge::{icon_index, Judge, PlayResult},
+    judge::{icon_index, Judge, PlayResult, RANK_NAMES},
     scene::show_message,
     task::Task,
-    ui::{Dialog, MessageHandle, Ui},
+    ui::{Dialog, MessageHandle, TextPainter, Ui},
 };
 use anyhow::Result;
 use macroquad::prelude::*;
 use sasa::{AudioClip, AudioManager, Music, MusicParams};
 use serde::Deserialize;
-use std::{cell::RefCell, ops::DerefMut};
+use std::{cell::RefCell, ops::DerefMut, rc::Rc};
 
 #[derive(Deserialize)]
 pub struct RecordUpdateState {
@@ -38,11 +39,13 @@ pub struct EndingScene {
     bgm: Music,
 
     info: ChartInfo,
+    chart_font: Option<Rc<RefCell<TextPainter>>>,
     result: PlayResult,
     player_name: String,
     player_rks: Option<f32>,
     challenge_texture: SafeTexture,
     challenge_rank: u32,
+    score_mode: ScoreMode,
     autoplay: bool,
     speed: f32,
     next: u8, // 0 -> none, 1 -> pop, 2 -> exit
@@ -64,6 +67,7 @@ impl EndingScene {
         icon_retry: SafeTexture,
         icon_proceed: SafeTexture,
         info: ChartInfo,
+        chart_font: Option<Rc<RefCell<TextPainter>>>,
         result: PlayResult,
         challenge_texture: SafeTexture,
         config: &Config,
@@ -73,6 +77,16 @@ impl EndingScene {
         record_data: Option<Vec<u8>>,
         record: Option<SimpleRecord>,
     ) -> Result<Self> {
+        info!(
+            "Result: {} {:0width$} ({:.2}%), max combo {}/{}, counts {:?}",
+            RANK_NAMES[icon_index(result.score, config.score_mode.max_score(), result.num_of_notes == result.max_combo)],
+            result.score,
+            result.accuracy * 100.,
+            result.max_combo,
+            result.num_of_notes,
+            result.counts,
+            width = config.score_mode.digits()
+        );
         let mut audio = create_audio_manger(config)?;
         let bgm = audio.create_music(
             bgm,
@@ -108,11 +122,13 @@ impl EndingScene {
             rated: upload_task.is_some(),
 
             info,
+            chart_font,
             result,
             player_name: config.player_name.clone(),
             player_rks,
             challenge_texture,
             challenge_rank: config.challenge_rank,
+            score_mode: config.score_mode,
             autoplay: config.autoplay,
             speed: config.speed,
             next: 0,
@@ -197,7 +213,7 @@ impl Scene for EndingScene {
             render_target: self.target,
             ..Default::default()
         });
-        draw_background(*self.background);
+        draw_background(*self.background, BACKGROUND_DIM);
 
         fn ran(t: f32, l: f32, r: f32) -> f32 {
             ((t - l) / (r - l)).clamp(0., 1.)
@@ -217,16 +233,32 @@ impl Scene for EndingScene {
             Color::new(0., 0., 0., 0.7),
             false,
         );
-        let rr = draw_text_aligned(ui, &self.info.level, r.right() - r.h / 7. * 13. * 0.13 - 0.01, r.bottom() - top / 20., (1., 1.), 0.46, WHITE);
+        let mut font = self.chart_font.as_ref().map(|it| it.borrow_mut());
+        let rr = draw_text_aligned_with_font(
+            ui,
+            font.as_deref_mut(),
+            &self.info.level,
+            r.right() - r.h / 7. * 13. * 0.13 - 0.01,
+            r.bottom() - top / 20.,
+            (1., 1.),
+            0.46,
+            WHITE,
+        );
         let p = (r.x + 0.04, r.bottom() - top / 20.);
         let mw = rr.x - 0.02 - p.0;
         let mut text = ui.text(&self.info.name).pos(p.0, p.1).anchor(0., 1.).size(0.7);
-        if text.measure().w <= mw {
-            text.draw();
+        if text.measure_with_font(font.as_deref_mut()).w <= mw {
+            text.draw_with_font(font.as_deref_mut());
         } else {
             drop(text);
-            ui.text(&self.info.name).pos(p.0, p.1).anchor(0., 1.).size(0.5).max_width(mw).draw();
+            ui.text(&self.info.name)
+                .pos(p.0, p.1)
+                .anchor(0., 1.)
+                .size(0.5)
+                .max_width(mw)
+                .draw_with_font(font.as_deref_mut());
         }
+        drop(font);
         gl.pop_model_matrix();
 
         let dx = 0.06;
@@ -249,7 +281,7 @@ impl Scene for EndingScene {
                 format!(
                     "PHIRA {spd}  {}",
                     if state.best {
-                        format!("NEW BEST +{:07}", state.improvement)
+                        format!("NEW BEST +{:0width$}", state.improvement, width = self.score_mode.digits())
                     } else {
                         String::new()
                     }
@@ -258,8 +290,8 @@ impl Scene for EndingScene {
                 "Uploading…".to_owned()
             };
             let r = draw_text_aligned(ui, &text, main.x + dx, main.bottom() - 0.035, (0., 1.), 0.34, WHITE);
-            let r = draw_text_aligned(ui, &format!("{:07}", res.score), r.x, r.y - 0.023, (0., 1.), 1., WHITE);
-            let icon = icon_index(res.score, res.num_of_notes == res.max_combo);
+            let r = draw_text_aligned(ui, &format!("{:0width$}", res.score, width = self.score_mode.digits()), r.x, r.y - 0.023, (0., 1.), 1., WHITE);
+            let icon = icon_index(res.score, self.score_mode.max_score(), res.num_of_notes == res.max_combo);
             let p = ran(now, 1.4, 1.9).powi(2);
             let s = main.h * 0.67;
             let ct = (main.right() - main.h * slope - s / 2., r.bottom() + 0.02 - s / 2.);
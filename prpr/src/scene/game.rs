@@ -3,7 +3,6 @@
 crate::tl_file!("game");
 
 use super::{
-    draw_background,
     ending::RecordUpdateState,
     loading::{BasicPlayer, UploadFn},
     request_input, return_input, show_message, take_input, EndingScene, NextScene, Scene,
@@ -11,15 +10,17 @@ use super::{
 use crate::{
     bin::{BinaryReader, BinaryWriter},
     config::Config,
-    core::{copy_fbo, BadNote, Chart, ChartExtra, Effect, Point, Resource, UIElement, Vector, StaticTween, TweenFunction},
+    core::{blit_fbo, Chart, ChartExtra, Effect, Point, Resource, UIElement, Vector, StaticTween, TweenFunction},
     ext::{parse_time, screen_aspect, semi_white, RectExt, SafeTexture},
     fs::FileSystem,
     info::{ChartFormat, ChartInfo},
-    judge::Judge,
-    parse::{parse_extra, parse_pec, parse_phigros, parse_rpe},
+    judge::{FcState, Judge},
+    parse::{detect_format, parse_extra, parse_osu, parse_pec, parse_phigros, parse_rpe, ParseOptions},
+    player::GamePlayer,
+    replay::{Replay, ReplayFrame},
     task::Task,
     time::TimeManager,
-    ui::{RectButton, Ui},
+    ui::{RectButton, TextPainter, Ui},
 };
 use anyhow::{bail, Context, Result};
 use concat_string::concat_string;
@@ -29,6 +30,7 @@ use sasa::{Music, MusicParams};
 use serde::{Deserialize, Serialize};
 use std::{
     any::Any,
+    cell::RefCell,
     fs::File,
     io::{Cursor, ErrorKind},
     ops::{DerefMut, Range},
@@ -39,6 +41,10 @@ use std::{
 };
 
 const PAUSE_CLICK_INTERVAL: f32 = 0.7;
+const PAUSE_WIDTH: f32 = 0.012;
+const PAUSE_HEIGHT: f32 = PAUSE_WIDTH * 3.375;
+/// Taps within this distance to the center of the pause button go to the button
+const PAUSE_RADIUS: f32 = 0.05;
 
 #[cfg(feature = "closed")]
 mod inner;
@@ -49,6 +55,8 @@ pub static FFMPEG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
 
 const WAIT_TIME: f32 = 0.5;
 const AFTER_TIME: f32 = 0.7;
+const SPEED_HINT_TIME: f32 = 1.;
+const REPLAY_SCORE_TOLERANCE: u32 = 100;
 
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -114,9 +122,7 @@ pub struct GameScene {
     next_scene: Option<NextScene>,
 
     pub mode: GameMode,
-    pub res: Resource,
-    pub chart: Chart,
-    pub judge: Judge,
+    pub game: GamePlayer,
     pub gl: InternalGlContext<'static>,
     player: Option<BasicPlayer>,
     chart_bytes: Vec<u8>,
@@ -138,18 +144,119 @@ pub struct GameScene {
     last_update_time: f64,
     pause_rewind: Option<f64>,
     pause_first_time: f32,
+    // the touch started on the pause button, which pauses if it's also released there
+    pause_touch: Option<u64>,
+    pause_selected: i32,
+    // practice checkpoint in music time, retrying restarts from here
+    checkpoint: Option<f32>,
+    speed_changed: Option<f64>,
+    offset_changed: Option<f64>,
+    // A/B loop points, in chart time
+    loop_points: (Option<f32>, Option<f32>),
 
-    bad_notes: Vec<BadNote>,
+    // the replay being played back, along with the index of the next frame
+    replay: Option<(Replay, usize)>,
+    recording: Option<Replay>,
 
     upload_fn: Option<UploadFn>,
 }
 
+/// Actions shared by keyboard and gamepad
+#[derive(Clone, Copy, PartialEq)]
+enum InputAction {
+    TogglePause,
+    /// Seeks by the given amount of seconds
+    Seek(f32),
+    ChangeSpeed(f32),
+    /// Shifts the chart offset by the given amount of seconds
+    NudgeOffset(f32),
+    /// Sets loop point A, then B, and clears both on the third press
+    SetLoopPoint,
+    Quit,
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+thread_local! {
+    static GILRS: RefCell<Option<gilrs::Gilrs>> = RefCell::new(match gilrs::Gilrs::new() {
+        Ok(gilrs) => Some(gilrs),
+        Err(err) => {
+            warn!("Failed to initialize gamepad support: {err:?}");
+            None
+        }
+    });
+}
+
+impl InputAction {
+    /// Actions triggered in this frame. Keys used for keyboard play don't trigger actions, and pausing moves from Space to Escape while it's enabled.
+    fn poll(play_keys: &[KeyCode]) -> Vec<Self> {
+        const KEYS: [(KeyCode, InputAction); 11] = [
+            (KeyCode::Space, InputAction::TogglePause),
+            (KeyCode::Left, InputAction::Seek(-1.)),
+            (KeyCode::Right, InputAction::Seek(5.)),
+            (KeyCode::Equal, InputAction::ChangeSpeed(0.1)),
+            (KeyCode::KpAdd, InputAction::ChangeSpeed(0.1)),
+            (KeyCode::Minus, InputAction::ChangeSpeed(-0.1)),
+            (KeyCode::KpSubtract, InputAction::ChangeSpeed(-0.1)),
+            (KeyCode::LeftBracket, InputAction::NudgeOffset(-0.005)),
+            (KeyCode::RightBracket, InputAction::NudgeOffset(0.005)),
+            (KeyCode::L, InputAction::SetLoopPoint),
+            (KeyCode::Q, InputAction::Quit),
+        ];
+        let mut actions: Vec<Self> = Vec::new();
+        let mut push = |action| {
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+        };
+        let keyboard_play = !play_keys.is_empty();
+        for (key, action) in KEYS {
+            let key = if keyboard_play && key == KeyCode::Space { KeyCode::Escape } else { key };
+            if !play_keys.contains(&key) && is_key_pressed(key) {
+                push(action);
+            }
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        GILRS.with(|it| {
+            use gilrs::{Button, EventType};
+            let mut guard = it.borrow_mut();
+            let Some(gilrs) = guard.as_mut() else {
+                return;
+            };
+            while let Some(event) = gilrs.next_event() {
+                let EventType::ButtonPressed(button, _) = event.event else {
+                    continue;
+                };
+                push(match button {
+                    Button::South => Self::TogglePause,
+                    Button::LeftTrigger => Self::Seek(-1.),
+                    Button::RightTrigger => Self::Seek(1.),
+                    Button::DPadUp => Self::ChangeSpeed(0.1),
+                    Button::DPadDown => Self::ChangeSpeed(-0.1),
+                    Button::North => Self::SetLoopPoint,
+                    Button::Select => Self::Quit,
+                    _ => continue,
+                });
+            }
+        });
+        actions
+    }
+}
+
 macro_rules! reset {
     ($self:ident, $res:expr, $tm:ident) => {{
-        $self.bad_notes.clear();
-        $self.judge.reset();
-        $self.chart.reset();
-        $res.judge_line_color = Color::from_hex($res.res_pack.info.color_perfect);
+        $self.game.bad_notes.clear();
+        $res.emitter.clear();
+        $self.pause_rewind = None;
+        $self.game.judge.end_practice();
+        $self.game.judge.reset();
+        if let Some((_, cursor)) = &mut $self.replay {
+            *cursor = 0;
+        }
+        if let Some(recording) = &mut $self.recording {
+            recording.frames.clear();
+        }
+        $self.game.chart.reset();
+        $res.judge_line_color = $res.judge_line_colors().0;
         $self.music.pause()?;
         $self.music.seek_to(0.)?;
         $tm.reset();
@@ -174,7 +281,7 @@ impl GameScene {
         bail!("Cannot find chart file")
     }
 
-    pub async fn load_chart(fs: &mut dyn FileSystem, info: &ChartInfo) -> Result<(Chart, Vec<u8>, ChartFormat)> {
+    pub async fn load_chart(fs: &mut dyn FileSystem, info: &ChartInfo, options: ParseOptions) -> Result<(Chart, Vec<u8>, ChartFormat)> {
         let extra = fs.load_file("extra.json").await.ok().map(String::from_utf8).transpose()?;
         let extra = if let Some(extra) = extra {
             let ffmpeg: PathBuf = FFMPEG_PATH.lock().unwrap().to_owned().unwrap_or_else(|| "ffmpeg".into());
@@ -192,32 +299,36 @@ impl GameScene {
             ChartExtra::default()
         };
         let bytes = Self::load_chart_bytes(fs, info).await.context("Failed to load chart")?;
-        let format = info.format.clone().unwrap_or_else(|| {
-            if let Ok(text) = String::from_utf8(bytes.clone()) {
-                if text.starts_with('{') {
-                    if text.contains("\"META\"") {
-                        ChartFormat::Rpe
-                    } else {
-                        ChartFormat::Pgr
-                    }
-                } else {
-                    ChartFormat::Pec
-                }
-            } else {
-                ChartFormat::Pbc
-            }
-        });
-        let mut chart = match format {
-            ChartFormat::Rpe => parse_rpe(&String::from_utf8_lossy(&bytes), fs, extra).await,
-            ChartFormat::Pgr => parse_phigros(&String::from_utf8_lossy(&bytes), extra),
-            ChartFormat::Pec => parse_pec(&String::from_utf8_lossy(&bytes), extra),
+        let format = match info.format {
+            ChartFormat::Auto => match String::from_utf8(bytes.clone()) {
+                Ok(text) => detect_format(&text),
+                Err(_) => ChartFormat::Pbc,
+            },
+            ref format => format.clone(),
+        };
+        let (mut chart, warnings) = match format {
+            ChartFormat::Rpe => parse_rpe(&String::from_utf8_lossy(&bytes), fs, extra, options).await,
+            ChartFormat::Pgr => parse_phigros(&String::from_utf8_lossy(&bytes), extra, options),
+            ChartFormat::Pec => parse_pec(&String::from_utf8_lossy(&bytes), extra, options),
+            ChartFormat::Osu => parse_osu(&String::from_utf8_lossy(&bytes), extra).map(|it| (it, Vec::new())),
             ChartFormat::Pbc => {
                 let mut r = BinaryReader::new(Cursor::new(&bytes));
-                r.read()
+                r.read().map(|it| (it, Vec::new()))
             }
+            ChartFormat::Auto => unreachable!(),
         }?;
+        if !warnings.is_empty() {
+            for warning in &warnings {
+                warn!("{warning}");
+            }
+            warn!("Skipped {} invalid notes or events", warnings.len());
+        }
         chart.load_textures(fs).await?;
-        chart.settings.hold_partial_cover = info.hold_partial_cover;
+        chart.load_hitsounds(fs).await;
+        chart.settings.hold_partial_cover |= info.hold_partial_cover;
+        for warning in chart.validate() {
+            warn!("{warning}");
+        }
         Ok((chart, bytes, format))
     }
 
@@ -229,6 +340,7 @@ impl GameScene {
         player: Option<BasicPlayer>,
         background: SafeTexture,
         illustration: SafeTexture,
+        chart_font: Option<Rc<RefCell<TextPainter>>>,
         get_size_fn: Rc<dyn Fn() -> (u32, u32)>,
         upload_fn: Option<UploadFn>,
     ) -> Result<Self> {
@@ -241,7 +353,17 @@ impl GameScene {
             }
             _ => {}
         }
-        let (mut chart, chart_bytes, chart_format) = Self::load_chart(fs.deref_mut(), &info).await?;
+        config.validate()?;
+        if let Some(windows) = info.judge_windows {
+            config.judge_windows = windows;
+        }
+        let options = ParseOptions {
+            strict: !config.lenient_parse,
+        };
+        let (mut chart, chart_bytes, chart_format) = Self::load_chart(fs.deref_mut(), &info, options).await?;
+        if config.mirror {
+            chart.mirror();
+        }
         let effects = std::mem::take(&mut chart.extra.global_effects);
         if config.fxaa {
             chart
@@ -254,6 +376,7 @@ impl GameScene {
         let mut res = Resource::new(
             config,
             info,
+            chart_font,
             fs,
             player.as_ref().and_then(|it| it.avatar.clone()),
             background,
@@ -262,9 +385,22 @@ impl GameScene {
         )
         .await
         .context("Failed to load resources")?;
-        let exercise_range = (chart.offset + info_offset + res.config.offset)..res.track_length;
+        let exercise_range = (chart.offset + info_offset + res.config.offset + res.config.audio_offset)..res.track_length;
 
-        let judge = Judge::new(&chart);
+        for (path, clip) in &chart.hitsounds {
+            let sfx = res.audio.create_sfx(clip.clone(), Some(1024))?;
+            res.hitsounds.insert(path.clone(), sfx);
+        }
+        let judge = Judge::new(&chart, &res.config)?;
+        let replay = match &res.config.replay {
+            Some(path) => Some((Replay::load(&std::fs::read(path).context("Cannot read replay")?).context("Failed to load replay")?, 0)),
+            None => None,
+        };
+        let recording = if mode == GameMode::Normal && replay.is_none() && res.config.record_replay.is_some() {
+            Some(Replay::default())
+        } else {
+            None
+        };
 
         let music = Self::new_music(&mut res)?;
         Ok(Self {
@@ -272,9 +408,7 @@ impl GameScene {
             next_scene: None,
 
             mode,
-            res,
-            chart,
-            judge,
+            game: GamePlayer::new(res, chart, judge),
             gl: unsafe { get_internal_gl() },
             player,
             chart_bytes,
@@ -296,8 +430,15 @@ impl GameScene {
             last_update_time: 0.,
             pause_rewind: None,
             pause_first_time: f32::NEG_INFINITY,
+            pause_touch: None,
+            pause_selected: 1,
+            checkpoint: None,
+            speed_changed: None,
+            offset_changed: None,
+            loop_points: (None, None),
 
-            bad_notes: Vec::new(),
+            replay,
+            recording,
 
             upload_fn,
         })
@@ -307,7 +448,7 @@ impl GameScene {
         res.audio.create_music(
             res.music.clone(),
             MusicParams {
-                amplifier: res.config.volume_music as _,
+                amplifier: res.config.music_volume() as _,
                 playback_rate: res.config.speed as _,
                 ..Default::default()
             },
@@ -327,28 +468,64 @@ impl GameScene {
             State::BeforeMusic => 1.,
             State::Playing => 1.,
             State::Ending => {
-                let t = time - self.res.track_length - WAIT_TIME;
+                let t = time - self.game.res.track_length - WAIT_TIME;
                 1. - (t / (AFTER_TIME + 0.3)).min(1.).powi(2)
             }
         };
-        let c = Color::new(1., 1., 1., self.res.alpha);
-        let res = &mut self.res;
+        let c = Color::new(1., 1., 1., self.game.res.alpha);
+        let res = &mut self.game.res;
         let eps = 2e-2 / res.aspect_ratio;
         let top = -1. / res.aspect_ratio;
-        let pause_w = 0.012;
-        let pause_h = pause_w * 3.375;
-        let pause_center = Point::new(pause_w * 4.4 - 1., top + eps * 3.6454 - (1. - p) * 0.4 + pause_h / 2.);
-        if res.config.interactive
-            && !tm.paused()
-            && self.pause_rewind.is_none()
-            && Judge::get_touches().iter().any(|touch| {
-                touch.phase == TouchPhase::Started && {
-                    let p = touch.position;
-                    let p = Point::new(p.x, p.y);
-                    (pause_center - p).norm() < 0.05
+        let (perfect, good) = res.judge_line_colors();
+        let fc_color = match self.game.judge.fc_state() {
+            FcState::AllPerfect => Some(("AP", perfect)),
+            FcState::FullCombo => Some(("FC", good)),
+            FcState::None => None,
+        };
+        if let Some((badge, color)) = fc_color {
+            // glow around the playfield while FC / AP is maintained
+            let glow = Color {
+                a: color.a * c.a * 0.4,
+                ..color
+            };
+            let clear = Color { a: 0., ..color };
+            let w = 0.03;
+            let bottom = -top;
+            ui.fill_rect(Rect::new(-1., top, 2., w), (glow, (0., top), clear, (0., top + w)));
+            ui.fill_rect(Rect::new(-1., bottom - w, 2., w), (glow, (0., bottom), clear, (0., bottom - w)));
+            ui.fill_rect(Rect::new(-1., top, w, bottom - top), (glow, (-1., 0.), clear, (-1. + w, 0.)));
+            ui.fill_rect(Rect::new(1. - w, top, w, bottom - top), (glow, (1., 0.), clear, (1. - w, 0.)));
+            if matches!(self.state, State::Ending) {
+                ui.text(badge)
+                    .pos(0., 0.)
+                    .anchor(0.5, 0.5)
+                    .size(1.2)
+                    .color(Color { a: color.a * c.a, ..color })
+                    .draw();
+            }
+        }
+        let (pause_w, pause_h) = (PAUSE_WIDTH, PAUSE_HEIGHT);
+        let pause_center = Self::pause_center(res.aspect_ratio, p);
+        // the button only takes a touch that both starts and ends on it
+        let mut pause_tapped = false;
+        if res.config.interactive && !tm.paused() && self.pause_rewind.is_none() {
+            for touch in Judge::get_touches() {
+                let inside = (pause_center - Point::new(touch.position.x, touch.position.y)).norm() < PAUSE_RADIUS;
+                match touch.phase {
+                    TouchPhase::Started => {
+                        if inside {
+                            self.pause_touch = Some(touch.id);
+                        }
+                    }
+                    TouchPhase::Ended | TouchPhase::Cancelled if self.pause_touch == Some(touch.id) => {
+                        self.pause_touch = None;
+                        pause_tapped |= inside && touch.phase == TouchPhase::Ended;
+                    }
+                    _ => {}
                 }
-            })
-        {
+            }
+        }
+        if pause_tapped {
             let t = tm.now() as f32;
             if t - self.pause_first_time > PAUSE_CLICK_INTERVAL && res.config.double_click_to_pause {
                 self.pause_first_time = t;
@@ -358,6 +535,7 @@ impl GameScene {
                     self.music.pause()?;
                 }
                 tm.pause();
+                self.game.judge.on_pause();
             }
         }
         if tm.now() as f32 - self.pause_first_time <= PAUSE_CLICK_INTERVAL {
@@ -366,8 +544,8 @@ impl GameScene {
 
         let margin = 0.046;
 
-        self.chart.with_element(ui, res, UIElement::Score, |ui, color, scale| {
-            ui.text(format!("{:07}", self.judge.score()))
+        self.game.chart.with_element(ui, res, UIElement::Score, |ui, color, scale| {
+            ui.text(format!("{:0width$}", self.game.judge.score(), width = self.game.judge.score_digits()))
                 .pos(1. - margin + 0.001, top + eps * 2.8125 - (1. - p) * 0.4)
                 .anchor(1., 0.)
                 .size(0.70867) //magic constant(
@@ -376,14 +554,14 @@ impl GameScene {
                 .draw();
         });
         if res.config.show_acc {
-            ui.text(format!("{:05.2}%", self.judge.real_time_accuracy() * 100.))
+            ui.text(format!("{:05.2}%", self.game.judge.real_time_accuracy() * 100.))
                 .pos(1. - margin, top + eps * 2.2 - (1. - p) * 0.4 + 0.07)
                 .anchor(1., 0.)
                 .size(0.4)
                 .color(semi_white(0.7))
                 .draw();
         }
-        self.chart.with_element(ui, res, UIElement::Pause, |ui, color, scale| {
+        self.game.chart.with_element(ui, res, UIElement::Pause, |ui, color, scale| {
             let mut r = Rect::new(pause_center.x - pause_w * 1.5, pause_center.y - pause_h / 2., pause_w, pause_h);
             let ct = pause_center.coords;
             let c = Color { a: color.a * c.a, ..color };
@@ -393,29 +571,65 @@ impl GameScene {
                 ui.fill_rect(r, c);
             });
         });
-        if self.judge.combo() >= 3 {
-            let btm = self.chart.with_element(ui, res, UIElement::ComboNumber, |ui, color, scale| {
-                ui.text(self.judge.combo().to_string())
+        let mut combo_btm = top + eps * 1.346 - (1. - p) * 0.4;
+        if self.game.judge.combo() >= 3 {
+            let btm = self.game.chart.with_element(ui, res, UIElement::ComboNumber, |ui, color, scale| {
+                let r = ui
+                    .text(self.game.judge.combo().to_string())
                     .pos(0., top + eps * 1.346 - (1. - p) * 0.4)
                     .anchor(0.5, 0.)
                     .color(Color { a: color.a * c.a, ..color })
                     .scale(scale)
-                    .draw()
-                    .bottom()
+                    .draw();
+                // the best combo only tells something once it's no longer the current one
+                if res.config.show_max_combo && !self.game.judge.is_full_combo() {
+                    ui.text(format!("/{}", self.game.judge.max_combo()))
+                        .pos(r.right() + 0.01, r.bottom())
+                        .anchor(0., 1.)
+                        .size(0.5)
+                        .color(Color::new(0.6, 0.6, 0.6, color.a * c.a * 0.8))
+                        .scale(scale)
+                        .draw();
+                }
+                r.bottom()
+            });
+            combo_btm = self.game.chart.with_element(ui, res, UIElement::Combo, |ui, color, scale| {
+                ui.text(if res.config.practice {
+                    "PRACTICE"
+                } else if res.config.autoplay {
+                    "AUTOPLAY"
+                } else {
+                    "COMBO"
+                })
+                .pos(0., btm + 0.007777)
+                .anchor(0.5, 0.)
+                .size(0.325)
+                .color(Color { a: color.a * c.a, ..color })
+                .scale(scale)
+                .draw()
+                .bottom()
             });
-            self.chart.with_element(ui, res, UIElement::Combo, |ui, color, scale| {
-                ui.text(if res.config.autoplay { "AUTOPLAY" } else { "COMBO" })
-                    .pos(0., btm + 0.007777)
+        }
+        if let Some((diff, at)) = self.game.judge.last_judge_diff {
+            const EARLY_LATE_TIME: f32 = 0.5;
+            let dt = res.time - at;
+            if res.config.show_early_late && !res.config.autoplay && (0.0..EARLY_LATE_TIME).contains(&dt) {
+                let a = c.a * (1. - dt / EARLY_LATE_TIME);
+                ui.text(if diff < 0. { "EARLY" } else { "LATE" })
+                    .pos(0., combo_btm + 0.01)
                     .anchor(0.5, 0.)
-                    .size(0.325)
-                    .color(Color { a: color.a * c.a, ..color })
-                    .scale(scale)
+                    .size(0.3)
+                    .color(if diff < 0. {
+                        Color::new(0.45, 0.75, 1., a)
+                    } else {
+                        Color::new(1., 0.55, 0.45, a)
+                    })
                     .draw();
-            });
+            }
         }
         let lf = -1. + margin;
         let bt = -top - eps * 3.64;
-        self.chart.with_element(ui, res, UIElement::Name, |ui, color, scale| {
+        self.game.chart.with_element(ui, res, UIElement::Name, |ui, color, scale| {
             ui.text(&res.info.name)
                 .pos(lf, bt + (1. - p) * 0.4)
                 .anchor(0., 1.)
@@ -423,21 +637,34 @@ impl GameScene {
                 .color(Color { a: color.a * c.a, ..color })
                 .scale(scale)
                 .max_width(0.8)
-                .draw();
+                .draw_with_font(res.chart_font.as_ref().map(|it| it.borrow_mut()).as_deref_mut());
         });
-        self.chart.with_element(ui, res, UIElement::Level, |ui, color, scale| {
+        self.game.chart.with_element(ui, res, UIElement::Level, |ui, color, scale| {
             ui.text(&res.info.level)
                 .pos(-lf, bt + (1. - p) * 0.4)
                 .anchor(1., 1.)
                 .size(0.5)
                 .color(Color { a: color.a * c.a, ..color })
                 .scale(scale)
-                .draw();
+                .draw_with_font(res.chart_font.as_ref().map(|it| it.borrow_mut()).as_deref_mut());
         });
         let hw = 0.003;
         let height = eps * 1.2;
+        // tapping the progress bar seeks to the corresponding position, unless the chart moves the bar around
+        let mut seek = None;
+        if Self::interactive(res, &self.state) && !tm.paused() && self.game.chart.attach_ui[UIElement::Bar as usize - 1].is_none() {
+            if let Some(touch) = Judge::get_touches().iter().find(|touch| {
+                touch.phase == TouchPhase::Started
+                    && touch.position.y <= top + eps * 2.
+                    && (pause_center - Point::new(touch.position.x, touch.position.y)).norm() >= PAUSE_RADIUS
+            }) {
+                let offset = self.game.chart.offset + res.config.offset + res.config.audio_offset + self.info_offset;
+                seek = Some((((touch.position.x + 1.) / 2.).clamp(0., 1.) * res.track_length + offset).clamp(0., res.track_length));
+            }
+        }
         let dest = 2. * res.time / res.track_length;
-        self.chart.with_element(ui, res, UIElement::Bar, |ui, color, scale| {
+        let loop_points = self.loop_points;
+        self.game.chart.with_element(ui, res, UIElement::Bar, |ui, color, scale| {
             let ct = Vector::new(0., top + height / 2.);
             ui.with(scale.prepend_translation(&-ct).append_translation(&ct), |ui| {
                 ui.fill_rect(
@@ -448,20 +675,53 @@ impl GameScene {
                     },
                 );
                 ui.fill_rect(Rect::new(-1. + dest - hw, top, hw * 2., height), Color { a: color.a * c.a, ..color });
+                for point in [loop_points.0, loop_points.1].into_iter().flatten() {
+                    let x = 2. * point / res.track_length;
+                    ui.fill_rect(Rect::new(-1. + x - hw / 2., top, hw, height * 2.), Color { a: color.a * c.a, ..YELLOW });
+                }
             });
         });
+        if let Some(time) = self.speed_changed {
+            let t = (tm.real_time() - time) as f32;
+            if t < SPEED_HINT_TIME {
+                ui.text(format!("{:.1}x", res.config.speed))
+                    .pos(0., top + 0.2)
+                    .anchor(0.5, 0.5)
+                    .size(0.7)
+                    .color(Color::new(1., 1., 1., c.a * (1. - t / SPEED_HINT_TIME)))
+                    .draw();
+            }
+        }
+        if let Some(time) = self.offset_changed {
+            let t = (tm.real_time() - time) as f32;
+            if t < SPEED_HINT_TIME {
+                ui.text(format!("{}ms", (self.info_offset * 1000.).round() as i32))
+                    .pos(0., top + 0.3)
+                    .anchor(0.5, 0.5)
+                    .size(0.6)
+                    .color(Color::new(1., 1., 1., c.a * (1. - t / SPEED_HINT_TIME)))
+                    .draw();
+            }
+        }
+        if let Some(dst) = seek {
+            self.seek_to(tm, dst)?;
+        }
         Ok(())
     }
 
     fn overlay_ui(&mut self, ui: &mut Ui, tm: &mut TimeManager) -> Result<()> {
-        let c = Color::new(1., 1., 1., self.res.alpha);
-        let res = &mut self.res;
+        let c = Color::new(1., 1., 1., self.game.res.alpha);
+        let res = &mut self.game.res;
         if tm.paused() {
             let h = 1. / res.aspect_ratio;
             draw_rectangle(-1., -h, 2., h * 2., Color::new(0., 0., 0., 0.6));
             let o = if self.mode == GameMode::Exercise { -0.3 } else { 0. };
             let s = 0.06;
             let w = 0.05;
+            if res.config.interactive {
+                let ct = Point::new((s * 2. + w) * self.pause_selected as f32, o);
+                ui.fill_rect(Rect::new(ct.x - s, ct.y - s, s * 2., s * 2.).feather(0.015), Color::new(1., 1., 1., 0.2 * c.a));
+            }
             draw_texture_ex(
                 *res.icon_back,
                 -s * 3. - w,
@@ -509,11 +769,32 @@ impl GameScene {
                         }
                     }
                 }
+                if is_key_pressed(KeyCode::Left) {
+                    self.pause_selected = (self.pause_selected - 1).max(-1);
+                }
+                if is_key_pressed(KeyCode::Right) {
+                    self.pause_selected = (self.pause_selected + 1).min(1);
+                }
+                for (key, i) in [(KeyCode::Key1, -1), (KeyCode::Key2, 0), (KeyCode::Key3, 1)] {
+                    if is_key_pressed(key) {
+                        clicked = Some(i);
+                    }
+                }
+                if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::KpEnter) {
+                    clicked = Some(self.pause_selected);
+                }
+                if clicked.is_some() {
+                    self.pause_selected = 1;
+                }
                 match clicked {
                     Some(-1) => {
                         self.should_exit = true;
                     }
                     Some(0) => {
+                        if let Some(checkpoint) = self.checkpoint {
+                            self.restart_from(tm, checkpoint)?;
+                            return Ok(());
+                        }
                         reset!(self, res, tm);
                     }
                     Some(1) => {
@@ -523,7 +804,7 @@ impl GameScene {
                             self.music = res.audio.create_music(
                                 res.music.clone(),
                                 MusicParams {
-                                    amplifier: res.config.volume_music as _,
+                                    amplifier: res.config.music_volume() as _,
                                     playback_rate: res.config.speed as _,
                                     ..Default::default()
                                 },
@@ -535,8 +816,9 @@ impl GameScene {
                             pos = self.exercise_range.start;
                         }
                         self.music.play()?;
-                        res.time -= 3.;
-                        let dst = pos - 3.;
+                        let rewind = res.config.resume_rewind;
+                        res.time -= rewind;
+                        let dst = (pos - rewind).min(res.track_length);
                         if dst < 0. {
                             self.music.pause()?;
                             self.state = State::BeforeMusic;
@@ -546,17 +828,33 @@ impl GameScene {
                         let now = tm.now();
                         tm.speed = res.config.speed as _;
                         tm.resume();
-                        tm.seek_to(now - 3.);
+                        tm.seek_to(now - rewind as f64);
                         self.pause_rewind = Some(tm.now() - 0.2);
                     }
                     _ => {}
                 }
             }
+            if res.config.practice && res.config.interactive {
+                let r = Rect::new(-0.2, o + s + 0.05, 0.4, 0.08);
+                if let Some(checkpoint) = self.checkpoint {
+                    ui.text(tl!("practice-checkpoint", "time" => format!("{}:{:02}", (checkpoint / 60.) as u32, checkpoint as u32 % 60)))
+                        .pos(0., r.bottom() + 0.03)
+                        .anchor(0.5, 0.)
+                        .size(0.4)
+                        .color(c)
+                        .draw();
+                    if ui.button("clear_checkpoint", r, tl!("practice-clear-checkpoint")) {
+                        self.checkpoint = None;
+                    }
+                } else if ui.button("set_checkpoint", r, tl!("practice-set-checkpoint")) {
+                    self.checkpoint = Some(tm.now().clamp(0., res.track_length as f64) as f32);
+                }
+            }
             if self.mode == GameMode::Exercise {
                 ui.scope(|ui| {
                     ui.dx(0.3);
                     ui.dy(-0.3);
-                    ui.slider(tl!("speed"), 0.5..2.0, 0.05, &mut self.res.config.speed, Some(0.5));
+                    ui.slider(tl!("speed"), 0.5..2.0, 0.05, &mut self.game.res.config.speed, Some(0.5));
                 });
                 ui.dy(0.06);
                 let hw = 0.7;
@@ -565,10 +863,10 @@ impl GameScene {
                 let rad = 0.03;
                 let sp = self.offset().min(0.);
                 ui.fill_rect(Rect::new(-hw, -h, hw * 2., h * 2.), GRAY);
-                let st = -hw + (self.exercise_range.start - sp) / (self.res.track_length - sp) * hw * 2.;
-                let en = -hw + (self.exercise_range.end - sp) / (self.res.track_length - sp) * hw * 2.;
+                let st = -hw + (self.exercise_range.start - sp) / (self.game.res.track_length - sp) * hw * 2.;
+                let en = -hw + (self.exercise_range.end - sp) / (self.game.res.track_length - sp) * hw * 2.;
                 let t = tm.now() as f32;
-                let cur = -hw + (t - sp) / (self.res.track_length - sp) * hw * 2.;
+                let cur = -hw + (t - sp) / (self.game.res.track_length - sp) * hw * 2.;
                 ui.fill_rect(Rect::new(st, -h, en - st, h * 2.), WHITE);
                 ui.fill_rect(Rect::new(st, -eh, 0., eh + h).feather(0.005), BLUE);
                 ui.fill_circle(st, -eh, rad, BLUE);
@@ -601,16 +899,16 @@ impl GameScene {
                 if let Some((ctrl, id)) = &self.exercise_press {
                     if let Some(touch) = Judge::get_touches().iter().rfind(|it| it.id == *id) {
                         let x = touch.position.x;
-                        let p = (x + hw) / (hw * 2.) * (self.res.track_length - sp) + sp;
-                        let p = if self.res.track_length - sp <= 3. || *ctrl == 0 {
-                            p.clamp(sp, self.res.track_length)
+                        let p = (x + hw) / (hw * 2.) * (self.game.res.track_length - sp) + sp;
+                        let p = if self.game.res.track_length - sp <= 3. || *ctrl == 0 {
+                            p.clamp(sp, self.game.res.track_length)
                         } else {
                             p.clamp(
                                 if *ctrl == -1 { sp } else { self.exercise_range.start + 3. },
                                 if *ctrl == -1 {
                                     self.exercise_range.end - 3.
                                 } else {
-                                    self.res.track_length
+                                    self.game.res.track_length
                                 },
                             )
                         };
@@ -657,17 +955,18 @@ impl GameScene {
         }
         if let Some(time) = self.pause_rewind {
             let dt = tm.now() - time;
-            let t = 3 - dt.floor() as i32;
+            let countdown = self.game.res.config.resume_countdown;
+            let t = countdown as i32 - dt.floor() as i32;
             if t <= 0 {
                 self.pause_rewind = None;
             } else {
-                let a = (1. - dt as f32 / 3.) * 1.;
-                let h = 1. / self.res.aspect_ratio;
+                let a = 1. - dt as f32 / countdown as f32;
+                let h = 1. / self.game.res.aspect_ratio;
                 draw_rectangle(-1., -h, 2., h * 2., Color::new(0., 0., 0., a));
                 ui.text(t.to_string()).anchor(0.5, 0.5).size(1.).color(c).draw();
             }
         }
-        if self.res.config.touch_debug {
+        if self.game.res.config.touch_debug {
             for touch in Judge::get_touches() {
                 ui.fill_circle(touch.position.x, touch.position.y, 0.04, Color { a: 0.4, ..RED });
             }
@@ -675,12 +974,127 @@ impl GameScene {
         Ok(())
     }
 
+    /// Center of the pause button, which slides in along with the rest of the UI as `p` goes to 1
+    fn pause_center(aspect_ratio: f32, p: f32) -> Point {
+        let eps = 2e-2 / aspect_ratio;
+        let top = -1. / aspect_ratio;
+        Point::new(PAUSE_WIDTH * 4.4 - 1., top + eps * 3.6454 - (1. - p) * 0.4 + PAUSE_HEIGHT / 2.)
+    }
+
+    /// Region of the pause button while playing, touches starting in which are left out of judging
+    fn pause_region(aspect_ratio: f32) -> Rect {
+        let ct = Self::pause_center(aspect_ratio, 1.);
+        Rect::new(ct.x - PAUSE_RADIUS, ct.y - PAUSE_RADIUS, PAUSE_RADIUS * 2., PAUSE_RADIUS * 2.)
+    }
+
     fn interactive(res: &Resource, state: &State) -> bool {
         res.config.interactive && matches!(state, State::Playing)
     }
 
     fn offset(&self) -> f32 {
-        self.chart.offset + self.res.config.offset + self.info_offset
+        // audio offset shifts when the music is considered to start, while input offset is handled by the judge
+        self.game.chart.offset + self.game.res.config.offset + self.game.res.config.audio_offset + self.info_offset
+    }
+
+    fn set_speed(&mut self, tm: &mut TimeManager, speed: f32) -> Result<()> {
+        let res = &mut self.game.res;
+        res.config.speed = speed.clamp(0.5, 2.);
+        let pos = self.music.position();
+        let paused = self.music.paused();
+        self.music = res.audio.create_music(
+            res.music.clone(),
+            MusicParams {
+                amplifier: res.config.music_volume() as _,
+                playback_rate: res.config.speed as _,
+                ..Default::default()
+            },
+        )?;
+        self.music.seek_to(pos)?;
+        if !paused {
+            self.music.play()?;
+        }
+        // re-anchor the clock so that the chart stays in sync
+        let now = tm.now();
+        tm.speed = res.config.speed as _;
+        tm.seek_to(now);
+        self.speed_changed = Some(tm.real_time());
+        Ok(())
+    }
+
+    // derived from the music position every frame, so seeking, pausing or recreating the music never leaves it stuck at a faded volume
+    fn update_fade(&mut self) -> Result<()> {
+        let config = &self.game.res.config;
+        if config.fade_in <= 0. && config.fade_out <= 0. {
+            return Ok(());
+        }
+        let pos = self.music.position();
+        let mut gain = 1.;
+        if config.fade_in > 0. {
+            gain *= (pos / config.fade_in).clamp(0., 1.);
+        }
+        if config.fade_out > 0. {
+            gain *= ((self.game.res.track_length - pos) / config.fade_out).clamp(0., 1.);
+        }
+        self.music.set_amplifier(config.music_volume() * gain)?;
+        Ok(())
+    }
+
+    /// Retries from a practice checkpoint, judging only the notes after it
+    fn restart_from(&mut self, tm: &mut TimeManager, checkpoint: f32) -> Result<()> {
+        reset!(self, self.game.res, tm);
+        self.game.res.alpha = 1.;
+        self.state = State::Playing;
+        self.music.play()?;
+        self.seek_to(tm, checkpoint)?;
+        self.game.judge.start_from(&mut self.game.chart, self.game.res.time, None);
+        self.last_update_time = tm.real_time();
+        Ok(())
+    }
+
+    /// Seeks to the given music position, clamped to the track
+    fn seek_to(&mut self, tm: &mut TimeManager, dst: f32) -> Result<()> {
+        let dst = dst.clamp(0., self.game.res.track_length);
+        if dst >= self.game.res.track_length {
+            // leave the music finished, the same as playing till the end, so that the ending branch takes over
+            self.music.pause()?;
+        } else {
+            self.music.seek_to(dst)?;
+            // music stopped at the end, e.g. by a previous seek
+            if self.music.paused() && !tm.paused() && matches!(self.state, State::Playing) {
+                self.music.play()?;
+            }
+        }
+        tm.seek_to(dst as f64);
+        self.game.res.time = (dst - self.offset()).max(0.);
+        self.game.bad_notes.clear();
+        self.game
+            .judge
+            .seek(&mut self.game.chart, self.game.res.time, self.game.res.config.autoplay);
+        if let Some((replay, cursor)) = &mut self.replay {
+            *cursor = replay.frames.partition_point(|it| it.time < self.game.res.time);
+        }
+        if self.recording.take().is_some() {
+            warn!("Seeking during recording, the replay is discarded");
+        }
+        Ok(())
+    }
+
+    fn finish_replay(&mut self) {
+        let score = self.game.judge.score();
+        if let Some((replay, _)) = &self.replay {
+            if replay.score.abs_diff(score) > REPLAY_SCORE_TOLERANCE {
+                warn!("Replayed score ({score}) diverges from the recorded one ({})", replay.score);
+            }
+        }
+        if let (Some(recording), Some(path)) = (&mut self.recording, &self.game.res.config.record_replay) {
+            recording.score = score;
+            if let Err(err) = recording
+                .save()
+                .and_then(|bytes| std::fs::write(path, bytes).context("Cannot write replay"))
+            {
+                warn!("Failed to save replay: {err:?}");
+            }
+        }
     }
 
     fn tweak_offset(&mut self, ui: &mut Ui, ita: bool) {
@@ -744,12 +1158,12 @@ impl Scene for GameScene {
     fn enter(&mut self, tm: &mut TimeManager, target: Option<RenderTarget>) -> Result<()> {
         #[cfg(target_arch = "wasm32")]
         on_game_start();
-        self.music = Self::new_music(&mut self.res)?;
-        self.res.camera.render_target = target;
-        tm.speed = self.res.config.speed as _;
-        tm.adjust_time = self.res.config.adjust_time;
-        reset!(self, self.res, tm);
-        set_camera(&self.res.camera);
+        self.music = Self::new_music(&mut self.game.res)?;
+        self.game.res.camera.render_target = target;
+        tm.speed = self.game.res.config.speed as _;
+        tm.adjust_time = self.game.res.config.adjust_time;
+        reset!(self, self.game.res, tm);
+        set_camera(&self.game.res.camera);
         self.first_in = true;
         Ok(())
     }
@@ -759,6 +1173,7 @@ impl Scene for GameScene {
             self.pause_rewind = None;
             self.music.pause()?;
             tm.pause();
+            self.game.judge.on_pause();
         }
         Ok(())
     }
@@ -771,13 +1186,14 @@ impl Scene for GameScene {
     }
 
     fn update(&mut self, tm: &mut TimeManager) -> Result<()> {
-        self.res.audio.recover_if_needed()?;
+        self.game.res.audio.recover_if_needed()?;
         if matches!(self.state, State::Playing) {
             tm.update(self.music.position() as f64);
+            self.update_fade()?;
         }
         if self.mode == GameMode::Exercise && tm.now() > self.exercise_range.end as f64 && !tm.paused() {
             let state = self.state.clone();
-            reset!(self, self.res, tm);
+            reset!(self, self.game.res, tm);
             self.state = state;
             tm.seek_to(self.exercise_range.start as f64);
             tm.pause();
@@ -788,7 +1204,7 @@ impl Scene for GameScene {
         let time = match self.state {
             State::Starting => {
                 if time >= Self::BEFORE_TIME {
-                    self.res.alpha = 1.;
+                    self.game.res.alpha = 1.;
                     self.state = State::BeforeMusic;
                     tm.reset();
                     tm.seek_to(if self.mode == GameMode::Exercise {
@@ -803,7 +1219,7 @@ impl Scene for GameScene {
                     }
                     tm.now() as f32
                 } else {
-                    self.res.alpha = 1. - (1. - time / Self::BEFORE_TIME).powi(3);
+                    self.game.res.alpha = 1. - (1. - time / Self::BEFORE_TIME).powi(3);
                     if self.mode == GameMode::Exercise {
                         self.exercise_range.start
                     } else {
@@ -822,28 +1238,43 @@ impl Scene for GameScene {
                 time
             }
             State::Playing => {
-                if time > self.res.track_length + WAIT_TIME {
+                if time > self.game.res.track_length + WAIT_TIME {
                     self.state = State::Ending;
+                    let result = self.game.judge.result();
+                    info!(
+                        "Chart finished: score {:0width$}, accuracy {:.2}%, max combo {}/{}",
+                        result.score,
+                        self.game.judge.real_time_accuracy() * 100.,
+                        result.max_combo,
+                        result.num_of_notes,
+                        width = self.game.judge.score_digits()
+                    );
+                    info!("Judgements by note kind:\n{}", self.game.judge.detailed_counts());
+                    self.finish_replay();
                 }
                 time
             }
             State::Ending => {
-                let t = time - self.res.track_length - WAIT_TIME;
+                let t = time - self.game.res.track_length - WAIT_TIME;
                 if t >= AFTER_TIME + 0.3 {
                     let mut record_data = None;
                     // TODO strengthen the protection
                     #[cfg(feature = "closed")]
                     if let Some(upload_fn) = &self.upload_fn {
-                        if !self.res.config.offline_mode && !self.res.config.autoplay && self.res.config.speed >= 1.0 - 1e-3 {
+                        if !self.game.res.config.offline_mode
+                            && !self.game.res.config.autoplay
+                            && !self.game.res.config.practice
+                            && self.game.res.config.speed >= 1.0 - 1e-3
+                        {
                             if let Some(player) = &self.player {
-                                if let Some(chart) = &self.res.info.id {
+                                if let Some(chart) = &self.game.res.info.id {
                                     record_data = Some(encode_record(self, player.id, *chart));
                                 }
                             }
                         }
                     }
-                    let result = self.judge.result();
-                    let record = if self.res.config.autoplay || self.res.config.speed < 1.0 - 1e-3 {
+                    let result = self.game.judge.result();
+                    let record = if self.game.res.config.autoplay || self.game.res.config.practice || self.game.res.config.speed < 1.0 - 1e-3 {
                         None
                     } else {
                         Some(SimpleRecord {
@@ -854,17 +1285,18 @@ impl Scene for GameScene {
                     };
                     self.next_scene = match self.mode {
                         GameMode::Normal => Some(NextScene::Overlay(Box::new(EndingScene::new(
-                            self.res.background.clone(),
-                            self.res.illustration.clone(),
-                            self.res.player.clone(),
-                            self.res.icons.clone(),
-                            self.res.icon_retry.clone(),
-                            self.res.icon_proceed.clone(),
-                            self.res.info.clone(),
-                            self.judge.result(),
-                            self.res.challenge_icons[self.res.config.challenge_color.clone() as usize].clone(),
-                            &self.res.config,
-                            self.res.res_pack.ending.clone(),
+                            self.game.res.background.clone(),
+                            self.game.res.illustration.clone(),
+                            self.game.res.player.clone(),
+                            self.game.res.icons.clone(),
+                            self.game.res.icon_retry.clone(),
+                            self.game.res.icon_proceed.clone(),
+                            self.game.res.info.clone(),
+                            self.game.res.chart_font.clone(),
+                            self.game.judge.result(),
+                            self.game.res.challenge_icons[self.game.res.config.challenge_color.clone() as usize].clone(),
+                            &self.game.res.config,
+                            self.game.res.res_pack.ending.clone(),
                             self.upload_fn.as_ref().map(Arc::clone),
                             self.player.as_ref().map(|it| it.rks),
                             record_data,
@@ -874,31 +1306,38 @@ impl Scene for GameScene {
                         GameMode::Exercise => None,
                     };
                 }
-                self.res.alpha = 1. - (t / AFTER_TIME).min(1.).powi(2);
-                self.res.track_length
+                self.game.res.alpha = 1. - (t / AFTER_TIME).min(1.).powi(2);
+                self.game.res.track_length
             }
         };
         let time = (time - offset).max(0.);
-        self.res.time = time;
+        self.game.res.time = time;
         if !tm.paused() && self.pause_rewind.is_none() {
-            self.gl.quad_gl.viewport(self.res.camera.viewport);
-            self.judge.update(&mut self.res, &mut self.chart, &mut self.bad_notes);
+            self.gl.quad_gl.viewport(self.game.res.camera.viewport);
+            if let Some((replay, cursor)) = &mut self.replay {
+                // feed every recorded frame up to now, each at the time it was recorded
+                while let Some(ReplayFrame { time: frame_time, input }) = replay.frames.get(*cursor).filter(|it| it.time <= time) {
+                    self.game.res.time = *frame_time;
+                    self.game.feed(input.clone());
+                    *cursor += 1;
+                }
+                self.game.res.time = time;
+            } else {
+                let mut input = self.game.judge.live_input(&self.game.res);
+                if self.game.res.config.interactive {
+                    input.exclusions.push(Self::pause_region(self.game.res.aspect_ratio));
+                }
+                if let Some(recording) = &mut self.recording {
+                    recording.frames.push(ReplayFrame { time, input: input.clone() });
+                }
+                self.game.feed(input);
+            }
             self.gl.quad_gl.viewport(None);
         }
-        let counts = self.judge.counts();
-        self.res.judge_line_color = if counts[2] + counts[3] == 0 {
-            Color::from_hex(if counts[1] == 0 {
-                self.res.res_pack.info.color_perfect
-            } else {
-                self.res.res_pack.info.color_good
-            })
-        } else {
-            WHITE
-        };
-        self.res.judge_line_color.a *= self.res.alpha;
-        self.chart.update(&mut self.res);
-        let res = &mut self.res;
-        if res.config.interactive && is_key_pressed(KeyCode::Space) {
+        self.game.update_chart();
+        let actions = InputAction::poll(self.game.judge.play_keys());
+        let res = &mut self.game.res;
+        if res.config.interactive && actions.contains(&InputAction::TogglePause) {
             if tm.paused() {
                 if matches!(self.state, State::Playing) {
                     self.music.play()?;
@@ -909,34 +1348,56 @@ impl Scene for GameScene {
                     self.music.pause()?;
                 }
                 tm.pause();
+                self.game.judge.on_pause();
             }
         }
         if Self::interactive(res, &self.state) {
-            if is_key_pressed(KeyCode::Left) {
-                res.time -= 1.;
-                let dst = (self.music.position() - 1.).max(0.);
-                self.music.seek_to(dst)?;
-                tm.seek_to(dst as f64);
-            }
-            if is_key_pressed(KeyCode::Right) {
-                res.time += 5.;
-                let dst = (self.music.position() + 5.).min(res.track_length);
-                self.music.seek_to(dst)?;
-                tm.seek_to(dst as f64);
+            for action in actions {
+                match action {
+                    InputAction::TogglePause => {}
+                    // arrow keys navigate the pause menu while paused
+                    InputAction::Seek(delta) if !tm.paused() => {
+                        self.seek_to(tm, self.music.position() + delta)?;
+                    }
+                    InputAction::ChangeSpeed(delta) if !tm.paused() => {
+                        self.set_speed(tm, self.game.res.config.speed + delta)?;
+                    }
+                    InputAction::NudgeOffset(delta) => {
+                        // chart time is derived from the music position every frame, so this takes effect immediately
+                        self.info_offset += delta;
+                        self.offset_changed = Some(tm.real_time());
+                    }
+                    InputAction::SetLoopPoint => {
+                        let t = self.game.res.time;
+                        self.loop_points = match self.loop_points {
+                            (None, _) => (Some(t), None),
+                            (Some(a), None) if t > a => (Some(a), Some(t)),
+                            _ => (None, None),
+                        };
+                    }
+                    InputAction::Quit => {
+                        self.should_exit = true;
+                    }
+                    _ => {}
+                }
             }
-            if is_key_pressed(KeyCode::Q) {
-                self.should_exit = true;
+        }
+        if let (Some(a), Some(b)) = self.loop_points {
+            // each pass judges afresh from A to B, so the score only reflects the looped segment rather than what was kept by the seek
+            if !tm.paused() && matches!(self.state, State::Playing) && self.game.res.time >= b {
+                self.seek_to(tm, a + self.offset())?;
+                self.game.judge.start_from(&mut self.game.chart, self.game.res.time, Some(b));
             }
         }
         for e in &mut self.effects {
-            e.update(&self.res);
+            e.update(&self.game.res);
         }
         if let Some((id, text)) = take_input() {
             let offset = self.offset().min(0.);
             match id.as_str() {
                 "exercise_start" => {
                     if let Some(t) = parse_time(&text) {
-                        if !(offset..self.res.track_length.min(self.exercise_range.end - 3.).max(offset)).contains(&t) {
+                        if !(offset..self.game.res.track_length.min(self.exercise_range.end - 3.).max(offset)).contains(&t) {
                             show_message(tl!("ex-time-out-of-range")).error();
                         } else {
                             self.exercise_range.start = t;
@@ -948,7 +1409,7 @@ impl Scene for GameScene {
                 }
                 "exercise_end" => {
                     if let Some(t) = parse_time(&text) {
-                        if !((self.exercise_range.start + 3.).max(offset).min(self.res.track_length)..self.res.track_length).contains(&t) {
+                        if !((self.exercise_range.start + 3.).max(offset).min(self.game.res.track_length)..self.game.res.track_length).contains(&t) {
                             show_message(tl!("ex-time-out-of-range")).error();
                         } else {
                             self.exercise_range.end = t;
@@ -979,7 +1440,7 @@ impl Scene for GameScene {
     }
 
     fn render(&mut self, tm: &mut TimeManager, ui: &mut Ui) -> Result<()> {
-        let res = &mut self.res;
+        let res = &mut self.game.res;
         let asp = screen_aspect();
         let dim = (self.get_size_fn)();
         if res.update_size(dim) {
@@ -994,24 +1455,20 @@ impl Scene for GameScene {
             .map(|it| if msaa { it.input() } else { it.output() })
             .or(res.camera.render_target);
         push_camera_state();
-        self.gl.quad_gl.viewport(None);
-        set_camera(&Camera2D {
-            zoom: vec2(1., -screen_aspect()),
-            render_target: chart_onto,
-            ..Default::default()
-        });
-        clear_background(BLACK);
-        draw_background(*res.background);
+        // with a fixed aspect ratio the background is letterboxed along with the playfield, leaving black bars around
+        self.gl
+            .quad_gl
+            .viewport(if res.config.fix_aspect_ratio { res.render_viewport() } else { None });
+        self.game.render_background(chart_onto);
         pop_camera_state();
 
+        let res = &self.game.res;
         self.gl.quad_gl.render_pass(chart_onto.map(|it| it.render_pass));
-        self.gl.quad_gl.viewport(res.camera.viewport);
-
-        let h = 1. / res.aspect_ratio;
-        draw_rectangle(-1., -h, 2., h * 2., Color::new(0., 0., 0., res.alpha * res.info.background_dim));
+        self.gl.quad_gl.viewport(res.render_viewport());
 
-        self.chart.render(ui, res);
+        self.game.render_chart(ui);
 
+        let res = &self.game.res;
         self.gl.quad_gl.render_pass(
             res.chart_target
                 .as_ref()
@@ -1019,12 +1476,9 @@ impl Scene for GameScene {
                 .or_else(|| res.camera.render_pass()),
         );
 
-        self.bad_notes.retain(|dummy| dummy.render(res));
         let t = tm.real_time();
         let dt = (t - std::mem::replace(&mut self.last_update_time, t)) as f32;
-        if res.config.particle {
-            res.emitter.draw(dt);
-        }
+        self.game.render_overlay(dt);
         self.ui(ui, tm)?;
         self.overlay_ui(ui, tm)?;
 
@@ -1033,32 +1487,40 @@ impl Scene for GameScene {
             self.gl.quad_gl.viewport(None);
             set_camera(&Camera2D {
                 zoom: vec2(1., -screen_aspect()),
-                render_target: self.res.chart_target.as_ref().map(|it| it.output()).or(self.res.camera.render_target),
+                render_target: self
+                    .game
+                    .res
+                    .chart_target
+                    .as_ref()
+                    .map(|it| it.output())
+                    .or(self.game.res.camera.render_target),
                 ..Default::default()
             });
-            self.tweak_offset(ui, Self::interactive(&self.res, &self.state));
+            self.tweak_offset(ui, Self::interactive(&self.game.res, &self.state));
             pop_camera_state();
         }
 
-        if !self.res.no_effect && !self.effects.is_empty() {
+        if !self.game.res.no_effect && !self.effects.is_empty() {
             push_camera_state();
             set_camera(&Camera2D {
                 zoom: vec2(1., asp),
                 ..Default::default()
             });
             for e in &self.effects {
-                e.render(&mut self.res);
+                e.render(&mut self.game.res);
             }
             pop_camera_state();
         }
-        if msaa || !self.res.no_effect {
+        if msaa || !self.game.res.no_effect || self.game.res.config.render_scale != 1. {
             // render the texture onto screen
-            if let Some(target) = &self.res.chart_target {
+            if let Some(target) = &self.game.res.chart_target {
                 self.gl.flush();
                 if !self.compatible_mode
-                    && !copy_fbo(
+                    && !blit_fbo(
                         target.output().render_pass.gl_internal_id(self.gl.quad_context),
-                        self.res
+                        target.dim(),
+                        self.game
+                            .res
                             .camera
                             .render_target
                             .map_or(0, |it| it.render_pass.gl_internal_id(self.gl.quad_context)),
@@ -1072,7 +1534,7 @@ impl Scene for GameScene {
                     self.gl.quad_gl.viewport(None);
                     set_camera(&Camera2D {
                         zoom: vec2(1., screen_aspect()),
-                        render_target: self.res.camera.render_target,
+                        render_target: self.game.res.camera.render_target,
                         ..Default::default()
                     });
                     draw_texture_ex(
@@ -1100,6 +1562,10 @@ impl Scene for GameScene {
             tm.speed = 1.0;
             tm.adjust_time = false;
             match self.mode {
+                // hand the nudged offset back so that it gets saved to the chart info
+                GameMode::Normal | GameMode::Exercise if self.info_offset != self.game.res.info.offset => {
+                    NextScene::PopWithResult(Box::new(Some(self.info_offset)))
+                }
                 GameMode::Normal | GameMode::Exercise => NextScene::Pop,
                 GameMode::TweakOffset => NextScene::PopWithResult(Box::new(None::<f32>)),
             }
@@ -1112,3 +1578,52 @@ impl Scene for GameScene {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::NoteKind,
+        judge::{
+            tests::{chart, line, note, TestEnv},
+            JudgeInput,
+        },
+    };
+
+    #[test]
+    fn pause_region_excludes_notes() {
+        // the aspect ratio of the test environment
+        let region = GameScene::pause_region(16. / 9.);
+        let ct = region.center();
+        let corner = |id: u64, phase: TouchPhase, time: f32| Touch {
+            id,
+            phase,
+            position: ct,
+            time: time as f64,
+        };
+        // a note right below the button, which falls through it on its way to the line
+        let run = |exclusions: Vec<Rect>| {
+            let mut chart = chart(vec![line(vec![note(NoteKind::Click, 1., ct.x)])]);
+            let mut judge = Judge::new(&chart, &Config::default()).unwrap();
+            let mut env = TestEnv::new(Config::default());
+            let frames = [
+                (1., vec![corner(1, TouchPhase::Started, 1.)]),
+                (1.05, vec![corner(1, TouchPhase::Ended, 1.05)]),
+                (2., Vec::new()),
+            ];
+            for (time, touches) in frames {
+                env.time = time;
+                let input = JudgeInput {
+                    exclusions: exclusions.clone(),
+                    ..JudgeInput::from_touches(touches)
+                };
+                judge.update(&mut env, &mut chart, &mut Vec::new(), input);
+            }
+            judge.counts()
+        };
+        // only x along the line matters to the judge, so the tap on the button hits the note
+        assert_eq!(run(Vec::new()), [1, 0, 0, 0]);
+        // unless the button is left out, where the note goes on to be missed
+        assert_eq!(run(vec![region]), [0, 0, 0, 1]);
+    }
+}
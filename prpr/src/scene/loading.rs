@@ -1,18 +1,19 @@
-use super::{draw_background, draw_illustration, ending::RecordUpdateState, game::GameMode, GameScene, NextScene, Scene};
+use super::{draw_background, draw_illustration, ending::RecordUpdateState, game::GameMode, GameScene, NextScene, Scene, BACKGROUND_DIM};
 use crate::{
     config::Config,
-    ext::{draw_parallelogram, draw_text_aligned, poll_future, screen_aspect, LocalTask, SafeTexture, BLACK_TEXTURE},
-    fs::FileSystem,
+    core::NoteStats,
+    ext::{draw_parallelogram, draw_text_aligned, draw_text_aligned_with_font, poll_future, screen_aspect, LocalTask, SafeTexture, BLACK_TEXTURE},
+    fs::{load_chart_font, FileSystem},
     info::ChartInfo,
     task::Task,
     time::TimeManager,
-    ui::Ui,
+    ui::{TextPainter, Ui},
 };
 use ::rand::{seq::SliceRandom, thread_rng};
 use anyhow::{Context, Result};
 use macroquad::prelude::*;
 use regex::Regex;
-use std::{rc::Rc, sync::Arc};
+use std::{cell::RefCell, rc::Rc, sync::Arc};
 
 const BEFORE_TIME: f32 = 1.;
 const TRANSITION_TIME: f32 = 1.4;
@@ -35,6 +36,8 @@ pub struct LoadingScene {
     finish_time: f32,
     target: Option<RenderTarget>,
     charter: String,
+    note_stats: Option<NoteStats>,
+    font: Option<Rc<RefCell<TextPainter>>>,
 }
 
 impl LoadingScene {
@@ -87,8 +90,21 @@ impl LoadingScene {
         if info.tip.is_none() {
             info.tip = Some(crate::config::TIPS.choose(&mut thread_rng()).unwrap().to_owned());
         }
-        let future =
-            Box::pin(GameScene::new(mode, info.clone(), config, fs, player, background.clone(), illustration.clone(), get_size_fn, upload_fn));
+        let font = load_chart_font(fs.as_mut(), &info)
+            .await
+            .map(|it| Rc::new(RefCell::new(TextPainter::new(it))));
+        let future = Box::pin(GameScene::new(
+            mode,
+            info.clone(),
+            config,
+            fs,
+            player,
+            background.clone(),
+            illustration.clone(),
+            font.clone(),
+            get_size_fn,
+            upload_fn,
+        ));
         let charter = Regex::new(r"\[!:[0-9]+:([^:]*)\]").unwrap().replace_all(&info.charter, "$1").to_string();
         Ok(Self {
             info,
@@ -99,6 +115,8 @@ impl LoadingScene {
             finish_time: f32::INFINITY,
             target: None,
             charter,
+            note_stats: None,
+            font,
         })
     }
 }
@@ -121,6 +139,11 @@ impl Scene for LoadingScene {
                     }
                     Some(game_scene) => {
                         self.load_task = None;
+                        if let Ok(game_scene) = &game_scene {
+                            let stats = game_scene.game.chart.stats();
+                            info!("Loaded chart: {stats}");
+                            self.note_stats = Some(stats.notes);
+                        }
                         self.next_scene =
                             Some(game_scene.map_or_else(|e| NextScene::PopWithResult(Box::new(e)), |it| NextScene::Replace(Box::new(it))));
                         self.finish_time = tm.now() as f32 + BEFORE_TIME;
@@ -143,7 +166,7 @@ impl Scene for LoadingScene {
             render_target: self.target,
             ..Default::default()
         });
-        draw_background(*self.background);
+        draw_background(*self.background, BACKGROUND_DIM);
         let dx = if now > self.finish_time {
             let p = ((now - self.finish_time) / TRANSITION_TIME).min(1.);
             p.powi(3) * 2.
@@ -159,9 +182,10 @@ impl Scene for LoadingScene {
         let main = Rect::new(-0.88, vo - h / 2. - top / 10., 0.78, h);
         draw_parallelogram(main, None, Color::new(0., 0., 0., 0.7), true);
         let p = (main.x + main.w * 0.09, main.y + main.h * 0.36);
+        let mut font = self.font.as_ref().map(|it| it.borrow_mut());
         let mut text = ui.text(&self.info.name).pos(p.0, p.1).anchor(0., 0.5).size(0.7);
-        if text.measure().w <= main.w * 0.6 {
-            text.draw();
+        if text.measure_with_font(font.as_deref_mut()).w <= main.w * 0.6 {
+            text.draw_with_font(font.as_deref_mut());
         } else {
             drop(text);
             ui.text(&self.info.name)
@@ -169,9 +193,18 @@ impl Scene for LoadingScene {
                 .anchor(0., 0.5)
                 .max_width(main.w * 0.6)
                 .size(0.5)
-                .draw();
+                .draw_with_font(font.as_deref_mut());
         }
-        draw_text_aligned(ui, &self.info.composer, main.x + main.w * 0.09, main.y + main.h * 0.73, (0., 0.5), 0.36, WHITE);
+        draw_text_aligned_with_font(
+            ui,
+            font.as_deref_mut(),
+            &self.info.composer,
+            main.x + main.w * 0.09,
+            main.y + main.h * 0.73,
+            (0., 0.5),
+            0.36,
+            WHITE,
+        );
 
         let ext = 0.06;
         let sub = Rect::new(main.x + main.w * 0.71, main.y - main.h * ext, main.w * 0.26, main.h * (1. + ext * 2.));
@@ -179,12 +212,26 @@ impl Scene for LoadingScene {
         ct.x += sub.w * 0.02;
         draw_parallelogram(sub, None, WHITE, true);
         draw_text_aligned(ui, &(self.info.difficulty as u32).to_string(), ct.x, ct.y + sub.h * 0.05, (0.5, 1.), 0.88, BLACK);
-        draw_text_aligned(ui, self.info.level.split_whitespace().next().unwrap_or_default(), ct.x, ct.y + sub.h * 0.09, (0.5, 0.), 0.34, BLACK);
+        draw_text_aligned_with_font(
+            ui,
+            font.as_deref_mut(),
+            self.info.level.split_whitespace().next().unwrap_or_default(),
+            ct.x,
+            ct.y + sub.h * 0.09,
+            (0.5, 0.),
+            0.34,
+            BLACK,
+        );
         let t = draw_text_aligned(ui, "Chart", main.x + main.w / 6., main.y + main.h * 1.2, (0., 0.), 0.3, WHITE);
-        draw_text_aligned(ui, &self.charter, t.x, t.y + top / 20., (0., 0.), 0.47, WHITE);
+        draw_text_aligned_with_font(ui, font.as_deref_mut(), &self.charter, t.x, t.y + top / 20., (0., 0.), 0.47, WHITE);
         let w = 0.027;
         let t = draw_text_aligned(ui, "Illustration", t.x - w, t.y + w / 0.13 / 13. * 5., (0., 0.), 0.3, WHITE);
-        draw_text_aligned(ui, &self.info.illustrator, t.x, t.y + top / 20., (0., 0.), 0.47, WHITE);
+        draw_text_aligned_with_font(ui, font.as_deref_mut(), &self.info.illustrator, t.x, t.y + top / 20., (0., 0.), 0.47, WHITE);
+        drop(font);
+        if let Some(stats) = &self.note_stats {
+            let t = draw_text_aligned(ui, "Notes", t.x - w, t.y + w / 0.13 / 13. * 5., (0., 0.), 0.3, WHITE);
+            draw_text_aligned(ui, &stats.total.to_string(), t.x, t.y + top / 20., (0., 0.), 0.47, WHITE);
+        }
 
         draw_text_aligned(ui, self.info.tip.as_ref().unwrap(), -0.91, top * 0.92, (0., 1.), 0.47, WHITE);
         let t = draw_text_aligned(ui, "Loading...", 0.87, top * 0.92, (1., 1.), 0.44, WHITE);
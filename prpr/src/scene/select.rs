@@ -0,0 +1,183 @@
+crate::tl_file!("game");
+
+use super::{draw_illustration, game::GameMode, load_scene, show_error, take_loaded_scene, LoadingScene, NextScene, Scene};
+use crate::{
+    config::Config,
+    ext::{screen_aspect, semi_white, SafeTexture},
+    fs::{self, FileSystem},
+    info::ChartInfo,
+    time::TimeManager,
+    ui::{RectButton, Scroll, Ui},
+};
+use anyhow::{Context, Result};
+use macroquad::prelude::*;
+use std::{ops::DerefMut, path::Path};
+
+const ROW_HEIGHT: f32 = 0.22;
+
+struct ChartEntry {
+    fs: Box<dyn FileSystem>,
+    info: ChartInfo,
+    illustration: Option<SafeTexture>,
+    button: RectButton,
+}
+
+/// Lists charts under a directory, each of them a chart folder or archive. Picking one plays it, and the list is back after it finishes.
+pub struct SelectScene {
+    config: Config,
+    dir: String,
+    charts: Vec<ChartEntry>,
+    scroll: Scroll,
+    target: Option<RenderTarget>,
+    next_scene: Option<NextScene>,
+}
+
+impl SelectScene {
+    pub async fn new(dir: &Path, config: Config) -> Result<Self> {
+        let root = fs::fs_from_file(dir).with_context(|| format!("Cannot open {}", dir.display()))?;
+        let mut names = root.list_root().context("Cannot list charts")?;
+        names.sort();
+        let mut charts = Vec::new();
+        for name in names {
+            let path = dir.join(&name);
+            let entry = async {
+                let mut fs = fs::fs_from_file(&path)?;
+                let info = fs::load_info(fs.deref_mut()).await?;
+                let illustration = match fs
+                    .load_file(&info.illustration)
+                    .await
+                    .and_then(|bytes| Ok(image::load_from_memory(&bytes)?))
+                {
+                    Ok(image) => Some(SafeTexture::from(Texture2D::from_rgba8(image.width() as _, image.height() as _, &image.into_rgba8()))),
+                    Err(err) => {
+                        warn!("Failed to load illustration of {name}: {err:?}");
+                        None
+                    }
+                };
+                Ok::<_, anyhow::Error>(ChartEntry {
+                    fs,
+                    info,
+                    illustration,
+                    button: RectButton::new(),
+                })
+            };
+            match entry.await {
+                Ok(entry) => charts.push(entry),
+                Err(err) => warn!("Skipping {}: {err:?}", path.display()),
+            }
+        }
+        Ok(Self {
+            config,
+            dir: dir.display().to_string(),
+            charts,
+            scroll: Scroll::new(),
+            target: None,
+            next_scene: None,
+        })
+    }
+
+    fn select(&mut self, index: usize) {
+        let entry = &self.charts[index];
+        load_scene(LoadingScene::new(GameMode::Normal, entry.info.clone(), self.config.clone(), entry.fs.clone_box(), None, None, None));
+    }
+}
+
+impl Scene for SelectScene {
+    fn enter(&mut self, _tm: &mut TimeManager, target: Option<RenderTarget>) -> Result<()> {
+        self.target = target;
+        Ok(())
+    }
+
+    fn on_result(&mut self, _tm: &mut TimeManager, result: Box<dyn std::any::Any>) -> Result<()> {
+        // results of finished plays are of no use here, only loading failures are reported
+        if let Ok(err) = result.downcast::<anyhow::Error>() {
+            show_error(err.context(tl!("select-load-failed")));
+        }
+        Ok(())
+    }
+
+    fn touch(&mut self, tm: &mut TimeManager, touch: &Touch) -> Result<bool> {
+        if self.scroll.touch(touch, tm.now() as _) {
+            // dragging the list cancels any pressed entry
+            let cancel = Touch {
+                phase: TouchPhase::Cancelled,
+                ..touch.clone()
+            };
+            for entry in &mut self.charts {
+                entry.button.touch(&cancel);
+            }
+            return Ok(true);
+        }
+        if let Some(index) = self.charts.iter_mut().position(|it| it.button.touch(touch)) {
+            self.select(index);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn update(&mut self, tm: &mut TimeManager) -> Result<()> {
+        self.scroll.update(tm.now() as _);
+        if let Some(res) = take_loaded_scene() {
+            match res {
+                Ok(next) => self.next_scene = Some(next),
+                Err(err) => show_error(err.context(tl!("select-load-failed"))),
+            }
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            self.next_scene = Some(NextScene::Pop);
+        }
+        Ok(())
+    }
+
+    fn render(&mut self, _tm: &mut TimeManager, ui: &mut Ui) -> Result<()> {
+        let top = 1. / screen_aspect();
+        set_camera(&Camera2D {
+            zoom: vec2(1., -screen_aspect()),
+            render_target: self.target,
+            ..Default::default()
+        });
+        clear_background(BLACK);
+        ui.text(tl!("select-title")).pos(-0.92, -top + 0.06).size(0.8).draw();
+        if self.charts.is_empty() {
+            ui.text(tl!("select-empty", "dir" => self.dir.as_str()))
+                .pos(0., 0.)
+                .anchor(0.5, 0.5)
+                .size(0.5)
+                .color(semi_white(0.7))
+                .draw();
+            return Ok(());
+        }
+        let list_top = -top + 0.2;
+        ui.scope(|ui| {
+            ui.dx(-0.92);
+            ui.dy(list_top);
+            let width = 1.84;
+            self.scroll.size((width, top - list_top));
+            let charts = &mut self.charts;
+            self.scroll.render(ui, |ui| {
+                for (index, entry) in charts.iter_mut().enumerate() {
+                    let y = index as f32 * ROW_HEIGHT;
+                    let r = Rect::new(0., y, width, ROW_HEIGHT - 0.02);
+                    entry.button.set(ui, r);
+                    ui.fill_rect(r, Color::new(0., 0., 0., if entry.button.touching() { 0.6 } else { 0.4 }));
+                    if let Some(tex) = &entry.illustration {
+                        draw_illustration(**tex, 0.2, y + r.h / 2., 0.35, 0.35, WHITE);
+                    }
+                    ui.text(&entry.info.name).pos(0.4, y + 0.03).size(0.6).max_width(width - 0.45).draw();
+                    ui.text(format!("{}  {}", entry.info.level, entry.info.composer))
+                        .pos(0.4, y + 0.11)
+                        .size(0.4)
+                        .max_width(width - 0.45)
+                        .color(semi_white(0.7))
+                        .draw();
+                }
+                (width, charts.len() as f32 * ROW_HEIGHT)
+            });
+        });
+        Ok(())
+    }
+
+    fn next_scene(&mut self, _tm: &mut TimeManager) -> NextScene {
+        self.next_scene.take().unwrap_or_default()
+    }
+}
@@ -85,6 +85,20 @@ impl TimeManager {
         (self.pause_time.unwrap_or_else(&self.get_time_fn) - self.start_time) * self.speed
     }
 
+    /// Maps `at`, a timestamp read from another clock (e.g. the one of touch events) whose reading is `clock_now` right now, onto a
+    /// timeline that is at `now` and runs `speed` times as fast. Only the time elapsed since the event counts, so seeking and pausing are
+    /// followed as long as `now` is.
+    #[must_use]
+    pub fn map_timestamp(now: f64, speed: f64, clock_now: f64, at: f64) -> f64 {
+        now - (clock_now - at) * speed
+    }
+
+    /// Time of this manager at `at`, a timestamp read from another clock whose reading is `clock_now` right now
+    #[must_use]
+    pub fn time_at(&self, clock_now: f64, at: f64) -> f64 {
+        Self::map_timestamp(self.now(), self.speed, clock_now, at)
+    }
+
     pub fn update(&mut self, music_time: f64) {
         if self.adjust_time && self.real_time() > self.wait && self.pause_time.is_none() {
             self.start_time -= (music_time - self.now()) * self.force;